@@ -0,0 +1,427 @@
+//! Animated GIF, APNG and WebP
+//!
+//! [`load`] decodes every frame of an animation together with its delay and
+//! the loop count. Frames are composited at the source size, applying each
+//! frame's offset, disposal and blending, so every [`AnimationFrame`] is a
+//! full canvas. Upscaling partial frames on their own would blur their edges
+//! against the transparent area around them.
+//!
+//! [`Animation::save`] writes the frames back in the source format with the
+//! same delays and loop count. Each frame replaces the whole canvas (GIF
+//! disposal "background", APNG blend "source", WebP "no blend"), so the
+//! result plays exactly like the composited source. Animated WebP is written
+//! by hand from lossless frames, since the encoders only write still images.
+
+use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::{AnimationDecoder, Delay, RgbaImage};
+use std::io::Cursor;
+
+/// Largest canvas side of an animated WebP
+const MAX_WEBP_SIZE: u32 = 16384;
+
+/// Longest frame duration an animated WebP can store, in milliseconds
+const MAX_WEBP_DURATION: u32 = 0xff_ffff;
+
+/// Container of an animation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    Webp,
+}
+
+impl AnimationFormat {
+    /// File extension without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::Webp => "webp",
+        }
+    }
+}
+
+/// One full-canvas frame and how long it is shown
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+    pub image: RgbaImage,
+    pub delay: Delay,
+}
+
+/// Decoded animation
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub format: AnimationFormat,
+    pub frames: Vec<AnimationFrame>,
+    /// Times the animation plays; 0 loops forever
+    pub plays: u32,
+}
+
+/// Decode the animation at `path`, or `None` when the file is a still image
+/// (a single frame or a format without animation)
+pub fn load(path: &str) -> Result<Option<Animation>, Box<dyn std::error::Error>> {
+    let data = std::fs::read(path)?;
+    decode(&data)
+}
+
+/// Format of the animation at `path`, or `None` for a still image. Only
+/// reads the headers and frame list.
+pub fn detect(path: &str) -> Result<Option<AnimationFormat>, Box<dyn std::error::Error>> {
+    let data = std::fs::read(path)?;
+    Ok(probe(&data)?.map(|(format, _)| format))
+}
+
+/// Format and plays of an animation with at least two frames
+fn probe(data: &[u8]) -> Result<Option<(AnimationFormat, u32)>, Box<dyn std::error::Error>> {
+    Ok(match image::guess_format(data) {
+        Ok(image::ImageFormat::Gif) => gif_plays(data)?.map(|plays| (AnimationFormat::Gif, plays)),
+        Ok(image::ImageFormat::Png) => {
+            let reader = png::Decoder::new(Cursor::new(data)).read_info()?;
+            reader
+                .info()
+                .animation_control
+                .filter(|control| control.num_frames > 1)
+                .map(|control| (AnimationFormat::Apng, control.num_plays))
+        }
+        Ok(image::ImageFormat::WebP) => {
+            let decoder = image_webp::WebPDecoder::new(Cursor::new(data))?;
+            if decoder.is_animated() && decoder.num_frames() > 1 {
+                let plays = match decoder.loop_count() {
+                    image_webp::LoopCount::Forever => 0,
+                    image_webp::LoopCount::Times(times) => times.get() as u32,
+                };
+                Some((AnimationFormat::Webp, plays))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    })
+}
+
+/// Decode an animation from memory, or `None` for a still image
+pub fn decode(data: &[u8]) -> Result<Option<Animation>, Box<dyn std::error::Error>> {
+    let Some((format, plays)) = probe(data)? else {
+        return Ok(None);
+    };
+
+    let frames = match format {
+        AnimationFormat::Gif => GifDecoder::new(Cursor::new(data))?.into_frames(),
+        AnimationFormat::Apng => PngDecoder::new(Cursor::new(data))?.apng()?.into_frames(),
+        AnimationFormat::Webp => WebPDecoder::new(Cursor::new(data))?.into_frames(),
+    };
+    let frames = frames
+        .collect_frames()?
+        .into_iter()
+        .map(|frame| AnimationFrame {
+            delay: frame.delay(),
+            image: frame.into_buffer(),
+        })
+        .collect();
+
+    Ok(Some(Animation {
+        format,
+        frames,
+        plays,
+    }))
+}
+
+/// Plays of a GIF with at least two frames. The NETSCAPE extension counts
+/// repeats after the first play, and a GIF without it plays once.
+fn gif_plays(data: &[u8]) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(Cursor::new(data))?;
+    let mut frames = 0;
+    while decoder.next_frame_info()?.is_some() {
+        frames += 1;
+    }
+    if frames < 2 {
+        return Ok(None);
+    }
+    Ok(Some(match decoder.repeat() {
+        gif::Repeat::Infinite => 0,
+        gif::Repeat::Finite(repeats) => repeats as u32 + 1,
+    }))
+}
+
+/// Delay in whole milliseconds
+fn delay_ms(delay: Delay) -> u32 {
+    let (numerator, denominator) = delay.numer_denom_ms();
+    ((numerator as f64 / denominator.max(1) as f64).round()) as u32
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// APNG delay as a fraction of a second, exact when it fits in 16 bits
+fn apng_delay(delay: Delay) -> (u16, u16) {
+    let (numerator, denominator) = delay.numer_denom_ms();
+    let (numerator, denominator) = (numerator as u64, denominator.max(1) as u64 * 1000);
+    let divisor = gcd(numerator, denominator).max(1);
+    match (
+        u16::try_from(numerator / divisor),
+        u16::try_from(denominator / divisor),
+    ) {
+        (Ok(numerator), Ok(denominator)) => (numerator, denominator),
+        _ => (delay_ms(delay).min(u16::MAX as u32) as u16, 1000),
+    }
+}
+
+impl Animation {
+    /// Size of the canvas
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.frames
+            .first()
+            .map_or((0, 0), |frame| frame.image.dimensions())
+    }
+
+    /// Encode in `self.format`
+    pub fn encode(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let (width, height) = self.dimensions();
+        if self.frames.is_empty() || width == 0 || height == 0 {
+            return Err("An animation needs at least one non-empty frame".into());
+        }
+        if self
+            .frames
+            .iter()
+            .any(|frame| frame.image.dimensions() != (width, height))
+        {
+            return Err("Every frame of an animation must have the same size".into());
+        }
+        match self.format {
+            AnimationFormat::Gif => self.encode_gif(width, height),
+            AnimationFormat::Apng => self.encode_apng(width, height),
+            AnimationFormat::Webp => self.encode_webp(width, height),
+        }
+    }
+
+    /// Encode in `self.format` and write to `path`
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, self.encode()?)?;
+        Ok(())
+    }
+
+    fn encode_gif(&self, width: u32, height: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(format!(
+                "GIF frames can be at most 65535 pixels across, not {}x{}",
+                width, height
+            )
+            .into());
+        };
+
+        let mut data = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut data, width, height, &[])?;
+            match self.plays {
+                0 => encoder.set_repeat(gif::Repeat::Infinite)?,
+                1 => {}
+                plays => {
+                    let repeats = (plays - 1).min(u16::MAX as u32) as u16;
+                    encoder.set_repeat(gif::Repeat::Finite(repeats))?
+                }
+            }
+            for frame in &self.frames {
+                // GIF transparency is on or off, and only one palette entry
+                // can be transparent
+                let mut pixels = frame.image.as_raw().clone();
+                for pixel in pixels.chunks_exact_mut(4) {
+                    if pixel[3] < 128 {
+                        pixel.copy_from_slice(&[0, 0, 0, 0]);
+                    } else {
+                        pixel[3] = 255;
+                    }
+                }
+                let mut gif_frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, 10);
+                // GIF delays are in hundredths of a second
+                gif_frame.delay = ((delay_ms(frame.delay) + 5) / 10).min(u16::MAX as u32) as u16;
+                gif_frame.dispose = gif::DisposalMethod::Background;
+                encoder.write_frame(&gif_frame)?;
+            }
+        }
+        Ok(data)
+    }
+
+    fn encode_apng(&self, width: u32, height: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.len() as u32, self.plays)?;
+        let mut writer = encoder.write_header()?;
+        for frame in &self.frames {
+            let (numerator, denominator) = apng_delay(frame.delay);
+            writer.set_frame_delay(numerator, denominator)?;
+            writer.set_blend_op(png::BlendOp::Source)?;
+            writer.set_dispose_op(png::DisposeOp::None)?;
+            writer.write_image_data(frame.image.as_raw())?;
+        }
+        writer.finish()?;
+        Ok(data)
+    }
+
+    fn encode_webp(&self, width: u32, height: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if width > MAX_WEBP_SIZE || height > MAX_WEBP_SIZE {
+            return Err(format!(
+                "WebP frames can be at most {} pixels across, not {}x{}",
+                MAX_WEBP_SIZE, width, height
+            )
+            .into());
+        }
+
+        let mut chunks = Vec::new();
+        // Flags: alpha and animation, then the canvas size minus one
+        let mut vp8x = vec![0x10 | 0x02, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(width - 1));
+        vp8x.extend_from_slice(&u24(height - 1));
+        write_chunk(&mut chunks, b"VP8X", &vp8x);
+
+        // Transparent background, then the loop count (0 loops forever)
+        let mut anim = vec![0, 0, 0, 0];
+        anim.extend_from_slice(&(self.plays.min(u16::MAX as u32) as u16).to_le_bytes());
+        write_chunk(&mut chunks, b"ANIM", &anim);
+
+        for frame in &self.frames {
+            let mut still = Vec::new();
+            image_webp::WebPEncoder::new(&mut still).encode(
+                frame.image.as_raw(),
+                width,
+                height,
+                image_webp::ColorType::Rgba8,
+            )?;
+            let bitstream = find_chunk(&still, b"VP8L")
+                .ok_or("The WebP encoder wrote no lossless bitstream")?;
+
+            // Offset (in units of 2), size minus one, duration, then flags:
+            // don't blend with the previous frame, don't dispose
+            let mut anmf = Vec::with_capacity(16 + bitstream.len() + 9);
+            anmf.extend_from_slice(&u24(0));
+            anmf.extend_from_slice(&u24(0));
+            anmf.extend_from_slice(&u24(width - 1));
+            anmf.extend_from_slice(&u24(height - 1));
+            anmf.extend_from_slice(&u24(delay_ms(frame.delay).min(MAX_WEBP_DURATION)));
+            anmf.push(0x02);
+            write_chunk(&mut anmf, b"VP8L", bitstream);
+            write_chunk(&mut chunks, b"ANMF", &anmf);
+        }
+
+        let mut data = Vec::with_capacity(12 + chunks.len());
+        data.extend_from_slice(b"RIFF");
+        data.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend_from_slice(&chunks);
+        Ok(data)
+    }
+}
+
+/// Little-endian 24-bit value
+fn u24(value: u32) -> [u8; 3] {
+    let bytes = value.to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+/// Append a RIFF chunk, padded to an even length
+fn write_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Payload of the first top-level `fourcc` chunk of a WebP file
+fn find_chunk<'a>(webp: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut offset = 12;
+    while offset + 8 <= webp.len() {
+        let size = u32::from_le_bytes(webp[offset + 4..offset + 8].try_into().ok()?) as usize;
+        let payload = webp.get(offset + 8..offset + 8 + size)?;
+        if &webp[offset..offset + 4] == fourcc {
+            return Some(payload);
+        }
+        offset += 8 + size + size % 2;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three frames with transparency and different delays
+    fn animation(format: AnimationFormat, plays: u32) -> Animation {
+        let frames = [(0u8, 40), (120, 100), (240, 70)]
+            .into_iter()
+            .map(|(shade, delay)| AnimationFrame {
+                image: RgbaImage::from_fn(6, 5, |x, y| {
+                    let alpha = if (x + y) % 4 == 0 { 0 } else { 255 };
+                    image::Rgba([shade, 255 - shade, (x * 40) as u8, alpha])
+                }),
+                delay: Delay::from_numer_denom_ms(delay, 1),
+            })
+            .collect();
+        Animation {
+            format,
+            frames,
+            plays,
+        }
+    }
+
+    #[test]
+    fn round_trips_frames_delays_and_plays() {
+        for format in [
+            AnimationFormat::Gif,
+            AnimationFormat::Apng,
+            AnimationFormat::Webp,
+        ] {
+            for plays in [0, 1, 3] {
+                let original = animation(format, plays);
+                let decoded = decode(&original.encode().unwrap()).unwrap().unwrap();
+                assert_eq!(decoded.format, format);
+                assert_eq!(decoded.plays, plays, "{:?}", format);
+                assert_eq!(decoded.frames.len(), original.frames.len());
+                for (decoded, original) in decoded.frames.iter().zip(&original.frames) {
+                    assert_eq!(delay_ms(decoded.delay), delay_ms(original.delay));
+                    assert_eq!(decoded.image.dimensions(), original.image.dimensions());
+                    let alpha =
+                        |image: &RgbaImage| image.pixels().map(|p| p[3]).collect::<Vec<_>>();
+                    assert_eq!(
+                        alpha(&decoded.image),
+                        alpha(&original.image),
+                        "{:?}",
+                        format
+                    );
+                    if format != AnimationFormat::Gif {
+                        assert_eq!(decoded.image, original.image, "{:?}", format);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn still_images_are_not_animations() {
+        let mut still = Vec::new();
+        RgbaImage::new(4, 4)
+            .write_to(&mut Cursor::new(&mut still), image::ImageFormat::Png)
+            .unwrap();
+        assert!(decode(&still).unwrap().is_none());
+
+        let mut single = animation(AnimationFormat::Gif, 0);
+        single.frames.truncate(1);
+        assert!(decode(&single.encode().unwrap()).unwrap().is_none());
+    }
+
+    #[test]
+    fn apng_delays_stay_exact() {
+        assert_eq!(apng_delay(Delay::from_numer_denom_ms(40, 1)), (1, 25));
+        assert_eq!(apng_delay(Delay::from_numer_denom_ms(100, 3)), (1, 30));
+        assert_eq!(apng_delay(Delay::from_numer_denom_ms(0, 1)), (0, 1));
+    }
+}
@@ -0,0 +1,312 @@
+//! Throughput benchmarks
+//!
+//! [`run`] times three upscale methods (standard, enhanced and nearest
+//! neighbour) through [`pipeline::upscale`], the path every real job takes,
+//! for every test image and factor. Each run is split into the stages of the
+//! job's [`StageTimings`](crate::vulkan::StageTimings): decode, upload,
+//! dispatch, readback, post-processing and encode, plus the GPU's own upload,
+//! dispatch and readback time from timestamp queries. Every combination runs
+//! once untimed to warm up and then `iterations` times; the report keeps the
+//! median of each stage. Images are decoded from and encoded to memory, so
+//! disk speed doesn't count.
+//!
+//! The report serializes to JSON with the app version and the device, so runs
+//! of different versions on the same machine can be compared.
+
+use crate::pipeline::{self, UpscaleMethod, UpscaleOptions};
+use crate::vulkan::VulkanContext;
+use image::codecs::png::PngEncoder;
+use image::{ImageEncoder, RgbaImage};
+use serde::Serialize;
+use std::time::Instant;
+
+/// Sides of the generated square test images
+pub const DEFAULT_SIZES: [u32; 3] = [256, 512, 1024];
+
+/// Factors benchmarked by default
+pub const DEFAULT_FACTORS: [u32; 2] = [2, 4];
+
+/// Timed runs of each combination by default
+pub const DEFAULT_ITERATIONS: u32 = 3;
+
+/// Upscale method being timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BenchmarkPath {
+    /// [`UpscaleMethod::Standard`]: GPU upscale with the default kernel
+    Standard,
+    /// [`UpscaleMethod::Enhanced`]: the GPU upscale followed by the default
+    /// post-processing
+    Enhanced,
+    /// [`UpscaleMethod::NearestNeighbor`]: grid detection and resize on the CPU
+    NearestNeighbor,
+}
+
+impl BenchmarkPath {
+    const ALL: [BenchmarkPath; 3] = [
+        BenchmarkPath::Standard,
+        BenchmarkPath::Enhanced,
+        BenchmarkPath::NearestNeighbor,
+    ];
+
+    fn method(self) -> UpscaleMethod {
+        match self {
+            BenchmarkPath::Standard => UpscaleMethod::Standard,
+            BenchmarkPath::Enhanced => UpscaleMethod::Enhanced,
+            BenchmarkPath::NearestNeighbor => UpscaleMethod::NearestNeighbor,
+        }
+    }
+}
+
+/// Encoded test image, decoded again on every run
+pub struct BenchmarkImage {
+    pub name: String,
+    data: Vec<u8>,
+}
+
+impl BenchmarkImage {
+    /// Square PNG of `size` pixels mixing gradients, hard edges and noise,
+    /// so post-processing and encoding have realistic work to do
+    pub fn generated(size: u32) -> Result<Self, String> {
+        let mut seed = 0x9e37_79b9u32;
+        let image = RgbaImage::from_fn(size, size, |x, y| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let checker = if (x / 32 + y / 32) % 2 == 0 { 160 } else { 40 };
+            image::Rgba([
+                (x * 255 / size) as u8,
+                (y * 255 / size) as u8,
+                checker + (seed % 64) as u8,
+                255,
+            ])
+        });
+
+        let mut data = Vec::new();
+        PngEncoder::new(&mut data)
+            .write_image(image.as_raw(), size, size, image::ExtendedColorType::Rgba8)
+            .map_err(|e| format!("Failed to encode test image: {}", e))?;
+        Ok(BenchmarkImage {
+            name: format!("generated_{}", size),
+            data,
+        })
+    }
+
+    /// Image file at `path`, read once into memory
+    pub fn load(path: &str) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        image::load_from_memory(&data).map_err(|e| format!("Failed to decode {}: {}", path, e))?;
+        Ok(BenchmarkImage {
+            name: path.to_string(),
+            data,
+        })
+    }
+}
+
+/// What to benchmark
+pub struct BenchmarkConfig {
+    pub images: Vec<BenchmarkImage>,
+    pub factors: Vec<u32>,
+    pub iterations: u32,
+}
+
+/// Time spent in each stage of one run, in milliseconds. Stages a path
+/// doesn't have are 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct StageBreakdown {
+    pub decode_ms: f64,
+    pub upload_ms: f64,
+    pub dispatch_ms: f64,
+    pub readback_ms: f64,
+    /// Grid detection and resize of the nearest-neighbour path on the CPU
+    pub resize_ms: f64,
+    pub post_processing_ms: f64,
+    /// PNG encoding with the default settings
+    pub encode_ms: f64,
+    /// Wall-clock time of the whole run
+    pub total_ms: f64,
+    /// GPU time of the upload, dispatch and readback commands, from
+    /// timestamp queries; `null` when the device can't time them
+    pub gpu_upload_ms: Option<f64>,
+    pub gpu_dispatch_ms: Option<f64>,
+    pub gpu_readback_ms: Option<f64>,
+}
+
+/// Median timings of one path, image and factor
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkResult {
+    pub path: BenchmarkPath,
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+    pub factor: u32,
+    pub median: StageBreakdown,
+    /// Output pixels per second of the median total
+    pub megapixels_per_second: f64,
+}
+
+/// Everything measured by [`run`]
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkReport {
+    pub version: String,
+    pub os: String,
+    pub device: String,
+    /// Seconds since the Unix epoch when the benchmark started
+    pub timestamp: u64,
+    pub iterations: u32,
+    /// Time to create the Vulkan context
+    pub context_ms: f64,
+    pub results: Vec<BenchmarkResult>,
+}
+
+fn elapsed_ms(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Decode, upscale and encode `image` once along `path`
+fn run_once(
+    context: &VulkanContext,
+    image: &BenchmarkImage,
+    factor: u32,
+    path: BenchmarkPath,
+) -> Result<(StageBreakdown, (u32, u32)), Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let decoded = image::load_from_memory(&image.data)?.to_rgba8();
+    let (width, height) = decoded.dimensions();
+    let decode_ms = elapsed_ms(started);
+
+    let options = UpscaleOptions {
+        factor: factor as f32,
+        method: path.method(),
+        ..Default::default()
+    };
+    let (output, report) = pipeline::upscale(Some(context), &decoded, &options)?;
+    let timings = report.timings;
+
+    let encode_started = Instant::now();
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(
+        output.as_raw(),
+        output.width(),
+        output.height(),
+        image::ExtendedColorType::Rgba8,
+    )?;
+    let encode_ms = elapsed_ms(encode_started);
+
+    // The CPU path has no GPU stages; its whole enlarge step is the resize
+    let (dispatch_ms, resize_ms) = match path {
+        BenchmarkPath::NearestNeighbor => (0.0, timings.dispatch_ms),
+        BenchmarkPath::Standard | BenchmarkPath::Enhanced => (timings.dispatch_ms, 0.0),
+    };
+    let stages = StageBreakdown {
+        decode_ms,
+        upload_ms: timings.upload_ms,
+        dispatch_ms,
+        readback_ms: timings.readback_ms,
+        resize_ms,
+        post_processing_ms: timings.post_processing_ms,
+        encode_ms,
+        total_ms: elapsed_ms(started),
+        gpu_upload_ms: timings.gpu.map(|gpu| gpu.upload_ms),
+        gpu_dispatch_ms: timings.gpu.map(|gpu| gpu.dispatch_ms),
+        gpu_readback_ms: timings.gpu.map(|gpu| gpu.readback_ms),
+    };
+    Ok((stages, (width, height)))
+}
+
+/// Median of one stage over `runs`
+fn median(runs: &[StageBreakdown], stage: impl Fn(&StageBreakdown) -> f64) -> f64 {
+    let mut values: Vec<f64> = runs.iter().map(stage).collect();
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[middle]
+    } else {
+        (values[middle - 1] + values[middle]) / 2.0
+    }
+}
+
+/// Median of a GPU stage over `runs`, if every run timed it
+fn gpu_median(
+    runs: &[StageBreakdown],
+    stage: impl Fn(&StageBreakdown) -> Option<f64>,
+) -> Option<f64> {
+    runs.iter()
+        .all(|run| stage(run).is_some())
+        .then(|| median(runs, |run| stage(run).unwrap_or_default()))
+}
+
+/// Create a Vulkan context and time every path for each image and factor
+pub fn run(config: &BenchmarkConfig) -> Result<BenchmarkReport, Box<dyn std::error::Error>> {
+    if config.images.is_empty() || config.factors.is_empty() {
+        return Err("The benchmark needs at least one image and one factor".into());
+    }
+    if let Some(factor) = config.factors.iter().find(|f| !(1..=8).contains(*f)) {
+        return Err(format!("Invalid scale factor: {}. Must be between 1 and 8.", factor).into());
+    }
+    if config.iterations == 0 {
+        return Err("The benchmark needs at least one iteration".into());
+    }
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let context_started = Instant::now();
+    let context = VulkanContext::new()?;
+    let context_ms = elapsed_ms(context_started);
+    let device = context.device_name();
+    println!(
+        "⏱️  Benchmarking on {} (context in {:.1} ms)",
+        device, context_ms
+    );
+
+    let mut results = Vec::new();
+    for image in &config.images {
+        for &factor in &config.factors {
+            for path in BenchmarkPath::ALL {
+                run_once(&context, image, factor, path)?;
+                let mut runs = Vec::with_capacity(config.iterations as usize);
+                let mut size = (0, 0);
+                for _ in 0..config.iterations {
+                    let (stages, image_size) = run_once(&context, image, factor, path)?;
+                    runs.push(stages);
+                    size = image_size;
+                }
+
+                let median = StageBreakdown {
+                    decode_ms: median(&runs, |s| s.decode_ms),
+                    upload_ms: median(&runs, |s| s.upload_ms),
+                    dispatch_ms: median(&runs, |s| s.dispatch_ms),
+                    readback_ms: median(&runs, |s| s.readback_ms),
+                    resize_ms: median(&runs, |s| s.resize_ms),
+                    post_processing_ms: median(&runs, |s| s.post_processing_ms),
+                    encode_ms: median(&runs, |s| s.encode_ms),
+                    total_ms: median(&runs, |s| s.total_ms),
+                    gpu_upload_ms: gpu_median(&runs, |s| s.gpu_upload_ms),
+                    gpu_dispatch_ms: gpu_median(&runs, |s| s.gpu_dispatch_ms),
+                    gpu_readback_ms: gpu_median(&runs, |s| s.gpu_readback_ms),
+                };
+                let output_pixels = (size.0 * factor) as f64 * (size.1 * factor) as f64;
+                results.push(BenchmarkResult {
+                    path,
+                    image: image.name.clone(),
+                    width: size.0,
+                    height: size.1,
+                    factor,
+                    median,
+                    megapixels_per_second: output_pixels / 1e6 / (median.total_ms / 1000.0),
+                });
+            }
+        }
+    }
+
+    Ok(BenchmarkReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        os: std::env::consts::OS.to_string(),
+        device,
+        timestamp,
+        iterations: config.iterations,
+        context_ms,
+        results,
+    })
+}
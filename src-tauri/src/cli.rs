@@ -0,0 +1,727 @@
+//! Command-line interface for scripted upscaling without the GUI
+//!
+//! `v-upscale upscale <input> [options]` runs the same pipeline as the
+//! `upscale_image_enhanced` command, optionally starting from a preset.
+//! `v-upscale presets` lists the available presets and `v-upscale models`
+//! manages the model registry. `v-upscale metrics` scores an image against a
+//! reference, and `v-upscale evaluate` scores an upscale option set on a
+//! downscaled ground truth. `v-upscale benchmark` times the main processing
+//! paths and writes a JSON report. `v-upscale sequence` upscales a numbered
+//! frame sequence with shot-wide contrast and noise statistics. Any other
+//! invocation launches the app.
+
+use crate::benchmark::{self, BenchmarkConfig, BenchmarkImage};
+use crate::metrics::{self, ChannelScores, QualityReport};
+use crate::model_registry::{self, ModelImport, VerifiedFiles};
+use crate::pipeline::{self, Region, UpscaleOptions};
+use crate::presets;
+use crate::sequence::{self, FramePattern};
+use crate::vulkan::VulkanContext;
+use std::path::Path;
+
+const USAGE: &str = "Usage: v-upscale upscale <input> [options]
+       v-upscale presets
+       v-upscale models [import <path> [--name <name>] [--license <license>] | remove <name>]
+       v-upscale metrics <reference> <candidate>
+       v-upscale evaluate <ground-truth> [options]
+       v-upscale sequence <pattern> --range <first>-<last> [options]
+       v-upscale benchmark [--sizes <n,...>] [--factors <n,...>] [--iterations <n>]
+                           [--input <path>]... [-o <report.json>]
+
+`metrics` prints PSNR, SSIM and MS-SSIM of the candidate against the reference.
+`evaluate` shrinks the ground truth by the factor, upscales it back with the
+given options (writing the result with -o) and scores it against the original.
+`benchmark` times the standard, enhanced and nearest-neighbor methods through
+the upscale pipeline stage by stage on generated square images (default sizes
+256,512,1024) or the --input images, at each factor (default 2,4), and writes
+the median timings as JSON (default: benchmark.json).
+`sequence` upscales the numbered frames of a printf-style pattern such as
+frame_%05d.png into -o <pattern> (default: <pattern>_<factor>x.<format>).
+Auto-levels and CLAHE are fitted to the whole range and noise reduction is
+followed by a temporal filter, so the output doesn't flicker.
+
+Options:
+  -o, --output <path>             Output path (default: <input>_<factor>x.<format>)
+  -f, --factor <n>                Scale factor: 1-8 to upscale, 0.01-1 to downscale
+                                  (default: 2)
+      --region <x,y,w,h>          Upscale only this rectangle of the input; matches the
+                                  same crop of a full upscale
+      --preset <name>             Start from a saved or built-in preset
+      --method <method>           standard, enhanced, line_art, nearest_neighbor,
+                                  pixel_art, neural or model (default: enhanced)
+      --pixel-art-scaler <name>   scale, hqx or xbr (default: xbr)
+      --no-detect-grid            Don't undo earlier nearest-neighbor enlargement
+                                  (nearest_neighbor and pixel_art methods)
+      --line-thinning <x>         Line art thinning strength, 0-1 (default: 0.33)
+      --line-refinement <x>       Line art gradient refinement strength, 0-1 (default: 1)
+      --line-passes <n>           Line art thinning + refinement rounds, 1-4 (default: 1)
+      --model <path|name>         ncnn .param file for the neural method (the .bin
+                                  weights are read from next to it), .onnx file for
+                                  the model method, or the name of a registered model
+      --tile-size <n>             Model tile size in pixels, 32-1024
+                                  (default: 256 neural, 128 model)
+      --tile-overlap <n>          Model tile overlap in pixels, 0-64 (default: 16)
+      --kernel <kernel>           auto, mitchell, lanczos, edge_directed or fsr; area,
+                                  mitchell or lanczos when downscaling (default: auto)
+      --palette-lock              Map the output back to the source colours; PNG output
+                                  is written indexed when it fits in 256 colours
+      --self-ensemble             Average the upscale over all 8 flips and rotations
+                                  of the input (8x slower)
+      --format <format>           png, jpeg or webp (default: png)
+                                  Animated GIF, PNG and WebP keep their own format
+      --quality <n>               JPEG quality, 1-100 (default: 90)
+      --settings <file>           Load post-processing settings from a JSON file
+      --sharpen / --no-sharpen    Toggle sharpening (default: on)
+      --sharpen-mode <mode>       classic, unsharp_mask, cas or rcas (default: classic)
+      --sharpen-intensity <x>     Classic sharpening intensity, 0-5 (default: 1.8)
+      --unsharp-radius <x>        Unsharp mask radius in source pixels, 0.1-10 (default: 1.0)
+      --unsharp-amount <x>        Unsharp mask amount, 0-5 (default: 0.8)
+      --unsharp-threshold <x>     Unsharp mask threshold, 0-255 (default: 3)
+      --no-unsharp-scale          Don't scale the unsharp radius by the factor
+      --cas-sharpness <x>         Contrast-adaptive sharpening strength, 0-1 (default: 0.5)
+      --rcas-sharpness <x>        RCAS sharpness in stops, 0 is strongest, 0-2 (default: 0.2)
+      --sharpen-cpu               Run CAS/RCAS on the CPU
+      --contrast-enhancement / --no-contrast-enhancement
+                                  Toggle contrast enhancement (default: on)
+      --contrast-mode <mode>      classic, auto_levels or clahe (default: classic)
+      --gamma <x>                 Classic gamma, 0.1-5 (default: 1.15)
+      --contrast <x>              Classic contrast, 0-3 (default: 1.25)
+      --saturation <x>            Classic saturation, 0-3 (default: 1.1)
+      --levels-clip <x>           Auto-levels clip percentage, 0-10 (default: 0.5)
+      --clahe-tiles <x>x<y>       CLAHE tile grid, 1-64 each (default: 8x8)
+      --clahe-clip-limit <x>      CLAHE clip limit, 1-40 (default: 2)
+      --denoise / --no-denoise    Toggle noise reduction (default: off)
+      --denoise-mode <mode>       classic, bilateral or non_local_means (default: classic)
+      --denoise-strength <x>      Classic noise reduction strength, 0-2 (default: 0.5)
+      --bilateral-sigma-spatial <x>
+                                  Bilateral spatial sigma in pixels, 0.5-10 (default: 2)
+      --bilateral-sigma-range <x> Bilateral range sigma, 1-255 (default: 20)
+      --nlm-strength <x>          Non-local means strength h, 1-100 (default: 10)
+      --nlm-patch-radius <n>      Non-local means patch radius, 1-3 (default: 1)
+      --nlm-search-radius <n>     Non-local means search radius, 1-10 (default: 5)
+      --denoise-cpu               Run bilateral/non-local means on the CPU
+  -h, --help                      Show this help";
+
+/// Run the CLI when the first argument is a known subcommand.
+///
+/// Returns the process exit code, or `None` if the GUI should start instead.
+pub fn try_run(args: &[String]) -> Option<i32> {
+    let (command, rest) = args.split_first()?;
+
+    let result = match command.as_str() {
+        "upscale" => run_upscale(rest),
+        "presets" => list_presets(),
+        "models" => run_models(rest),
+        "metrics" => run_metrics(rest),
+        "evaluate" => run_evaluate(rest),
+        "benchmark" => run_benchmark(rest),
+        "sequence" => run_sequence(rest),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => return None,
+    };
+
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            eprintln!();
+            eprintln!("{}", USAGE);
+            Some(1)
+        }
+    }
+}
+
+struct UpscaleArgs {
+    input: String,
+    output: Option<String>,
+    region: Option<Region>,
+    range: Option<String>,
+    options: UpscaleOptions,
+}
+
+/// Arguments of [`apply_flags`] that aren't options
+#[derive(Default)]
+struct Flags {
+    input: Option<String>,
+    output: Option<String>,
+    region: Option<Region>,
+    /// `--range`, which only `sequence` takes
+    range: Option<String>,
+    preset: Option<String>,
+    settings: Option<String>,
+}
+
+fn parse_upscale_args(args: &[String]) -> Result<UpscaleArgs, String> {
+    // The preset, then the settings file, are the base that the other flags
+    // override wherever they appear. The first pass finds them; the second
+    // applies the flags on top.
+    let base = apply_flags(args, &mut UpscaleOptions::default())?;
+    let mut options = match &base.preset {
+        Some(name) => presets::find_preset(&app_data_dir()?, name)?.options,
+        None => UpscaleOptions::default(),
+    };
+    if let Some(file) = &base.settings {
+        let json = std::fs::read_to_string(file)
+            .map_err(|e| format!("Failed to read settings file {}: {}", file, e))?;
+        options.post_processing = serde_json::from_str(&json)
+            .map_err(|e| format!("Failed to parse settings file {}: {}", file, e))?;
+    }
+    let flags = apply_flags(args, &mut options)?;
+
+    Ok(UpscaleArgs {
+        input: flags.input.ok_or("Missing input image path")?,
+        output: flags.output,
+        region: flags.region,
+        range: flags.range,
+        options,
+    })
+}
+
+/// Parse `args` from left to right, setting the options their flags name
+fn apply_flags(args: &[String], options: &mut UpscaleOptions) -> Result<Flags, String> {
+    let mut flags = Flags::default();
+    let settings = &mut options.post_processing;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |flag: &str| {
+            iter.next()
+                .map(String::as_str)
+                .ok_or_else(|| format!("Missing value for {}", flag))
+        };
+
+        match arg.as_str() {
+            "-o" | "--output" => flags.output = Some(value(arg)?.to_string()),
+            "-f" | "--factor" => options.factor = parse_number(arg, value(arg)?)?,
+            "--region" => {
+                let rect = value(arg)?;
+                let numbers = rect
+                    .split(',')
+                    .map(|n| parse_number(arg, n.trim()))
+                    .collect::<Result<Vec<u32>, String>>()?;
+                let [x, y, width, height] = numbers[..] else {
+                    return Err(format!("Invalid value for {}: {}", arg, rect));
+                };
+                flags.region = Some(Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                });
+            }
+            "--range" => flags.range = Some(value(arg)?.to_string()),
+            "--preset" => flags.preset = Some(value(arg)?.to_string()),
+            "--settings" => flags.settings = Some(value(arg)?.to_string()),
+            "--method" => options.method = parse_enum(arg, value(arg)?)?,
+            "--kernel" => options.kernel = parse_enum(arg, value(arg)?)?,
+            "--pixel-art-scaler" => options.pixel_art_scaler = parse_enum(arg, value(arg)?)?,
+            "--format" => options.output_format = parse_enum(arg, value(arg)?)?,
+            "--no-detect-grid" => options.detect_grid = false,
+            "--line-thinning" => options.line_art.thinning = parse_number(arg, value(arg)?)?,
+            "--line-refinement" => options.line_art.refinement = parse_number(arg, value(arg)?)?,
+            "--line-passes" => options.line_art.passes = parse_number(arg, value(arg)?)?,
+            "--model" => {
+                let path = value(arg)?;
+                options.neural.model = path.to_string();
+                options.model.path = path.to_string();
+            }
+            "--tile-size" => {
+                let size = parse_number(arg, value(arg)?)?;
+                options.neural.tile_size = size;
+                options.model.tile_size = size;
+            }
+            "--tile-overlap" => {
+                let overlap = parse_number(arg, value(arg)?)?;
+                options.neural.tile_overlap = overlap;
+                options.model.tile_overlap = overlap;
+            }
+            "--palette-lock" => options.palette_lock = true,
+            "--self-ensemble" => options.self_ensemble = true,
+            "--quality" => options.jpeg_quality = parse_number(arg, value(arg)?)?,
+            "--sharpen" => settings.sharpening = true,
+            "--no-sharpen" => settings.sharpening = false,
+            "--sharpen-mode" => settings.sharpen_mode = parse_enum(arg, value(arg)?)?,
+            "--sharpen-intensity" => settings.sharpen_intensity = parse_number(arg, value(arg)?)?,
+            "--unsharp-radius" => settings.unsharp_radius = parse_number(arg, value(arg)?)?,
+            "--unsharp-amount" => settings.unsharp_amount = parse_number(arg, value(arg)?)?,
+            "--unsharp-threshold" => settings.unsharp_threshold = parse_number(arg, value(arg)?)?,
+            "--no-unsharp-scale" => settings.unsharp_scale_with_factor = false,
+            "--cas-sharpness" => settings.cas_sharpness = parse_number(arg, value(arg)?)?,
+            "--sharpen-cpu" => settings.sharpen_on_gpu = false,
+            "--rcas-sharpness" => settings.rcas_sharpness = parse_number(arg, value(arg)?)?,
+            "--contrast-enhancement" => settings.contrast_enhancement = true,
+            "--no-contrast-enhancement" => settings.contrast_enhancement = false,
+            "--contrast-mode" => settings.contrast_mode = parse_enum(arg, value(arg)?)?,
+            "--gamma" => settings.gamma = parse_number(arg, value(arg)?)?,
+            "--contrast" => settings.contrast = parse_number(arg, value(arg)?)?,
+            "--saturation" => settings.saturation = parse_number(arg, value(arg)?)?,
+            "--levels-clip" => settings.levels_clip_percent = parse_number(arg, value(arg)?)?,
+            "--clahe-tiles" => {
+                let grid = value(arg)?;
+                let (x, y) = grid
+                    .split_once('x')
+                    .ok_or_else(|| format!("Invalid value for {}: {}", arg, grid))?;
+                settings.clahe_tiles_x = parse_number(arg, x)?;
+                settings.clahe_tiles_y = parse_number(arg, y)?;
+            }
+            "--clahe-clip-limit" => settings.clahe_clip_limit = parse_number(arg, value(arg)?)?,
+            "--denoise" => settings.noise_reduction = true,
+            "--no-denoise" => settings.noise_reduction = false,
+            "--denoise-mode" => settings.denoise_mode = parse_enum(arg, value(arg)?)?,
+            "--denoise-strength" => settings.denoise_strength = parse_number(arg, value(arg)?)?,
+            "--bilateral-sigma-spatial" => {
+                settings.bilateral_sigma_spatial = parse_number(arg, value(arg)?)?
+            }
+            "--bilateral-sigma-range" => {
+                settings.bilateral_sigma_range = parse_number(arg, value(arg)?)?
+            }
+            "--nlm-strength" => settings.nlm_strength = parse_number(arg, value(arg)?)?,
+            "--nlm-patch-radius" => settings.nlm_patch_radius = parse_number(arg, value(arg)?)?,
+            "--nlm-search-radius" => settings.nlm_search_radius = parse_number(arg, value(arg)?)?,
+            "--denoise-cpu" => settings.denoise_on_gpu = false,
+            flag if flag.starts_with('-') => return Err(format!("Unknown option: {}", flag)),
+            path => {
+                if flags.input.replace(path.to_string()).is_some() {
+                    return Err(format!("Unexpected argument: {}", path));
+                }
+            }
+        }
+    }
+    Ok(flags)
+}
+
+/// Directory holding the user presets and registered models
+fn app_data_dir() -> Result<std::path::PathBuf, String> {
+    presets::default_app_data_dir().ok_or_else(|| "Could not locate the app data directory".into())
+}
+
+fn list_presets() -> Result<(), String> {
+    for preset in presets::list_presets(&app_data_dir()?)? {
+        let options = &preset.options;
+        println!(
+            "{}{}: {:?} {}x, {:?} kernel, {}",
+            preset.name,
+            if preset.builtin { " (built-in)" } else { "" },
+            options.method,
+            options.factor,
+            options.kernel,
+            options.output_format.extension()
+        );
+    }
+    Ok(())
+}
+
+/// `v-upscale models`: list, import or remove registered models
+fn run_models(args: &[String]) -> Result<(), String> {
+    let app_data_dir = app_data_dir()?;
+    match args.first().map(String::as_str) {
+        None => {
+            for manifest in model_registry::list_models(&app_data_dir)? {
+                println!(
+                    "{}: {:?} {}x, {}, {}",
+                    manifest.name,
+                    manifest.format,
+                    manifest.scale,
+                    manifest.license,
+                    manifest.files[0].sha256
+                );
+            }
+            Ok(())
+        }
+        Some("import") => {
+            let path = args
+                .get(1)
+                .filter(|path| !path.starts_with('-'))
+                .ok_or("Missing model path")?;
+            let mut import = ModelImport {
+                path: path.clone(),
+                name: None,
+                license: None,
+                tile: None,
+            };
+            let mut flags = args[2..].iter();
+            while let Some(flag) = flags.next() {
+                let field = match flag.as_str() {
+                    "--name" => &mut import.name,
+                    "--license" => &mut import.license,
+                    other => return Err(format!("Unexpected argument: {}", other)),
+                };
+                let value = flags
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}", flag))?;
+                *field = Some(value.clone());
+            }
+            let manifest = model_registry::import_model(&app_data_dir, &import)?;
+            println!(
+                "✅ Imported model {} ({:?}, {}x)",
+                manifest.name, manifest.format, manifest.scale
+            );
+            Ok(())
+        }
+        Some("remove") => {
+            let name = args.get(1).ok_or("Missing model name")?;
+            model_registry::remove_model(&app_data_dir, name)?;
+            println!("🗑️  Removed model {}", name);
+            Ok(())
+        }
+        Some(other) => Err(format!("Unknown models command: {}", other)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+/// Parse an enum option using the same names as its JSON representation
+fn parse_enum<T: serde::de::DeserializeOwned>(flag: &str, value: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+fn run_upscale(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let mut args = parse_upscale_args(args)?;
+    if args.range.is_some() {
+        return Err("--range can only be used with sequence".into());
+    }
+    args.options = model_registry::resolve_options(
+        &app_data_dir()?,
+        &args.options,
+        &VerifiedFiles::default(),
+    )?;
+
+    let options = &args.options;
+
+    if !Path::new(&args.input).exists() {
+        return Err(format!("Input file does not exist: {}", args.input));
+    }
+    options.validate()?;
+
+    let output = args.output.unwrap_or_else(|| {
+        let input = Path::new(&args.input);
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        input
+            .with_file_name(format!(
+                "{}_{}x.{}",
+                stem,
+                options.factor,
+                pipeline::output_extension(&args.input, options)
+            ))
+            .to_string_lossy()
+            .to_string()
+    });
+
+    let context = if options.method.uses_gpu() {
+        #[cfg(target_os = "macos")]
+        crate::setup_moltenvk_for_command().map_err(|e| format!("MoltenVK setup failed: {}", e))?;
+
+        Some(VulkanContext::new().map_err(|e| format!("Vulkan initialization failed: {}", e))?)
+    } else {
+        None
+    };
+
+    let report = pipeline::upscale_file(
+        context.as_ref(),
+        &args.input,
+        &output,
+        args.region.as_ref(),
+        options,
+    )
+    .map_err(|e| format!("Image processing failed: {}", e))?;
+
+    if let Some(grid) = report.grid {
+        println!(
+            "Pixel grid: {}x{} blocks at offset ({}, {}), native size {}x{}",
+            grid.block_width,
+            grid.block_height,
+            grid.offset_x,
+            grid.offset_y,
+            grid.native_width,
+            grid.native_height
+        );
+    }
+
+    report.timings.log();
+    println!("🎉 Saved upscaled image to: {}", output);
+    Ok(())
+}
+
+/// `v-upscale sequence <pattern> --range <first>-<last> [options]`
+fn run_sequence(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let mut args = parse_upscale_args(args)?;
+    if args.region.is_some() {
+        return Err("--region can't be used with sequence".into());
+    }
+    let range = args
+        .range
+        .as_deref()
+        .ok_or("Missing --range <first>-<last>")?;
+    let (first, last) = range
+        .split_once('-')
+        .ok_or_else(|| format!("Invalid value for --range: {}", range))?;
+    let first: u32 = parse_number("--range", first)?;
+    let last: u32 = parse_number("--range", last)?;
+    args.options = model_registry::resolve_options(
+        &app_data_dir()?,
+        &args.options,
+        &VerifiedFiles::default(),
+    )?;
+    let options = &args.options;
+    options.validate()?;
+
+    let output = args
+        .output
+        .unwrap_or_else(|| sequence::default_output_pattern(&args.input, options));
+    let input_pattern = FramePattern::parse(&args.input)?;
+    let output_pattern = FramePattern::parse(&output)?;
+
+    let context = if options.method.uses_gpu() {
+        #[cfg(target_os = "macos")]
+        crate::setup_moltenvk_for_command().map_err(|e| format!("MoltenVK setup failed: {}", e))?;
+
+        Some(VulkanContext::new().map_err(|e| format!("Vulkan initialization failed: {}", e))?)
+    } else {
+        None
+    };
+
+    let report = sequence::upscale_sequence(
+        context.as_ref(),
+        &input_pattern,
+        &output_pattern,
+        first,
+        last,
+        options,
+    )
+    .map_err(|e| format!("Sequence processing failed: {}", e))?;
+
+    report.timings.log();
+    println!(
+        "🎉 Saved {} frames at {}x{} to: {}",
+        report.frames, report.width, report.height, output
+    );
+    Ok(())
+}
+
+/// `v-upscale metrics <reference> <candidate>`
+fn run_metrics(args: &[String]) -> Result<(), String> {
+    let [reference, candidate] = args else {
+        return Err("Expected a reference and a candidate image".into());
+    };
+    print_quality_report(&metrics::compare_files(reference, candidate)?);
+    Ok(())
+}
+
+/// `v-upscale evaluate <ground-truth> [options]`
+fn run_evaluate(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let mut args = parse_upscale_args(args)?;
+    if args.region.is_some() {
+        return Err("--region can't be used with evaluate".into());
+    }
+    if args.range.is_some() {
+        return Err("--range can only be used with sequence".into());
+    }
+    args.options = model_registry::resolve_options(
+        &app_data_dir()?,
+        &args.options,
+        &VerifiedFiles::default(),
+    )?;
+    let options = &args.options;
+    options.validate()?;
+
+    let ground_truth = image::open(&args.input)
+        .map_err(|e| format!("Failed to open {}: {}", args.input, e))?
+        .to_rgba8();
+
+    let context = if options.method.uses_gpu() {
+        #[cfg(target_os = "macos")]
+        crate::setup_moltenvk_for_command().map_err(|e| format!("MoltenVK setup failed: {}", e))?;
+
+        Some(VulkanContext::new().map_err(|e| format!("Vulkan initialization failed: {}", e))?)
+    } else {
+        None
+    };
+
+    let (upscaled, report) = metrics::evaluate(context.as_ref(), &ground_truth, options)
+        .map_err(|e| format!("Evaluation failed: {}", e))?;
+    if let Some(output) = &args.output {
+        pipeline::save_image(
+            &upscaled,
+            output,
+            options.output_format,
+            options.jpeg_quality,
+        )
+        .map_err(|e| format!("Failed to save output image: {}", e))?;
+        println!("💾 Saved upscaled image to: {}", output);
+    }
+
+    print_quality_report(&report);
+    Ok(())
+}
+
+fn print_quality_report(report: &QualityReport) {
+    let row = |name: &str, scores: &ChannelScores, precision: usize| {
+        println!(
+            "{:<8}{:>10.*}{:>10.*}{:>10.*}{:>10.*}",
+            name,
+            precision,
+            scores.overall,
+            precision,
+            scores.red,
+            precision,
+            scores.green,
+            precision,
+            scores.blue
+        );
+    };
+
+    println!("{}x{}", report.width, report.height);
+    println!(
+        "{:<8}{:>10}{:>10}{:>10}{:>10}",
+        "", "overall", "red", "green", "blue"
+    );
+    row("PSNR", &report.psnr, 2);
+    row("SSIM", &report.ssim, 4);
+    match &report.ms_ssim {
+        Some(ms_ssim) => row("MS-SSIM", ms_ssim, 4),
+        None => println!(
+            "MS-SSIM needs at least {} pixels on each side",
+            metrics::MIN_MS_SSIM_SIZE
+        ),
+    }
+}
+
+/// Comma-separated list of numbers, e.g. `--sizes 256,512`
+fn parse_list<T: std::str::FromStr>(flag: &str, value: &str) -> Result<Vec<T>, String> {
+    value
+        .split(',')
+        .map(|item| parse_number(flag, item.trim()))
+        .collect()
+}
+
+/// `v-upscale benchmark [options]`
+fn run_benchmark(args: &[String]) -> Result<(), String> {
+    let mut sizes = benchmark::DEFAULT_SIZES.to_vec();
+    let mut sizes_given = false;
+    let mut inputs = Vec::new();
+    let mut factors = benchmark::DEFAULT_FACTORS.to_vec();
+    let mut iterations = benchmark::DEFAULT_ITERATIONS;
+    let mut output = "benchmark.json".to_string();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |flag: &str| {
+            iter.next()
+                .map(String::as_str)
+                .ok_or_else(|| format!("Missing value for {}", flag))
+        };
+        match arg.as_str() {
+            "--sizes" => {
+                sizes = parse_list(arg, value(arg)?)?;
+                sizes_given = true;
+            }
+            "--factors" => factors = parse_list(arg, value(arg)?)?,
+            "--iterations" => iterations = parse_number(arg, value(arg)?)?,
+            "--input" => inputs.push(value(arg)?.to_string()),
+            "-o" | "--output" => output = value(arg)?.to_string(),
+            other => return Err(format!("Unknown option: {}", other)),
+        }
+    }
+
+    // Given inputs replace the generated images, unless sizes were asked for too
+    let mut images = Vec::new();
+    if inputs.is_empty() || sizes_given {
+        for &size in &sizes {
+            if !(1..=8192).contains(&size) {
+                return Err(format!("Invalid test image size: {}", size));
+            }
+            images.push(BenchmarkImage::generated(size)?);
+        }
+    }
+    for input in &inputs {
+        images.push(BenchmarkImage::load(input)?);
+    }
+
+    #[cfg(target_os = "macos")]
+    crate::setup_moltenvk_for_command().map_err(|e| format!("MoltenVK setup failed: {}", e))?;
+
+    let report = benchmark::run(&BenchmarkConfig {
+        images,
+        factors,
+        iterations,
+    })
+    .map_err(|e| format!("Benchmark failed: {}", e))?;
+
+    let json = serde_json::to_string_pretty(&report)
+        .map_err(|e| format!("Failed to serialize benchmark report: {}", e))?;
+    std::fs::write(&output, json).map_err(|e| format!("Failed to write {}: {}", output, e))?;
+
+    println!(
+        "{:<16}{:<24}{:>7}{:>12}{:>10}",
+        "path", "image", "factor", "median ms", "MP/s"
+    );
+    for result in &report.results {
+        println!(
+            "{:<16}{:<24}{:>7}{:>12.1}{:>10.1}",
+            format!("{:?}", result.path),
+            result.image,
+            result.factor,
+            result.median.total_ms,
+            result.megapixels_per_second
+        );
+    }
+    println!("📊 Wrote benchmark report to: {}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::UpscaleMethod;
+
+    fn parse(args: &[&str]) -> Result<UpscaleArgs, String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        parse_upscale_args(&args)
+    }
+
+    #[test]
+    fn flag_values_that_look_like_flags_are_values() {
+        let args = parse(&["in.png", "-o", "--preset", "--factor", "3"]).unwrap();
+        assert_eq!(args.output.as_deref(), Some("--preset"));
+        assert_eq!(args.options.factor, 3.0);
+        assert_eq!(args.options.method, UpscaleMethod::Enhanced);
+    }
+
+    #[test]
+    fn flags_override_the_preset_wherever_they_appear() {
+        let args = parse(&["in.png", "--factor", "2", "--preset", "Pixel art 4x"]).unwrap();
+        assert_eq!(args.options.method, UpscaleMethod::NearestNeighbor);
+        assert_eq!(args.options.factor, 2.0);
+    }
+
+    #[test]
+    fn range_is_parsed_in_order() {
+        let args = parse(&["frame_%05d.png", "--range", "1-24", "-o", "--range"]).unwrap();
+        assert_eq!(args.range.as_deref(), Some("1-24"));
+        assert_eq!(args.output.as_deref(), Some("--range"));
+        assert!(parse(&["frame_%05d.png", "--range"]).is_err());
+    }
+}
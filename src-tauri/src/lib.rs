@@ -1,14 +1,35 @@
 // src-tauri/src/lib.rs
 
+pub mod animation;
+pub mod benchmark;
+pub mod cli;
+pub mod metrics;
+pub mod model;
+pub mod model_registry;
+pub mod palette;
+pub mod pipeline;
+pub mod pixel_art;
+pub mod presets;
+pub mod preview;
+pub mod sequence;
 pub mod vulkan;
 
-use image::GenericImageView;
+use metrics::QualityReport;
+use model::ModelSettings;
+use model_registry::{ModelImport, ModelManifest, VerifiedFiles};
+use pipeline::{Region, UpscaleMethod, UpscaleOptions, UpscaleReport};
+use pixel_art::PixelArtScaler;
+use presets::Preset;
+use preview::{PreviewCache, PreviewSource};
+use sequence::{FramePattern, SequenceReport};
 use std::env;
-use std::path::Path;
-use tauri::{generate_context, Builder};
+use std::path::{Path, PathBuf};
+use tauri::{generate_context, ipc::Response, AppHandle, Builder, Manager, State};
 use tauri_plugin_dialog::init as dialog_init;
 use tauri_plugin_fs::init as fs_init;
-use vulkan::VulkanContext;
+use vulkan::{
+    InterpolationKernel, LineArtSettings, NeuralSettings, PostProcessSettings, VulkanContext,
+};
 
 #[cfg(target_os = "macos")]
 fn setup_moltenvk_for_command() -> Result<(), Box<dyn std::error::Error>> {
@@ -136,45 +157,427 @@ fn setup_moltenvk_for_command() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 fn upscale_image_enhanced(
     path: String,
-    factor: u32,
+    factor: f32,
     apply_sharpening: Option<bool>,
     apply_contrast_enhancement: Option<bool>,
     apply_noise_reduction: Option<bool>,
-) -> Result<String, String> {
+    settings: Option<PostProcessSettings>,
+    kernel: Option<InterpolationKernel>,
+    palette_lock: Option<bool>,
+    self_ensemble: Option<bool>,
+) -> Result<UpscaleOutput, String> {
     println!(
         "🚀 Starting ENHANCED upscale process for: {} with factor: {}",
         path, factor
     );
 
-    // Default values for optional parameters - optimized for sharpness
-    let sharpening = apply_sharpening.unwrap_or(true);
-    let contrast = apply_contrast_enhancement.unwrap_or(true);
-    let noise_reduction = apply_noise_reduction.unwrap_or(false); // Keep false by default to avoid blur
+    // Start from the caller's settings (or the defaults, which are optimized
+    // for sharpness) and let the individual toggles override them
+    let mut settings = settings.unwrap_or_default();
+    if let Some(sharpening) = apply_sharpening {
+        settings.sharpening = sharpening;
+    }
+    if let Some(contrast) = apply_contrast_enhancement {
+        settings.contrast_enhancement = contrast;
+    }
+    if let Some(noise_reduction) = apply_noise_reduction {
+        settings.noise_reduction = noise_reduction;
+    }
 
+    let kernel = kernel.unwrap_or_default();
+    println!("🔍 Interpolation kernel: {:?}", kernel);
     println!("🎛️  Post-processing settings:");
-    println!("   - Sharpening: {}", sharpening);
-    println!("   - Contrast Enhancement: {}", contrast);
-    println!("   - Noise Reduction: {}", noise_reduction);
+    println!(
+        "   - Sharpening: {} (intensity: {})",
+        settings.sharpening, settings.sharpen_intensity
+    );
+    println!(
+        "   - Contrast Enhancement: {} (gamma: {}, contrast: {}, saturation: {})",
+        settings.contrast_enhancement, settings.gamma, settings.contrast, settings.saturation
+    );
+    println!(
+        "   - Noise Reduction: {} (strength: {})",
+        settings.noise_reduction, settings.denoise_strength
+    );
 
-    // Validate inputs
-    if !Path::new(&path).exists() {
-        let error_msg = format!("❌ Input file does not exist: {}", path);
+    let options = UpscaleOptions {
+        factor,
+        method: UpscaleMethod::Enhanced,
+        kernel,
+        post_processing: settings,
+        palette_lock: palette_lock.unwrap_or(false),
+        self_ensemble: self_ensemble.unwrap_or(false),
+        ..Default::default()
+    };
+    let (output_path, report) = run_upscale_job(&path, None, &options, "enhanced")?;
+
+    println!("🎉 Enhanced upscaling completed successfully!");
+    Ok(UpscaleOutput {
+        output_path,
+        report,
+    })
+}
+
+#[tauri::command]
+fn upscale_image_line_art(
+    path: String,
+    factor: f32,
+    settings: Option<LineArtSettings>,
+    kernel: Option<InterpolationKernel>,
+    palette_lock: Option<bool>,
+    self_ensemble: Option<bool>,
+) -> Result<UpscaleOutput, String> {
+    let settings = settings.unwrap_or_default();
+    println!(
+        "🚀 Starting LINE ART upscale for: {} with factor: {}",
+        path, factor
+    );
+    println!(
+        "✏️  Line art settings: thinning {}, refinement {}, {} passes",
+        settings.thinning, settings.refinement, settings.passes
+    );
+
+    let options = UpscaleOptions {
+        factor,
+        method: UpscaleMethod::LineArt,
+        kernel: kernel.unwrap_or_default(),
+        line_art: settings,
+        palette_lock: palette_lock.unwrap_or(false),
+        self_ensemble: self_ensemble.unwrap_or(false),
+        ..Default::default()
+    };
+    let (output_path, report) = run_upscale_job(&path, None, &options, "line_art")?;
+
+    println!("🎉 Line art upscaling completed: {}", output_path);
+    Ok(UpscaleOutput {
+        output_path,
+        report,
+    })
+}
+
+#[tauri::command]
+fn upscale_image_neural(
+    app: AppHandle,
+    path: String,
+    factor: f32,
+    settings: NeuralSettings,
+    palette_lock: Option<bool>,
+    self_ensemble: Option<bool>,
+) -> Result<UpscaleOutput, String> {
+    println!(
+        "🚀 Starting NEURAL upscale for: {} with factor: {}",
+        path, factor
+    );
+    println!(
+        "🧠 Model: {} (tiles {}px, overlap {}px)",
+        settings.model, settings.tile_size, settings.tile_overlap
+    );
+
+    let options = UpscaleOptions {
+        factor,
+        method: UpscaleMethod::Neural,
+        neural: settings,
+        palette_lock: palette_lock.unwrap_or(false),
+        self_ensemble: self_ensemble.unwrap_or(false),
+        ..Default::default()
+    };
+    let options = resolve_models(&app, &options)?;
+    let (output_path, report) = run_upscale_job(&path, None, &options, "neural")?;
+
+    println!("🎉 Neural upscaling completed: {}", output_path);
+    Ok(UpscaleOutput {
+        output_path,
+        report,
+    })
+}
+
+#[tauri::command]
+fn upscale_image_model(
+    app: AppHandle,
+    path: String,
+    factor: f32,
+    settings: ModelSettings,
+    post_processing: Option<PostProcessSettings>,
+    palette_lock: Option<bool>,
+    self_ensemble: Option<bool>,
+) -> Result<UpscaleOutput, String> {
+    println!(
+        "🚀 Starting ONNX MODEL upscale for: {} with factor: {}",
+        path, factor
+    );
+    println!(
+        "🧠 Model: {} (tiles {}px, overlap {}px)",
+        settings.path, settings.tile_size, settings.tile_overlap
+    );
+
+    let options = UpscaleOptions {
+        factor,
+        method: UpscaleMethod::Model,
+        model: settings,
+        post_processing: post_processing.unwrap_or_default(),
+        palette_lock: palette_lock.unwrap_or(false),
+        self_ensemble: self_ensemble.unwrap_or(false),
+        ..Default::default()
+    };
+    let options = resolve_models(&app, &options)?;
+    let (output_path, report) = run_upscale_job(&path, None, &options, "model")?;
+
+    println!("🎉 Model upscaling completed: {}", output_path);
+    Ok(UpscaleOutput {
+        output_path,
+        report,
+    })
+}
+
+/// Result of the upscale commands: the output file and the job's report,
+/// whose fields (grid, palette colours, stage timings) sit next to
+/// `output_path`
+#[derive(serde::Serialize)]
+struct UpscaleOutput {
+    output_path: String,
+    #[serde(flatten)]
+    report: UpscaleReport,
+}
+
+#[tauri::command]
+fn upscale_image_nearest_neighbor(
+    path: String,
+    factor: f32,
+    detect_grid: Option<bool>,
+) -> Result<UpscaleOutput, String> {
+    println!(
+        "🚀 Starting NEAREST NEIGHBOR upscale (pixel-perfect) for: {} with factor: {}",
+        path, factor
+    );
+
+    let options = UpscaleOptions {
+        factor,
+        method: UpscaleMethod::NearestNeighbor,
+        detect_grid: detect_grid.unwrap_or(true),
+        ..Default::default()
+    };
+    let (output_path, report) = run_upscale_job(&path, None, &options, "nearest")?;
+
+    println!("🎉 Nearest neighbor upscaling completed: {}", output_path);
+    Ok(UpscaleOutput {
+        output_path,
+        report,
+    })
+}
+
+#[tauri::command]
+fn upscale_image_pixel_art(
+    path: String,
+    factor: f32,
+    scaler: Option<PixelArtScaler>,
+    detect_grid: Option<bool>,
+    palette_lock: Option<bool>,
+    self_ensemble: Option<bool>,
+) -> Result<UpscaleOutput, String> {
+    let scaler = scaler.unwrap_or_default();
+    println!(
+        "🚀 Starting PIXEL ART upscale ({:?}) for: {} with factor: {}",
+        scaler, path, factor
+    );
+
+    let options = UpscaleOptions {
+        factor,
+        method: UpscaleMethod::PixelArt,
+        pixel_art_scaler: scaler,
+        detect_grid: detect_grid.unwrap_or(true),
+        palette_lock: palette_lock.unwrap_or(false),
+        self_ensemble: self_ensemble.unwrap_or(false),
+        ..Default::default()
+    };
+    let (output_path, report) = run_upscale_job(&path, None, &options, "pixel_art")?;
+
+    println!("🎉 Pixel art upscaling completed: {}", output_path);
+    Ok(UpscaleOutput {
+        output_path,
+        report,
+    })
+}
+
+#[tauri::command]
+fn upscale_image(
+    path: String,
+    factor: f32,
+    kernel: Option<InterpolationKernel>,
+    palette_lock: Option<bool>,
+    self_ensemble: Option<bool>,
+) -> Result<UpscaleOutput, String> {
+    let kernel = kernel.unwrap_or_default();
+    println!(
+        "🚀 Starting upscale process for: {} with factor: {} ({:?} kernel)",
+        path, factor, kernel
+    );
+
+    let options = UpscaleOptions {
+        factor,
+        method: UpscaleMethod::Standard,
+        kernel,
+        palette_lock: palette_lock.unwrap_or(false),
+        self_ensemble: self_ensemble.unwrap_or(false),
+        ..Default::default()
+    };
+    let (output_path, report) = run_upscale_job(&path, None, &options, "standard")?;
+
+    println!("🎉 Upscaling completed successfully!");
+    Ok(UpscaleOutput {
+        output_path,
+        report,
+    })
+}
+
+/// Upscale only a rectangle of the source, e.g. for previews and comparison
+/// crops. The result matches that rectangle of a full-image upscale with the
+/// same options.
+#[tauri::command]
+fn upscale_image_region(
+    app: AppHandle,
+    path: String,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    options: Option<UpscaleOptions>,
+) -> Result<UpscaleOutput, String> {
+    let options = options.unwrap_or_default();
+    println!(
+        "🚀 Starting REGION upscale ({:?}, {}x) for: {}",
+        options.method, options.factor, path
+    );
+
+    let region = Region {
+        x,
+        y,
+        width,
+        height,
+    };
+    let options = resolve_models(&app, &options)?;
+    let (output_path, report) = run_upscale_job(&path, Some(&region), &options, "region")?;
+
+    println!("🎉 Region upscaling completed: {}", output_path);
+    Ok(UpscaleOutput {
+        output_path,
+        report,
+    })
+}
+
+/// Render a quick preview with the current settings and return the PNG bytes.
+/// Pass a `region` to see a viewport-sized crop at full resolution, or leave
+/// it out to upscale a proxy of the whole image whose result fits in
+/// `max_size` pixels (default 1024). The decoded source and the Vulkan
+/// context are reused between calls.
+#[tauri::command]
+fn preview_image(
+    app: AppHandle,
+    cache: State<'_, PreviewCache>,
+    path: String,
+    options: Option<UpscaleOptions>,
+    region: Option<Region>,
+    max_size: Option<u32>,
+) -> Result<Response, String> {
+    let options = resolve_models(&app, &options.unwrap_or_default())?;
+    options.validate().map_err(|e| {
+        let error_msg = format!("❌ Invalid options: {}", e);
         println!("{}", error_msg);
-        return Err(error_msg);
-    }
+        error_msg
+    })?;
 
-    if factor == 0 || factor > 8 {
-        let error_msg = format!(
-            "❌ Invalid upscale factor: {}. Must be between 1 and 8.",
-            factor
-        );
+    let mut source = cache
+        .source
+        .lock()
+        .map_err(|_| "❌ Preview cache is poisoned")?;
+    let loaded = PreviewSource::load(source.take(), &path).map_err(|e| {
+        let error_msg = format!("❌ {}", e);
         println!("{}", error_msg);
-        return Err(error_msg);
+        error_msg
+    })?;
+    let source = source.insert(loaded);
+
+    let mut context = cache
+        .context
+        .lock()
+        .map_err(|_| "❌ Preview cache is poisoned")?;
+    if options.method.uses_gpu() && context.is_none() {
+        *context = Some(init_vulkan_context()?);
     }
 
-    // Ensure MoltenVK is properly configured for macOS
+    let png = preview::render(
+        context.as_ref(),
+        source,
+        &options,
+        region.as_ref(),
+        max_size.unwrap_or(preview::DEFAULT_PREVIEW_SIZE),
+    )
+    .map_err(|e| {
+        let error_msg = format!("❌ Preview failed: {}", e);
+        println!("{}", error_msg);
+        error_msg
+    })?;
+    Ok(Response::new(png))
+}
+
+/// Score `candidate` against `reference` with PSNR, SSIM and MS-SSIM, per
+/// channel and overall
+#[tauri::command]
+fn compare_images(reference: String, candidate: String) -> Result<QualityReport, String> {
+    println!("📏 Comparing {} against {}", candidate, reference);
+    metrics::compare_files(&reference, &candidate).map_err(|e| {
+        let error_msg = format!("❌ Comparison failed: {}", e);
+        println!("{}", error_msg);
+        error_msg
+    })
+}
+
+/// Shrink the high-resolution image at `path` by the factor, upscale it back
+/// with `options` and score the result against the original
+#[tauri::command]
+fn evaluate_upscale(
+    app: AppHandle,
+    path: String,
+    options: Option<UpscaleOptions>,
+) -> Result<QualityReport, String> {
+    let options = resolve_models(&app, &options.unwrap_or_default())?;
+    options.validate().map_err(|e| {
+        let error_msg = format!("❌ Invalid options: {}", e);
+        println!("{}", error_msg);
+        error_msg
+    })?;
+
+    let ground_truth = image::open(&path)
+        .map_err(|e| {
+            let error_msg = format!("❌ Failed to open {}: {}", path, e);
+            println!("{}", error_msg);
+            error_msg
+        })?
+        .to_rgba8();
+    let vulkan_context = if options.method.uses_gpu() {
+        Some(init_vulkan_context()?)
+    } else {
+        None
+    };
+
+    let (_, report) =
+        metrics::evaluate(vulkan_context.as_ref(), &ground_truth, &options).map_err(|e| {
+            let error_msg = format!("❌ Evaluation failed: {}", e);
+            println!("{}", error_msg);
+            error_msg
+        })?;
+    println!(
+        "🎉 Evaluation completed: PSNR {:.2} dB, SSIM {:.4}",
+        report.psnr.overall, report.ssim.overall
+    );
+    Ok(report)
+}
+
+/// Configure MoltenVK where needed and create a Vulkan context, logging the
+/// loader environment on failure
+fn init_vulkan_context() -> Result<VulkanContext, String> {
     #[cfg(target_os = "macos")]
     {
         setup_moltenvk_for_command().map_err(|e| {
@@ -194,172 +597,207 @@ fn upscale_image_enhanced(
         error_msg
     })?;
     println!("✅ Vulkan context initialized successfully");
+    Ok(vulkan_context)
+}
+
+/// Upscale `path` (or only `region` of it) with `options` into a new file in
+/// the temp directory and return its path
+fn run_upscale_job(
+    path: &str,
+    region: Option<&Region>,
+    options: &UpscaleOptions,
+    label: &str,
+) -> Result<(String, UpscaleReport), String> {
+    if !Path::new(path).exists() {
+        let error_msg = format!("❌ Input file does not exist: {}", path);
+        println!("{}", error_msg);
+        return Err(error_msg);
+    }
+
+    options.validate().map_err(|e| {
+        let error_msg = format!("❌ Invalid options: {}", e);
+        println!("{}", error_msg);
+        error_msg
+    })?;
+
+    let vulkan_context = if options.method.uses_gpu() {
+        Some(init_vulkan_context()?)
+    } else {
+        None
+    };
 
     let output_path = {
         let temp_dir = env::temp_dir();
         let file_name = format!(
-            "upscaled_enhanced_{}x_{}.png",
-            factor,
+            "upscaled_{}_{}x_{}.{}",
+            label,
+            options.factor,
             std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
-                .as_secs()
+                .as_secs(),
+            pipeline::output_extension(path, options)
         );
         temp_dir.join(file_name).to_str().unwrap().to_string()
     };
 
     println!("🎯 Output path: {}", output_path);
 
-    vulkan::process_image_enhanced(
-        &vulkan_context,
-        &path,
-        &output_path,
-        factor,
-        sharpening,
-        contrast,
-        noise_reduction,
-    )
-    .map_err(|e| {
-        let error_msg = format!("❌ Enhanced image processing failed: {}", e);
-        println!("{}", error_msg);
-        error_msg
-    })?;
+    let report =
+        pipeline::upscale_file(vulkan_context.as_ref(), path, &output_path, region, options)
+            .map_err(|e| {
+                let error_msg = format!("❌ Image processing failed: {}", e);
+                println!("{}", error_msg);
+                error_msg
+            })?;
+    report.timings.log();
 
-    println!("🎉 Enhanced upscaling completed successfully!");
-    Ok(output_path)
+    Ok((output_path, report))
 }
 
+/// Upscale frames `first..=last` of the numbered sequence `input_pattern`
+/// (e.g. `frame_%05d.png`) into `output_pattern`, by default
+/// `<pattern>_<factor>x.<format>` next to the input. Auto-levels and CLAHE
+/// are fitted to the whole shot and noise reduction is followed by a
+/// temporal filter, so the output doesn't flicker.
 #[tauri::command]
-fn upscale_image_nearest_neighbor(path: String, factor: u32) -> Result<String, String> {
+fn upscale_image_sequence(
+    app: AppHandle,
+    input_pattern: String,
+    output_pattern: Option<String>,
+    first: u32,
+    last: u32,
+    options: Option<UpscaleOptions>,
+) -> Result<SequenceReport, String> {
     println!(
-        "🚀 Starting NEAREST NEIGHBOR upscale (pixel-perfect) for: {} with factor: {}",
-        path, factor
+        "🎬 Upscaling frames {}-{} of: {}",
+        first, last, input_pattern
     );
 
-    // Validate inputs
-    if !Path::new(&path).exists() {
-        let error_msg = format!("❌ Input file does not exist: {}", path);
-        println!("{}", error_msg);
-        return Err(error_msg);
-    }
-
-    if factor == 0 || factor > 8 {
-        let error_msg = format!(
-            "❌ Invalid upscale factor: {}. Must be between 1 and 8.",
-            factor
-        );
-        println!("{}", error_msg);
-        return Err(error_msg);
-    }
-
-    // For nearest neighbor, we can use simple image library upscaling
-    let input_image = image::open(&path).map_err(|e| {
-        let error_msg = format!("❌ Failed to open input image: {}", e);
+    let options = resolve_models(&app, &options.unwrap_or_default())?;
+    let output_pattern = output_pattern
+        .unwrap_or_else(|| sequence::default_output_pattern(&input_pattern, &options));
+    let patterns = FramePattern::parse(&input_pattern)
+        .and_then(|input| Ok((input, FramePattern::parse(&output_pattern)?)));
+    let (input, output) = patterns.map_err(|e| {
+        let error_msg = format!("❌ {}", e);
         println!("{}", error_msg);
         error_msg
     })?;
 
-    let (width, height) = input_image.dimensions();
-    let output_width = width * factor;
-    let output_height = height * factor;
-
-    // Use image library's nearest neighbor resize
-    let resized = input_image.resize_exact(
-        output_width,
-        output_height,
-        image::imageops::FilterType::Nearest,
-    );
-
-    let output_path = {
-        let temp_dir = env::temp_dir();
-        let file_name = format!(
-            "upscaled_nearest_{}x_{}.png",
-            factor,
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-        );
-        temp_dir.join(file_name).to_str().unwrap().to_string()
+    let vulkan_context = if options.method.uses_gpu() {
+        Some(init_vulkan_context()?)
+    } else {
+        None
     };
 
-    resized.save(&output_path).map_err(|e| {
-        let error_msg = format!("❌ Failed to save output image: {}", e);
+    let report = sequence::upscale_sequence(
+        vulkan_context.as_ref(),
+        &input,
+        &output,
+        first,
+        last,
+        &options,
+    )
+    .map_err(|e| {
+        let error_msg = format!("❌ Sequence processing failed: {}", e);
         println!("{}", error_msg);
         error_msg
     })?;
+    report.timings.log();
+    println!(
+        "🎉 Upscaled {} frames to: {}",
+        report.frames, output_pattern
+    );
+    Ok(report)
+}
 
-    println!("🎉 Nearest neighbor upscaling completed: {}", output_path);
-    Ok(output_path)
+fn app_data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map_err(|e| format!("❌ Could not resolve app data directory: {}", e))
 }
 
 #[tauri::command]
-fn upscale_image(path: String, factor: u32) -> Result<String, String> {
-    println!(
-        "🚀 Starting upscale process for: {} with factor: {}",
-        path, factor
-    );
+fn list_presets(app: AppHandle) -> Result<Vec<Preset>, String> {
+    presets::list_presets(&app_data_dir(&app)?)
+}
 
-    // Validate inputs
-    if !Path::new(&path).exists() {
-        let error_msg = format!("❌ Input file does not exist: {}", path);
+#[tauri::command]
+fn save_preset(app: AppHandle, preset: Preset) -> Result<(), String> {
+    println!("💾 Saving preset: {}", preset.name);
+    presets::save_preset(&app_data_dir(&app)?, preset).map_err(|e| {
+        let error_msg = format!("❌ {}", e);
         println!("{}", error_msg);
-        return Err(error_msg);
-    }
+        error_msg
+    })
+}
 
-    if factor == 0 || factor > 8 {
-        let error_msg = format!(
-            "❌ Invalid upscale factor: {}. Must be between 1 and 8.",
-            factor
-        );
+#[tauri::command]
+fn delete_preset(app: AppHandle, name: String) -> Result<(), String> {
+    println!("🗑️  Deleting preset: {}", name);
+    presets::delete_preset(&app_data_dir(&app)?, &name).map_err(|e| {
+        let error_msg = format!("❌ {}", e);
         println!("{}", error_msg);
-        return Err(error_msg);
-    }
+        error_msg
+    })
+}
 
-    // Ensure MoltenVK is properly configured for macOS
-    #[cfg(target_os = "macos")]
-    {
-        setup_moltenvk_for_command().map_err(|e| {
-            let error_msg = format!("❌ MoltenVK setup failed: {}", e);
-            println!("{}", error_msg);
-            error_msg
-        })?;
-    }
+#[tauri::command]
+fn apply_preset(app: AppHandle, name: String, path: String) -> Result<UpscaleOutput, String> {
+    println!("🚀 Applying preset \"{}\" to: {}", name, path);
 
-    println!("📦 Initializing Vulkan context...");
-    let vulkan_context = VulkanContext::new().map_err(|e| {
-        let error_msg = format!("❌ Vulkan initialization failed: {}", e);
+    let preset = presets::find_preset(&app_data_dir(&app)?, &name).map_err(|e| {
+        let error_msg = format!("❌ {}", e);
         println!("{}", error_msg);
-        println!("💡 Debug info:");
-        println!("   VK_ICD_FILENAMES = {:?}", env::var("VK_ICD_FILENAMES"));
-        println!("   VK_DRIVER_FILES = {:?}", env::var("VK_DRIVER_FILES"));
         error_msg
     })?;
-    println!("✅ Vulkan context initialized successfully");
 
-    let output_path = {
-        let temp_dir = env::temp_dir();
-        let file_name = format!(
-            "upscaled_{}x_{}.png",
-            factor,
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-        );
-        temp_dir.join(file_name).to_str().unwrap().to_string()
-    };
+    let options = resolve_models(&app, &preset.options)?;
+    let (output_path, report) = run_upscale_job(&path, None, &options, "preset")?;
+    println!("🎉 Preset \"{}\" applied successfully!", preset.name);
+    Ok(UpscaleOutput {
+        output_path,
+        report,
+    })
+}
 
-    println!("🎯 Output path: {}", output_path);
+/// Swap a registered model name for its verified file, see
+/// `model_registry::resolve_options`
+fn resolve_models(app: &AppHandle, options: &UpscaleOptions) -> Result<UpscaleOptions, String> {
+    let verified = app.state::<VerifiedFiles>();
+    model_registry::resolve_options(&app_data_dir(app)?, options, &verified).map_err(|e| {
+        let error_msg = format!("❌ {}", e);
+        println!("{}", error_msg);
+        error_msg
+    })
+}
 
-    vulkan::process_image(&vulkan_context, &path, &output_path, factor).map_err(|e| {
-        let error_msg = format!("❌ Image processing failed: {}", e);
+#[tauri::command]
+fn list_models(app: AppHandle) -> Result<Vec<ModelManifest>, String> {
+    model_registry::list_models(&app_data_dir(&app)?)
+}
+
+#[tauri::command]
+fn import_model(app: AppHandle, import: ModelImport) -> Result<ModelManifest, String> {
+    println!("📥 Importing model: {}", import.path);
+    let manifest = model_registry::import_model(&app_data_dir(&app)?, &import).map_err(|e| {
+        let error_msg = format!("❌ {}", e);
         println!("{}", error_msg);
         error_msg
     })?;
+    println!("✅ Imported model {} ({}x)", manifest.name, manifest.scale);
+    Ok(manifest)
+}
 
-    println!("🎉 Upscaling completed successfully!");
-    Ok(output_path)
+#[tauri::command]
+fn remove_model(app: AppHandle, name: String) -> Result<(), String> {
+    println!("🗑️  Removing model: {}", name);
+    model_registry::remove_model(&app_data_dir(&app)?, &name).map_err(|e| {
+        let error_msg = format!("❌ {}", e);
+        println!("{}", error_msg);
+        error_msg
+    })
 }
 
 #[tauri::command]
@@ -409,12 +847,30 @@ pub fn run() {
             }
             Ok(())
         })
+        .manage(PreviewCache::default())
+        .manage(VerifiedFiles::default())
         .plugin(fs_init())
         .plugin(dialog_init())
         .invoke_handler(tauri::generate_handler![
             upscale_image,
             upscale_image_enhanced,
+            upscale_image_line_art,
+            upscale_image_neural,
+            upscale_image_model,
             upscale_image_nearest_neighbor,
+            upscale_image_pixel_art,
+            upscale_image_region,
+            upscale_image_sequence,
+            preview_image,
+            compare_images,
+            evaluate_upscale,
+            list_presets,
+            save_preset,
+            delete_preset,
+            apply_preset,
+            list_models,
+            import_model,
+            remove_model,
             test_moltenvk_setup
         ])
         .run(generate_context!())
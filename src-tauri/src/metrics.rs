@@ -0,0 +1,423 @@
+//! Full-reference image quality metrics
+//!
+//! [`compare`] scores a candidate image against a reference of the same size
+//! with PSNR, SSIM and MS-SSIM, per colour channel and overall, so kernels and
+//! post-processing settings can be compared with numbers instead of by eye.
+//! [`evaluate`] builds the comparison for an upscale method: it shrinks a
+//! high-resolution ground truth by the factor, upscales it back and scores the
+//! result against the original.
+//!
+//! All metrics run on the 8-bit RGB channels premultiplied by alpha, so opaque
+//! images are compared as they are and colour hidden under transparent pixels
+//! doesn't count. SSIM uses the usual 11x11 Gaussian window (sigma 1.5) over
+//! the positions where it fits inside the image, and MS-SSIM the five scales
+//! and weights of Wang et al. (2003).
+
+use crate::pipeline::{self, UpscaleOptions};
+use crate::vulkan::{downscale, InterpolationKernel, VulkanContext};
+use image::{imageops, RgbaImage};
+use serde::Serialize;
+
+/// Side of the SSIM window, in pixels
+const WINDOW_SIZE: usize = 11;
+
+/// Standard deviation of the SSIM window's Gaussian weights
+const WINDOW_SIGMA: f64 = 1.5;
+
+/// SSIM stabilizing constants, `(0.01 * 255)^2` and `(0.03 * 255)^2`
+const C1: f64 = 6.5025;
+const C2: f64 = 58.5225;
+
+/// Weight of each MS-SSIM scale, finest first
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// Shortest side that still fits the SSIM window at the coarsest MS-SSIM scale
+pub const MIN_MS_SSIM_SIZE: u32 = (WINDOW_SIZE as u32) << (MS_SSIM_WEIGHTS.len() - 1);
+
+/// One metric for each colour channel, and the mean over the channels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ChannelScores {
+    pub overall: f64,
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+}
+
+/// Scores of a candidate image against its reference
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QualityReport {
+    pub width: u32,
+    pub height: u32,
+    /// Peak signal-to-noise ratio in dB. Infinite (`null` in JSON) for
+    /// identical channels. The overall value comes from the mean squared error
+    /// of all three channels.
+    pub psnr: ChannelScores,
+    /// Structural similarity, 1 for identical images
+    pub ssim: ChannelScores,
+    /// Multi-scale structural similarity, for images of at least
+    /// [`MIN_MS_SSIM_SIZE`] pixels on both sides
+    pub ms_ssim: Option<ChannelScores>,
+}
+
+/// One premultiplied colour channel as floats
+struct Plane {
+    width: usize,
+    height: usize,
+    data: Vec<f64>,
+}
+
+impl Plane {
+    fn from_channel(image: &RgbaImage, channel: usize) -> Self {
+        let data = image
+            .pixels()
+            .map(|pixel| pixel[channel] as f64 * pixel[3] as f64 / 255.0)
+            .collect();
+        Plane {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            data,
+        }
+    }
+
+    fn map(&self, other: &Plane, f: impl Fn(f64, f64) -> f64) -> Plane {
+        Plane {
+            width: self.width,
+            height: self.height,
+            data: self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(&a, &b)| f(a, b))
+                .collect(),
+        }
+    }
+
+    /// Average of each 2x2 block; an odd last row or column is dropped
+    fn half(&self) -> Plane {
+        let (width, height) = (self.width / 2, self.height / 2);
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = 2 * y * self.width;
+            for x in 0..width {
+                let i = row + 2 * x;
+                let sum = self.data[i]
+                    + self.data[i + 1]
+                    + self.data[i + self.width]
+                    + self.data[i + self.width + 1];
+                data.push(sum / 4.0);
+            }
+        }
+        Plane {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Gaussian-weighted mean over every window position that fits inside the
+    /// plane, filtering rows then columns
+    fn window_means(&self, window: &[f64; WINDOW_SIZE]) -> Plane {
+        let width = self.width + 1 - WINDOW_SIZE;
+        let height = self.height + 1 - WINDOW_SIZE;
+
+        let mut rows: Vec<f64> = Vec::with_capacity(width * self.height);
+        for y in 0..self.height {
+            let row = &self.data[y * self.width..(y + 1) * self.width];
+            for x in 0..width {
+                rows.push(
+                    row[x..x + WINDOW_SIZE]
+                        .iter()
+                        .zip(window)
+                        .map(|(v, w)| v * w)
+                        .sum(),
+                );
+            }
+        }
+
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let sum = window
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| rows[(y + i) * width + x] * w)
+                    .sum();
+                data.push(sum);
+            }
+        }
+        Plane {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// Normalized 1D Gaussian weights of the SSIM window
+fn gaussian_window() -> [f64; WINDOW_SIZE] {
+    let center = (WINDOW_SIZE / 2) as f64;
+    let mut window = [0.0; WINDOW_SIZE];
+    for (i, weight) in window.iter_mut().enumerate() {
+        let d = i as f64 - center;
+        *weight = (-d * d / (2.0 * WINDOW_SIGMA * WINDOW_SIGMA)).exp();
+    }
+    let sum: f64 = window.iter().sum();
+    window.map(|weight| weight / sum)
+}
+
+fn psnr(mse: f64) -> f64 {
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+fn mean_squared_error(reference: &Plane, candidate: &Plane) -> f64 {
+    let sum: f64 = reference
+        .data
+        .iter()
+        .zip(&candidate.data)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum();
+    sum / reference.data.len() as f64
+}
+
+/// Mean SSIM and mean contrast-structure term of two planes
+fn ssim(reference: &Plane, candidate: &Plane, window: &[f64; WINDOW_SIZE]) -> (f64, f64) {
+    let mu_x = reference.window_means(window);
+    let mu_y = candidate.window_means(window);
+    let xx = reference.map(reference, |a, b| a * b).window_means(window);
+    let yy = candidate.map(candidate, |a, b| a * b).window_means(window);
+    let xy = reference.map(candidate, |a, b| a * b).window_means(window);
+
+    let (mut ssim_sum, mut cs_sum) = (0.0, 0.0);
+    for i in 0..mu_x.data.len() {
+        let (mx, my) = (mu_x.data[i], mu_y.data[i]);
+        let var_x = xx.data[i] - mx * mx;
+        let var_y = yy.data[i] - my * my;
+        let covariance = xy.data[i] - mx * my;
+
+        let cs = (2.0 * covariance + C2) / (var_x + var_y + C2);
+        let luminance = (2.0 * mx * my + C1) / (mx * mx + my * my + C1);
+        ssim_sum += luminance * cs;
+        cs_sum += cs;
+    }
+    let count = mu_x.data.len() as f64;
+    (ssim_sum / count, cs_sum / count)
+}
+
+/// Scores of one channel: mean squared error, SSIM and MS-SSIM when the
+/// planes are large enough
+fn channel_scores(
+    reference: Plane,
+    candidate: Plane,
+    window: &[f64; WINDOW_SIZE],
+) -> (f64, f64, Option<f64>) {
+    let mse = mean_squared_error(&reference, &candidate);
+    let (full_ssim, full_cs) = ssim(&reference, &candidate, window);
+
+    let multi_scale = reference.width.min(reference.height) >= MIN_MS_SSIM_SIZE as usize;
+    if !multi_scale {
+        return (mse, full_ssim, None);
+    }
+
+    // Contrast-structure at every scale but the coarsest, full SSIM there.
+    // Negative terms are clamped so the weighted product stays defined.
+    let mut ms_ssim = full_cs.max(0.0).powf(MS_SSIM_WEIGHTS[0]);
+    let (mut reference, mut candidate) = (reference, candidate);
+    for (scale, weight) in MS_SSIM_WEIGHTS.iter().enumerate().skip(1) {
+        reference = reference.half();
+        candidate = candidate.half();
+        let (scale_ssim, scale_cs) = ssim(&reference, &candidate, window);
+        let term = if scale == MS_SSIM_WEIGHTS.len() - 1 {
+            scale_ssim
+        } else {
+            scale_cs
+        };
+        ms_ssim *= term.max(0.0).powf(*weight);
+    }
+    (mse, full_ssim, Some(ms_ssim))
+}
+
+/// Score `candidate` against `reference`. Both must have the same size, at
+/// least the SSIM window on each side.
+pub fn compare(reference: &RgbaImage, candidate: &RgbaImage) -> Result<QualityReport, String> {
+    let (width, height) = reference.dimensions();
+    if candidate.dimensions() != (width, height) {
+        return Err(format!(
+            "Images differ in size: reference {}x{}, candidate {}x{}",
+            width,
+            height,
+            candidate.width(),
+            candidate.height()
+        ));
+    }
+    if width.min(height) < WINDOW_SIZE as u32 {
+        return Err(format!(
+            "Images must be at least {}x{} pixels to compare, not {}x{}",
+            WINDOW_SIZE, WINDOW_SIZE, width, height
+        ));
+    }
+
+    let window = gaussian_window();
+    let scores: Vec<(f64, f64, Option<f64>)> = (0..3)
+        .map(|channel| {
+            channel_scores(
+                Plane::from_channel(reference, channel),
+                Plane::from_channel(candidate, channel),
+                &window,
+            )
+        })
+        .collect();
+
+    let channels = |values: [f64; 3], overall: f64| ChannelScores {
+        overall,
+        red: values[0],
+        green: values[1],
+        blue: values[2],
+    };
+    let mse = [scores[0].0, scores[1].0, scores[2].0];
+    let ssim = [scores[0].1, scores[1].1, scores[2].1];
+    let ms_ssim = scores
+        .iter()
+        .map(|score| score.2)
+        .collect::<Option<Vec<f64>>>()
+        .map(|ms_ssim| [ms_ssim[0], ms_ssim[1], ms_ssim[2]]);
+
+    Ok(QualityReport {
+        width,
+        height,
+        psnr: channels(mse.map(psnr), psnr(mse.iter().sum::<f64>() / 3.0)),
+        ssim: channels(ssim, ssim.iter().sum::<f64>() / 3.0),
+        ms_ssim: ms_ssim.map(|values| channels(values, values.iter().sum::<f64>() / 3.0)),
+    })
+}
+
+/// Load two image files and score `candidate_path` against `reference_path`
+pub fn compare_files(reference_path: &str, candidate_path: &str) -> Result<QualityReport, String> {
+    let open = |path: &str| {
+        image::open(path)
+            .map(|image| image.to_rgba8())
+            .map_err(|e| format!("Failed to open {}: {}", path, e))
+    };
+    compare(&open(reference_path)?, &open(candidate_path)?)
+}
+
+/// Shrink `ground_truth` by the upscale factor with the area kernel, upscale
+/// it back with `options` and score the result against the ground truth.
+/// Sides that aren't a multiple of the factor are cropped first. Returns the
+/// upscaled image along with its scores.
+pub fn evaluate(
+    context: Option<&VulkanContext>,
+    ground_truth: &RgbaImage,
+    options: &UpscaleOptions,
+) -> Result<(RgbaImage, QualityReport), Box<dyn std::error::Error>> {
+    options.validate()?;
+    if options.is_downscale() {
+        return Err("Evaluation needs an upscale factor (1 or more)".into());
+    }
+
+    let factor = options.upscale_factor();
+    let (width, height) = (
+        ground_truth.width() / factor,
+        ground_truth.height() / factor,
+    );
+    if width == 0 || height == 0 {
+        return Err(format!(
+            "A {}x{} image is too small to evaluate at {}x",
+            ground_truth.width(),
+            ground_truth.height(),
+            factor
+        )
+        .into());
+    }
+    let reference =
+        imageops::crop_imm(ground_truth, 0, 0, width * factor, height * factor).to_image();
+
+    let input = if factor == 1 {
+        reference.clone()
+    } else {
+        let data = downscale::downscale(
+            reference.as_raw(),
+            reference.width(),
+            reference.height(),
+            width,
+            height,
+            InterpolationKernel::Area,
+        )?;
+        RgbaImage::from_raw(width, height, data).ok_or("Invalid downscaled image")?
+    };
+    println!(
+        "📐 Evaluating {:?} {}x: {}x{} -> {}x{}",
+        options.method,
+        factor,
+        width,
+        height,
+        reference.width(),
+        reference.height()
+    );
+
+    let (upscaled, _) = pipeline::upscale(context, &input, options)?;
+    if upscaled.dimensions() != reference.dimensions() {
+        return Err(format!(
+            "The {:?} method produced {}x{} instead of {}x{}",
+            options.method,
+            upscaled.width(),
+            upscaled.height(),
+            reference.width(),
+            reference.height()
+        )
+        .into());
+    }
+
+    let report = compare(&reference, &upscaled)?;
+    Ok((upscaled, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(width: u32, height: u32, offset: u8) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let value = ((x * 7 + y * 13) % 200) as u8 + offset;
+            image::Rgba([value, value / 2, 255 - value, 255])
+        })
+    }
+
+    #[test]
+    fn identical_images_score_perfectly() {
+        let image = pattern(MIN_MS_SSIM_SIZE, MIN_MS_SSIM_SIZE, 0);
+        let report = compare(&image, &image).unwrap();
+        assert!(report.psnr.overall.is_infinite() && report.psnr.overall > 0.0);
+        assert!((report.ssim.overall - 1.0).abs() < 1e-9);
+        let ms_ssim = report.ms_ssim.expect("image is large enough for MS-SSIM");
+        assert!((ms_ssim.overall - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn constant_offset_gives_analytic_psnr() {
+        let offset = 10u8;
+        let reference = pattern(32, 24, 0);
+        let candidate = RgbaImage::from_fn(32, 24, |x, y| {
+            let [r, g, b, a] = reference.get_pixel(x, y).0;
+            image::Rgba([r + offset, g + offset, b - offset, a])
+        });
+        let report = compare(&reference, &candidate).unwrap();
+
+        let expected = 10.0 * (255.0f64 * 255.0 / (offset as f64 * offset as f64)).log10();
+        for psnr in [
+            report.psnr.overall,
+            report.psnr.red,
+            report.psnr.green,
+            report.psnr.blue,
+        ] {
+            assert!((psnr - expected).abs() < 1e-9, "{} != {}", psnr, expected);
+        }
+        assert!(report.ms_ssim.is_none());
+    }
+
+    #[test]
+    fn rejects_mismatched_and_tiny_images() {
+        assert!(compare(&pattern(32, 32, 0), &pattern(32, 31, 0)).is_err());
+        let tiny = pattern(WINDOW_SIZE as u32 - 1, 64, 0);
+        assert!(compare(&tiny, &tiny).is_err());
+    }
+}
@@ -0,0 +1,364 @@
+//! ONNX super-resolution models on the CPU
+//!
+//! Runs super-resolution networks exported to ONNX through tract, a pure-Rust
+//! inference engine, so no GPU or native runtime is needed for the network
+//! itself. Models are expected to take a `1 x 3 x H x W` RGB tensor in
+//! `0..=1` and return a `1 x 3 x sH x sW` tensor for an integer scale `s`,
+//! the layout PyTorch SR models (Real-ESRGAN, SwinIR, ...) are exported with.
+//!
+//! The image is processed in tiles of one fixed size, so the model is
+//! optimized once. Neighbouring tiles overlap and their outputs are
+//! cross-faded over the overlap, which hides seams from the network's
+//! different context at tile edges.
+
+use crate::vulkan::neural::resize_alpha;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use tract_onnx::prelude::*;
+use tract_onnx::tract_hir::infer::Factoid;
+
+/// Model file and tiling of the `model` upscale method
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelSettings {
+    /// Path to the `.onnx` file
+    pub path: String,
+    /// Edge length of the input tiles in pixels, `32..=1024`. Ignored for
+    /// models with a fixed input size. Default: `128`.
+    pub tile_size: u32,
+    /// Overlap between neighbouring tiles in input pixels, `0..=64` and less
+    /// than half the tile size. Default: `16`.
+    pub tile_overlap: u32,
+}
+
+impl Default for ModelSettings {
+    fn default() -> Self {
+        ModelSettings {
+            path: String::new(),
+            tile_size: 128,
+            tile_overlap: 16,
+        }
+    }
+}
+
+impl ModelSettings {
+    /// Check that a model is set and the tiling is inside its documented range
+    pub fn validate(&self) -> Result<(), String> {
+        if self.path.trim().is_empty() {
+            return Err("The model method needs the path to an .onnx file".into());
+        }
+        if !(32..=1024).contains(&self.tile_size) {
+            return Err(format!(
+                "Invalid tile size: {}. Must be between 32 and 1024.",
+                self.tile_size
+            ));
+        }
+        if self.tile_overlap > 64 || self.tile_overlap * 2 >= self.tile_size {
+            return Err(format!(
+                "Invalid tile overlap: {}. Must be between 0 and 64 and less than half \
+                 the tile size.",
+                self.tile_overlap
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An ONNX model optimized for one tile size
+pub struct OnnxModel {
+    plan: TypedRunnableModel<TypedModel>,
+    /// Input tile size in pixels
+    pub tile_width: u32,
+    pub tile_height: u32,
+    /// Whether the model only accepts its own tile size
+    pub fixed_size: bool,
+    /// Ratio of output to input size
+    pub scale: u32,
+}
+
+impl OnnxModel {
+    /// Load the model at `path` for `tile_width` x `tile_height` input tiles.
+    /// A model with a fixed input size uses that size instead.
+    pub fn load(
+        path: &str,
+        tile_width: u32,
+        tile_height: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let model = tract_onnx::onnx()
+            .model_for_path(path)
+            .map_err(|e| format!("Failed to load model {}: {}", path, e))?;
+
+        // Concrete input dimensions, `None` where the model accepts any size
+        let fact = model.input_fact(0)?;
+        let dims: Vec<Option<usize>> = fact
+            .shape
+            .dims()
+            .map(|dim| {
+                dim.concretize()
+                    .and_then(|dim| dim.to_i64().ok())
+                    .map(|dim| dim as usize)
+            })
+            .collect();
+        if !dims.is_empty() && (dims.len() != 4 || dims[1].is_some_and(|c| c != 3)) {
+            return Err(format!(
+                "Model input has shape {:?}, expected 1 x 3 x height x width",
+                dims
+            )
+            .into());
+        }
+        let (tile_width, tile_height, fixed_size) = match (dims.get(3), dims.get(2)) {
+            (Some(&Some(width)), Some(&Some(height))) => (width as u32, height as u32, true),
+            _ => (tile_width, tile_height, false),
+        };
+
+        let shape = [1, 3, tile_height as usize, tile_width as usize];
+        // Exported output shapes often use their own symbols for the size;
+        // drop them and let tract infer the output from the tile size
+        let plan = model
+            .with_input_fact(0, f32::fact(shape).into())?
+            .with_output_fact(0, InferenceFact::default())?
+            .into_optimized()?
+            .into_runnable()?;
+
+        let output_shape = plan
+            .model()
+            .output_fact(0)?
+            .shape
+            .as_concrete()
+            .map(|shape| shape.to_vec())
+            .unwrap_or_default();
+        let (tile_w, tile_h) = (tile_width as usize, tile_height as usize);
+        let scale = match output_shape[..] {
+            [1, 3, height, width] if width % tile_w == 0 && height == width / tile_w * tile_h => {
+                width / tile_w
+            }
+            _ => 0,
+        };
+        if scale == 0 {
+            return Err(format!(
+                "Model maps {:?} to {:?}, expected an RGB upscaler with an integer scale",
+                shape, output_shape
+            )
+            .into());
+        }
+
+        Ok(OnnxModel {
+            plan,
+            tile_width,
+            tile_height,
+            fixed_size,
+            scale: scale as u32,
+        })
+    }
+
+    /// Run one planar RGB tile of `tile_width` x `tile_height` pixels and
+    /// return the planar RGB output
+    pub fn run(&self, tile: Vec<f32>) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let shape = [1, 3, self.tile_height as usize, self.tile_width as usize];
+        let input = tract_ndarray::Array4::from_shape_vec(shape, tile)?;
+        let outputs = self.plan.run(tvec!(Tensor::from(input).into()))?;
+        let output = outputs[0].to_array_view::<f32>()?;
+        Ok(output.iter().copied().collect())
+    }
+}
+
+/// Start positions of `tile`-sized windows covering `0..size`, overlapping by
+/// at least `overlap`. The last window ends exactly at `size`.
+fn tile_positions(size: u32, tile: u32, overlap: u32) -> Vec<u32> {
+    if size <= tile {
+        return vec![0];
+    }
+    let stride = tile - overlap;
+    let mut positions: Vec<u32> = (0..)
+        .map(|i| i * stride)
+        .take_while(|&start| start + tile < size)
+        .collect();
+    positions.push(size - tile);
+    positions
+}
+
+/// Blend weight of position `offset` in a tile of `length` output pixels.
+/// Rises over `ramp` pixels at edges shared with another tile and stays at 1
+/// at the image border. The smoothstep ramp gives the pixels right at the tile
+/// edge, which saw the least context, almost no weight; opposite ramps still
+/// add up to 1.
+fn blend_weight(offset: u32, length: u32, ramp: u32, first: bool, last: bool) -> f32 {
+    if ramp == 0 {
+        return 1.0;
+    }
+    let mut t = 1.0f32;
+    if !first {
+        t = t.min((offset as f32 + 0.5) / ramp as f32);
+    }
+    if !last {
+        t = t.min(((length - offset) as f32 - 0.5) / ramp as f32);
+    }
+    let t = t.min(1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Upscale `image` by the model's scale, tile by tile.
+///
+/// Tiles that reach past the image (fixed-size models on small images) are
+/// filled by repeating the edge pixels. Alpha is resized with Catmull-Rom.
+pub fn upscale_tiled(
+    model: &OnnxModel,
+    image: &RgbaImage,
+    overlap: u32,
+) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err("Cannot upscale an empty image".into());
+    }
+    let (tile_w, tile_h, scale) = (model.tile_width, model.tile_height, model.scale);
+    let overlap = overlap.min(tile_w / 2).min(tile_h / 2);
+    let (out_w, out_h) = (width * scale, height * scale);
+    let ramp = overlap * scale;
+
+    let columns = tile_positions(width, tile_w, overlap);
+    let rows = tile_positions(height, tile_h, overlap);
+    let plane = out_w as usize * out_h as usize;
+    let mut sum = vec![0.0f32; 3 * plane];
+    let mut weight_sum = vec![0.0f32; plane];
+
+    for (row, &y0) in rows.iter().enumerate() {
+        for (column, &x0) in columns.iter().enumerate() {
+            println!(
+                "  - Tile {}/{}",
+                row * columns.len() + column + 1,
+                rows.len() * columns.len()
+            );
+
+            let (tw, th) = (tile_w as usize, tile_h as usize);
+            let mut tile = vec![0.0f32; 3 * tw * th];
+            for y in 0..th {
+                for x in 0..tw {
+                    let pixel = image.get_pixel(
+                        (x0 + x as u32).min(width - 1),
+                        (y0 + y as u32).min(height - 1),
+                    );
+                    for c in 0..3 {
+                        tile[(c * th + y) * tw + x] = pixel[c] as f32 / 255.0;
+                    }
+                }
+            }
+
+            let result = model.run(tile)?;
+            let (rw, rh) = (tile_w * scale, tile_h * scale);
+            let first_column = column == 0;
+            let last_column = column + 1 == columns.len();
+            let first_row = row == 0;
+            let last_row = row + 1 == rows.len();
+
+            for y in 0..rh.min(out_h - y0 * scale) {
+                let weight_y = blend_weight(y, rh, ramp, first_row, last_row);
+                for x in 0..rw.min(out_w - x0 * scale) {
+                    let weight = weight_y * blend_weight(x, rw, ramp, first_column, last_column);
+                    let index = ((y0 * scale + y) * out_w + x0 * scale + x) as usize;
+                    weight_sum[index] += weight;
+                    for c in 0..3 {
+                        let value =
+                            result[(c * rh as usize + y as usize) * rw as usize + x as usize];
+                        sum[c * plane + index] += value * weight;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut output = RgbaImage::from_fn(out_w, out_h, |x, y| {
+        let index = (y * out_w + x) as usize;
+        let mut pixel = [0u8, 0, 0, 255];
+        for c in 0..3 {
+            let value = sum[c * plane + index] / weight_sum[index];
+            pixel[c] = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        image::Rgba(pixel)
+    });
+    resize_alpha(image, &mut output);
+    Ok(output)
+}
+
+/// Load the model in `settings` with tiles for images of `width` x `height`,
+/// once per job
+pub fn load(
+    settings: &ModelSettings,
+    width: u32,
+    height: u32,
+) -> Result<OnnxModel, Box<dyn std::error::Error>> {
+    settings.validate()?;
+    let model = OnnxModel::load(
+        &settings.path,
+        settings.tile_size.min(width),
+        settings.tile_size.min(height),
+    )?;
+    println!(
+        "🧠 Loaded ONNX model {} ({}x, {}x{} tiles)",
+        settings.path, model.scale, model.tile_width, model.tile_height
+    );
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_the_image() {
+        for (size, tile, overlap) in [
+            (10, 32, 8),
+            (32, 32, 8),
+            (100, 32, 8),
+            (129, 64, 0),
+            (97, 40, 16),
+        ] {
+            let positions = tile_positions(size, tile, overlap);
+            assert_eq!(positions[0], 0);
+            if size <= tile {
+                assert_eq!(positions, [0]);
+                continue;
+            }
+            assert_eq!(positions.last().unwrap() + tile, size);
+            for pair in positions.windows(2) {
+                assert!(pair[0] < pair[1], "{:?} for {}", positions, size);
+                assert!(
+                    pair[0] + tile >= pair[1] + overlap,
+                    "{:?} overlaps by less than {}",
+                    positions,
+                    overlap
+                );
+            }
+        }
+    }
+
+    /// Sum of the blend weights of all tiles at each pixel of an axis
+    fn weight_sums(size: u32, tile: u32, overlap: u32) -> Vec<f32> {
+        let positions = tile_positions(size, tile, overlap);
+        let mut sums = vec![0.0; size as usize];
+        for (index, &start) in positions.iter().enumerate() {
+            let first = index == 0;
+            let last = index + 1 == positions.len();
+            for offset in 0..tile.min(size - start) {
+                let weight = blend_weight(offset, tile, overlap, first, last);
+                assert!(weight > 0.0, "tile at {} has weight 0 at {}", start, offset);
+                sums[(start + offset) as usize] += weight;
+            }
+        }
+        sums
+    }
+
+    #[test]
+    fn blend_weights_add_up_to_one_across_overlaps() {
+        // Tiles exactly `overlap` apart: the opposite ramps sum to 1
+        let (tile, overlap) = (32, 8);
+        let size = 3 * (tile - overlap) + overlap;
+        for (x, sum) in weight_sums(size, tile, overlap).iter().enumerate() {
+            assert!((sum - 1.0).abs() < 1e-5, "weights sum to {} at {}", sum, x);
+        }
+
+        // A last tile pulled back to the border overlaps more; every pixel
+        // still gets weight, which `upscale_tiled` normalizes
+        assert!(weight_sums(100, 32, 8).iter().all(|&sum| sum > 0.0));
+        assert!(weight_sums(96, 32, 0).iter().all(|&sum| sum == 1.0));
+    }
+}
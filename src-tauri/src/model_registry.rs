@@ -0,0 +1,724 @@
+//! Local model registry
+//!
+//! Models for the `neural` (ncnn) and `model` (ONNX) methods can be imported
+//! into `models/` under the app data directory. Each model gets its own
+//! directory with the model files and a `manifest.json`:
+//!
+//! ```json
+//! {
+//!   "name": "realesr-animevideov3-x2",
+//!   "format": "ncnn",
+//!   "scale": 2,
+//!   "input_channels": 3,
+//!   "tile": { "fixed_size": null, "multiple_of": 1, "min_size": 32, "max_size": 1024 },
+//!   "license": "BSD-3-Clause",
+//!   "files": [
+//!     { "name": "realesr-animevideov3-x2.param", "size": 5031, "sha256": "…" },
+//!     { "name": "realesr-animevideov3-x2.bin", "size": 2487684, "sha256": "…" }
+//!   ]
+//! }
+//! ```
+//!
+//! The manifest holds nothing machine- or time-specific, so importing the same
+//! files with the same name and license gives a byte-identical manifest. A
+//! model directory copied from a colleague can be imported as-is: its files
+//! are checked against the manifest's hashes first. Every file is checked
+//! again before a registered model is loaded; [`VerifiedFiles`] skips hashing
+//! files that haven't changed since they were last verified.
+
+use crate::model::OnnxModel;
+use crate::pipeline::{UpscaleMethod, UpscaleOptions};
+use crate::vulkan::neural::Network;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Directory under the app data directory holding the registered models
+const MODELS_DIR: &str = "models";
+
+/// Manifest file in each model directory
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Longest accepted model name
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Tile sizes `ModelSettings` and `NeuralSettings` accept
+const TILE_SIZE_RANGE: std::ops::RangeInclusive<u32> = 32..=1024;
+
+/// Model file format, which decides the upscale method that can run it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelFormat {
+    /// ncnn `.param` + `.bin`, run on the GPU by the `neural` method
+    Ncnn,
+    /// `.onnx`, run on the CPU by the `model` method
+    Onnx,
+}
+
+impl ModelFormat {
+    fn method(self) -> UpscaleMethod {
+        match self {
+            ModelFormat::Ncnn => UpscaleMethod::Neural,
+            ModelFormat::Onnx => UpscaleMethod::Model,
+        }
+    }
+}
+
+/// Input tile sizes a model works with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileConstraints {
+    /// The only input size the model accepts, as `[width, height]`
+    pub fixed_size: Option<[u32; 2]>,
+    /// Tile sides are rounded down to a multiple of this, for networks that
+    /// downsample internally. Default: `1`.
+    pub multiple_of: u32,
+    /// Smallest tile side. Default: `32`.
+    pub min_size: u32,
+    /// Largest tile side. Default: `1024`.
+    pub max_size: u32,
+}
+
+impl Default for TileConstraints {
+    fn default() -> Self {
+        TileConstraints {
+            fixed_size: None,
+            multiple_of: 1,
+            min_size: 32,
+            max_size: 1024,
+        }
+    }
+}
+
+impl TileConstraints {
+    /// Tile sides allowed by both the constraints and the model settings
+    fn bounds(&self) -> (u32, u32) {
+        (
+            self.min_size.max(*TILE_SIZE_RANGE.start()),
+            self.max_size.min(*TILE_SIZE_RANGE.end()),
+        )
+    }
+
+    /// Check that some multiple of `multiple_of` lies in both `min_size..=max_size`
+    /// and the tile sizes the model settings accept
+    fn validate(&self) -> Result<(), String> {
+        let (min, max) = self.bounds();
+        if self.multiple_of == 0 || min > max || max / self.multiple_of * self.multiple_of < min {
+            return Err(format!(
+                "Invalid tile constraints: multiple of {}, {} to {} pixels. Tiles must \
+                 allow a size between {} and {}.",
+                self.multiple_of,
+                self.min_size,
+                self.max_size,
+                TILE_SIZE_RANGE.start(),
+                TILE_SIZE_RANGE.end()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Closest allowed tile size to `size`, rounded down to a multiple of
+    /// `multiple_of` where that stays in bounds
+    fn apply(&self, size: u32) -> u32 {
+        let (min, max) = self.bounds();
+        let size = size.clamp(min, max) / self.multiple_of * self.multiple_of;
+        if size < min {
+            min.div_ceil(self.multiple_of) * self.multiple_of
+        } else {
+            size
+        }
+    }
+}
+
+/// A file belonging to a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelFile {
+    /// File name inside the model directory
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Lowercase hex SHA-256 of the contents
+    pub sha256: String,
+}
+
+/// Everything recorded about a registered model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelManifest {
+    pub name: String,
+    pub format: ModelFormat,
+    /// Factor the model enlarges by
+    pub scale: u32,
+    /// Input channels (3 for RGB)
+    pub input_channels: u32,
+    pub tile: TileConstraints,
+    /// License of the weights, e.g. an SPDX identifier
+    pub license: String,
+    /// Model files; the first one is loaded (`.onnx` or `.param`)
+    pub files: Vec<ModelFile>,
+}
+
+/// What to import into the registry
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelImport {
+    /// An `.onnx` file, an ncnn `.param` file with its `.bin` next to it, or a
+    /// model directory with a `manifest.json`
+    pub path: String,
+    /// Registry name. Default: the manifest's name or the file name without
+    /// extension.
+    pub name: Option<String>,
+    /// License of the weights. Required unless importing a model directory.
+    pub license: Option<String>,
+    /// Tile constraints. Default: the manifest's, or detected from the model.
+    pub tile: Option<TileConstraints>,
+}
+
+fn models_dir(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join(MODELS_DIR)
+}
+
+/// Check that `name` can be used as a directory name on every platform
+fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid model name: {:?}. Use up to {} letters, digits, '.', '-' and '_'.",
+            name, MAX_NAME_LENGTH
+        ))
+    }
+}
+
+/// Lowercase hex SHA-256 and size of a file
+fn hash_file(path: &Path) -> Result<(String, u64), String> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    let mut size = 0u64;
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok((hash, size))
+}
+
+/// Hashes of model files taken earlier, keyed on path. Model files can be
+/// hundreds of megabytes and the app resolves the model for every preview, so
+/// a file is only hashed again when its size or modification time changed.
+#[derive(Default)]
+pub struct VerifiedFiles {
+    hashes: Mutex<HashMap<PathBuf, (u64, SystemTime, String)>>,
+}
+
+impl VerifiedFiles {
+    /// Like [`hash_file`], reusing the hash of an unchanged file
+    fn hash(&self, path: &Path) -> Result<(String, u64), String> {
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let (size, modified) = (metadata.len(), metadata.modified().ok());
+        let mut hashes = self
+            .hashes
+            .lock()
+            .map_err(|_| "Model verification cache is poisoned")?;
+        if let (Some(modified), Some((cached_size, cached_modified, sha256))) =
+            (modified, hashes.get(path))
+        {
+            if *cached_size == size && *cached_modified == modified {
+                return Ok((sha256.clone(), size));
+            }
+        }
+
+        let (sha256, size) = hash_file(path)?;
+        match modified {
+            Some(modified) => hashes.insert(path.to_path_buf(), (size, modified, sha256.clone())),
+            None => hashes.remove(path),
+        };
+        Ok((sha256, size))
+    }
+}
+
+fn read_manifest(dir: &Path) -> Result<ModelManifest, String> {
+    let path = dir.join(MANIFEST_FILE);
+    let json = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let manifest: ModelManifest = serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+    if manifest.files.is_empty() {
+        return Err(format!("{} lists no model files", path.display()));
+    }
+    for file in &manifest.files {
+        // File names must stay inside the model directory
+        validate_name(&file.name)
+            .map_err(|_| format!("Invalid file name in {}: {:?}", path.display(), file.name))?;
+    }
+    Ok(manifest)
+}
+
+fn write_manifest(dir: &Path, manifest: &ModelManifest) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(manifest)
+        .map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+    let path = dir.join(MANIFEST_FILE);
+    std::fs::write(&path, json + "\n")
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Check every file in `dir` against the sizes and hashes in `manifest`
+pub fn verify(dir: &Path, manifest: &ModelManifest) -> Result<(), String> {
+    check_files(dir, manifest, hash_file)
+}
+
+fn check_files(
+    dir: &Path,
+    manifest: &ModelManifest,
+    hash: impl Fn(&Path) -> Result<(String, u64), String>,
+) -> Result<(), String> {
+    for file in &manifest.files {
+        let (sha256, size) = hash(&dir.join(&file.name))?;
+        if size != file.size || !sha256.eq_ignore_ascii_case(&file.sha256) {
+            return Err(format!(
+                "Model {} failed the integrity check: {} has SHA-256 {} ({} bytes), \
+                 the manifest expects {} ({} bytes)",
+                manifest.name, file.name, sha256, size, file.sha256, file.size
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Manifests of all registered models, sorted by name
+pub fn list_models(app_data_dir: &Path) -> Result<Vec<ModelManifest>, String> {
+    let dir = models_dir(app_data_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let entries =
+        std::fs::read_dir(&dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut manifests = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+            .path();
+        // Skips half-finished imports and stray files
+        if path.join(MANIFEST_FILE).is_file() {
+            manifests.push(read_manifest(&path)?);
+        }
+    }
+    manifests.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(manifests)
+}
+
+/// Load a model file to check it runs and measure its scale and tile size
+fn inspect(format: ModelFormat, path: &Path) -> Result<(u32, Option<[u32; 2]>), String> {
+    match format {
+        ModelFormat::Ncnn => {
+            let network = Network::load(path).map_err(|e| e.to_string())?;
+            Ok((network.scale().map_err(|e| e.to_string())?, None))
+        }
+        ModelFormat::Onnx => {
+            let path = path.to_str().ok_or("Model path is not valid UTF-8")?;
+            let model = OnnxModel::load(path, 64, 64).map_err(|e| e.to_string())?;
+            let fixed = model
+                .fixed_size
+                .then_some([model.tile_width, model.tile_height]);
+            Ok((model.scale, fixed))
+        }
+    }
+}
+
+/// Manifest and source directory for importing a single model file
+fn manifest_for_file(import: &ModelImport) -> Result<(ModelManifest, PathBuf), String> {
+    let path = Path::new(&import.path);
+    let format = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("onnx") => ModelFormat::Onnx,
+        Some(ext) if ext.eq_ignore_ascii_case("param") => ModelFormat::Ncnn,
+        _ => {
+            return Err(format!(
+                "Unsupported model file {}: expected .onnx, .param or a model directory",
+                path.display()
+            ))
+        }
+    };
+    let license = import
+        .license
+        .as_deref()
+        .map(str::trim)
+        .filter(|license| !license.is_empty())
+        .ok_or("A license is required when importing a model file")?;
+    let name = match &import.name {
+        Some(name) => name.trim().to_string(),
+        None => path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    let mut paths = vec![path.to_path_buf()];
+    if format == ModelFormat::Ncnn {
+        paths.push(path.with_extension("bin"));
+    }
+    let files = paths
+        .iter()
+        .map(|path| {
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| format!("Invalid model file name: {}", path.display()))?;
+            validate_name(file_name)
+                .map_err(|_| format!("Unsupported characters in file name {:?}", file_name))?;
+            let (sha256, size) = hash_file(path)?;
+            Ok(ModelFile {
+                name: file_name.to_string(),
+                size,
+                sha256,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let (scale, fixed_size) = inspect(format, path)?;
+    let tile = import.tile.clone().unwrap_or(TileConstraints {
+        fixed_size,
+        ..Default::default()
+    });
+    let source_dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+
+    Ok((
+        ModelManifest {
+            name,
+            format,
+            scale,
+            input_channels: 3,
+            tile,
+            license: license.to_string(),
+            files,
+        },
+        source_dir,
+    ))
+}
+
+/// Import a model into the registry and return its manifest
+pub fn import_model(app_data_dir: &Path, import: &ModelImport) -> Result<ModelManifest, String> {
+    let source = Path::new(&import.path);
+    let (manifest, source_dir) = if source.is_dir() {
+        let mut manifest = read_manifest(source)?;
+        verify(source, &manifest)?;
+        if let Some(name) = &import.name {
+            manifest.name = name.trim().to_string();
+        }
+        if let Some(tile) = &import.tile {
+            manifest.tile = tile.clone();
+        }
+        (manifest, source.to_path_buf())
+    } else {
+        manifest_for_file(import)?
+    };
+    validate_name(&manifest.name)?;
+    manifest.tile.validate()?;
+
+    // A shared manifest has to describe the model it came with
+    let main_file = source_dir.join(&manifest.files[0].name);
+    let (scale, _) = inspect(manifest.format, &main_file)?;
+    if scale != manifest.scale {
+        return Err(format!(
+            "Model {} upscales {}x, but its manifest says {}x",
+            manifest.name, scale, manifest.scale
+        ));
+    }
+
+    let dir = models_dir(app_data_dir);
+    let target = dir.join(&manifest.name);
+    if target.exists() {
+        return Err(format!(
+            "A model named {} is already registered; remove it first",
+            manifest.name
+        ));
+    }
+
+    // Copy into a temporary directory first so a failed import leaves nothing behind
+    let temp = dir.join(format!(".{}.tmp", manifest.name));
+    let _ = std::fs::remove_dir_all(&temp);
+    std::fs::create_dir_all(&temp)
+        .map_err(|e| format!("Failed to create {}: {}", temp.display(), e))?;
+    let result = manifest
+        .files
+        .iter()
+        .try_for_each(|file| {
+            std::fs::copy(source_dir.join(&file.name), temp.join(&file.name))
+                .map(|_| ())
+                .map_err(|e| format!("Failed to copy {}: {}", file.name, e))
+        })
+        .and_then(|()| verify(&temp, &manifest))
+        .and_then(|()| write_manifest(&temp, &manifest))
+        .and_then(|()| {
+            std::fs::rename(&temp, &target)
+                .map_err(|e| format!("Failed to create {}: {}", target.display(), e))
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(&temp);
+    }
+    result?;
+
+    Ok(manifest)
+}
+
+/// Remove a registered model and its files
+pub fn remove_model(app_data_dir: &Path, name: &str) -> Result<(), String> {
+    validate_name(name)?;
+    let dir = models_dir(app_data_dir).join(name);
+    if !dir.join(MANIFEST_FILE).is_file() {
+        return Err(format!("Unknown model: {}", name));
+    }
+    std::fs::remove_dir_all(&dir).map_err(|e| format!("Failed to remove {}: {}", dir.display(), e))
+}
+
+/// Look up a registered model by name or by the path of one of its files,
+/// and check its files against the manifest
+fn find_verified(
+    app_data_dir: &Path,
+    reference: &str,
+    verified: &VerifiedFiles,
+) -> Result<Option<(PathBuf, ModelManifest)>, String> {
+    let by_name = models_dir(app_data_dir).join(reference);
+    let dir = if validate_name(reference).is_ok() && by_name.join(MANIFEST_FILE).is_file() {
+        by_name
+    } else {
+        match Path::new(reference).parent() {
+            Some(parent) if parent.join(MANIFEST_FILE).is_file() => parent.to_path_buf(),
+            _ => return Ok(None),
+        }
+    };
+
+    let manifest = read_manifest(&dir)?;
+    check_files(&dir, &manifest, |path| verified.hash(path))?;
+    Ok(Some((dir, manifest)))
+}
+
+/// Resolve the model of a `neural` or `model` job.
+///
+/// The model setting may name a registered model or point at a file in a
+/// model directory. Either way the files are verified against the manifest,
+/// the setting is replaced with the path of the file to load and the tile
+/// size is fitted to the model's constraints. Paths outside the registry are
+/// left alone. Files unchanged since `verified` last hashed them aren't
+/// hashed again.
+pub fn resolve_options(
+    app_data_dir: &Path,
+    options: &UpscaleOptions,
+    verified: &VerifiedFiles,
+) -> Result<UpscaleOptions, String> {
+    let mut options = options.clone();
+    let (reference, tile_size) = match options.method {
+        UpscaleMethod::Neural => (&mut options.neural.model, &mut options.neural.tile_size),
+        UpscaleMethod::Model => (&mut options.model.path, &mut options.model.tile_size),
+        _ => return Ok(options),
+    };
+
+    let Some((dir, manifest)) = find_verified(app_data_dir, reference, verified)? else {
+        return Ok(options);
+    };
+    manifest.tile.validate()?;
+    if manifest.format.method() != options.method {
+        return Err(format!(
+            "Model {} is an {:?} model and can't run with the {:?} method",
+            manifest.name, manifest.format, options.method
+        ));
+    }
+    println!(
+        "🔒 Verified model {} ({} files, {})",
+        manifest.name,
+        manifest.files.len(),
+        manifest.license
+    );
+
+    *reference = dir
+        .join(&manifest.files[0].name)
+        .to_string_lossy()
+        .into_owned();
+    *tile_size = manifest.tile.apply(*tile_size);
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiles(multiple_of: u32, min_size: u32, max_size: u32) -> TileConstraints {
+        TileConstraints {
+            fixed_size: None,
+            multiple_of,
+            min_size,
+            max_size,
+        }
+    }
+
+    #[test]
+    fn apply_rounds_to_the_multiple_within_bounds() {
+        let constraints = tiles(8, 48, 200);
+        assert_eq!(constraints.apply(100), 96);
+        assert_eq!(constraints.apply(10), 48);
+        assert_eq!(constraints.apply(5000), 200);
+        assert_eq!(tiles(64, 40, 300).apply(40), 64);
+        assert_eq!(tiles(64, 40, 300).apply(1000), 256);
+
+        for constraints in [tiles(1, 1, 40), tiles(7, 1, 2000), tiles(100, 1, 5000)] {
+            constraints.validate().unwrap();
+            for size in [1, 31, 32, 500, 1024, 4096] {
+                let tile = constraints.apply(size);
+                assert!(
+                    TILE_SIZE_RANGE.contains(&tile) && tile % constraints.multiple_of == 0,
+                    "{:?} gives {} for {}",
+                    constraints,
+                    tile,
+                    size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_tiles_the_model_settings_refuse() {
+        assert!(tiles(1, 8, 16).validate().is_err());
+        assert!(tiles(1, 2000, 4000).validate().is_err());
+        assert!(tiles(64, 70, 100).validate().is_err());
+        assert!(tiles(0, 32, 64).validate().is_err());
+        assert!(tiles(1, 64, 32).validate().is_err());
+        assert!(TileConstraints::default().validate().is_ok());
+    }
+
+    /// Empty directory in the temp directory that no other test uses
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "v-upscale-registry-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 2x ncnn upscaler: nearest-neighbour Interp and a PReLU whose slope is
+    /// the only weight. Returns the `.param` path.
+    fn write_model(dir: &Path) -> PathBuf {
+        let param = "7767517\n3 3\nInput in 0 1 data\n\
+                     Interp up 1 1 data up 0=1 1=2.0 2=2.0\n\
+                     PReLU act 1 1 up output 0=1\n";
+        std::fs::write(dir.join("tiny.param"), param).unwrap();
+        std::fs::write(dir.join("tiny.bin"), 0.25f32.to_le_bytes()).unwrap();
+        dir.join("tiny.param")
+    }
+
+    fn import(app_data_dir: &Path, source: &Path) -> Result<ModelManifest, String> {
+        import_model(
+            app_data_dir,
+            &ModelImport {
+                path: source.to_string_lossy().into_owned(),
+                license: Some("MIT".to_string()),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn imports_a_model_file_with_its_manifest() {
+        let source = temp_dir("import-source");
+        let app_data = temp_dir("import-app");
+        let param = write_model(&source);
+
+        let manifest = import(&app_data, &param).unwrap();
+        assert_eq!(manifest.name, "tiny");
+        assert_eq!(manifest.format, ModelFormat::Ncnn);
+        assert_eq!(manifest.scale, 2);
+        assert_eq!(manifest.license, "MIT");
+        let names: Vec<&str> = manifest.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["tiny.param", "tiny.bin"]);
+        assert_eq!(manifest.files[1].size, 4);
+
+        // Only the finished model directory is left behind
+        let dir = models_dir(&app_data);
+        let entries: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["tiny"]);
+        assert_eq!(
+            list_models(&app_data).unwrap(),
+            std::slice::from_ref(&manifest)
+        );
+        assert!(import(&app_data, &param).is_err());
+
+        // The registered directory imports elsewhere as the same manifest
+        let other = temp_dir("import-other");
+        assert_eq!(import(&other, &dir.join("tiny")).unwrap(), manifest);
+        let manifest_json =
+            |root: &Path| std::fs::read(models_dir(root).join("tiny").join(MANIFEST_FILE)).unwrap();
+        assert_eq!(manifest_json(&app_data), manifest_json(&other));
+
+        remove_model(&app_data, "tiny").unwrap();
+        assert!(list_models(&app_data).unwrap().is_empty());
+        assert!(remove_model(&app_data, "tiny").is_err());
+
+        for dir in [source, app_data, other] {
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn resolves_registered_models_and_rejects_tampered_files() {
+        let source = temp_dir("tamper-source");
+        let app_data = temp_dir("tamper-app");
+        let manifest = import(&app_data, &write_model(&source)).unwrap();
+        let dir = models_dir(&app_data).join("tiny");
+        verify(&dir, &manifest).unwrap();
+
+        let mut options = UpscaleOptions {
+            method: UpscaleMethod::Neural,
+            ..Default::default()
+        };
+        options.neural.model = "tiny".to_string();
+        options.neural.tile_size = 5000;
+        let verified = VerifiedFiles::default();
+        let resolved = resolve_options(&app_data, &options, &verified).unwrap();
+        assert_eq!(
+            Path::new(&resolved.neural.model),
+            dir.join("tiny.param").as_path()
+        );
+        assert_eq!(resolved.neural.tile_size, 1024);
+
+        options.method = UpscaleMethod::Model;
+        options.model.path = "tiny".to_string();
+        assert!(resolve_options(&app_data, &options, &verified).is_err());
+        options.method = UpscaleMethod::Neural;
+
+        // Changed weights fail the check, also after an earlier verification
+        std::fs::write(dir.join("tiny.bin"), [0u8; 8]).unwrap();
+        assert!(verify(&dir, &manifest).is_err());
+        assert!(resolve_options(&app_data, &options, &verified).is_err());
+
+        std::fs::remove_dir_all(source).unwrap();
+        std::fs::remove_dir_all(app_data).unwrap();
+    }
+}
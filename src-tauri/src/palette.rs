@@ -0,0 +1,257 @@
+//! Palette lock and indexed PNG output
+//!
+//! Interpolating kernels and post-processing blend neighbouring pixels, so an
+//! indexed PNG or a limited-palette sprite comes out with thousands of new
+//! colours. With palette lock the colours of the source image are collected
+//! first and every output pixel is mapped back to the closest of them. When the
+//! locked result has at most 256 colours it can be written as an indexed PNG.
+
+use image::RgbaImage;
+use std::collections::{HashMap, HashSet};
+
+/// Largest source palette palette lock accepts. Mapping cost grows with the
+/// palette size, and images with more colours than this aren't palette art.
+pub const MAX_PALETTE_COLORS: usize = 1024;
+
+/// Largest palette an indexed PNG can hold
+pub const MAX_INDEXED_COLORS: usize = 256;
+
+/// The distinct colours of an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Vec<[u8; 4]>,
+}
+
+impl Palette {
+    /// Collect the distinct colours of `image`, or `None` if it has more than
+    /// `max_colors`. Fully transparent pixels count as a single colour.
+    pub fn extract(image: &RgbaImage, max_colors: usize) -> Option<Palette> {
+        let mut seen = HashSet::new();
+        let mut colors = Vec::new();
+        for pixel in image.pixels() {
+            let color = normalize(pixel.0);
+            if seen.insert(color) {
+                if colors.len() == max_colors {
+                    return None;
+                }
+                colors.push(color);
+            }
+        }
+
+        // Translucent entries first, so an indexed PNG's tRNS chunk stays short
+        colors.sort_by_key(|color| color[3] == 255);
+        Some(Palette { colors })
+    }
+
+    pub fn colors(&self) -> &[[u8; 4]] {
+        &self.colors
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /// Index of the palette entry closest to `color`
+    fn nearest(&self, color: [u8; 4]) -> usize {
+        let mut best = 0;
+        let mut best_distance = u32::MAX;
+        for (index, &entry) in self.colors.iter().enumerate() {
+            let distance = color_distance(color, entry);
+            if distance < best_distance {
+                best = index;
+                best_distance = distance;
+                if distance == 0 {
+                    break;
+                }
+            }
+        }
+        best
+    }
+
+    /// Palette index of every pixel of `image`
+    fn index_pixels(&self, image: &RgbaImage) -> Vec<usize> {
+        // Upscaled images repeat the same blended colours a lot, so cache lookups
+        let mut cache: HashMap<[u8; 4], usize> = HashMap::new();
+        image
+            .pixels()
+            .map(|pixel| {
+                let color = normalize(pixel.0);
+                *cache.entry(color).or_insert_with(|| self.nearest(color))
+            })
+            .collect()
+    }
+
+    /// Replace every pixel of `image` with the closest palette colour
+    pub fn remap(&self, image: &RgbaImage) -> RgbaImage {
+        let (width, height) = image.dimensions();
+        let data = self
+            .index_pixels(image)
+            .into_iter()
+            .flat_map(|index| self.colors[index])
+            .collect();
+        RgbaImage::from_raw(width, height, data).expect("remapped image has the same size")
+    }
+}
+
+/// Map all fully transparent pixels to the same colour
+fn normalize(color: [u8; 4]) -> [u8; 4] {
+    if color[3] == 0 {
+        [0, 0, 0, 0]
+    } else {
+        color
+    }
+}
+
+/// Weighted squared distance between two colours, compared with premultiplied
+/// alpha so differences in the colour of nearly transparent pixels don't count
+fn color_distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+    let premultiply =
+        |color: [u8; 4], channel: usize| color[channel] as i32 * color[3] as i32 / 255;
+    let dr = premultiply(a, 0) - premultiply(b, 0);
+    let dg = premultiply(a, 1) - premultiply(b, 1);
+    let db = premultiply(a, 2) - premultiply(b, 2);
+    let da = a[3] as i32 - b[3] as i32;
+    (2 * dr * dr + 4 * dg * dg + 3 * db * db + 3 * da * da) as u32
+}
+
+/// Write `image` as an indexed PNG if it has at most 256 colours.
+///
+/// Uses the smallest bit depth that fits the palette. Returns `false`, without
+/// writing anything, if the image has too many colours.
+pub fn save_indexed_png(image: &RgbaImage, path: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let Some(palette) = Palette::extract(image, MAX_INDEXED_COLORS) else {
+        return Ok(false);
+    };
+    if palette.is_empty() {
+        return Ok(false);
+    }
+
+    let (bit_depth, bits) = match palette.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+
+    // Pack the indices of each row, most significant bits first
+    let (width, height) = image.dimensions();
+    let indices = palette.index_pixels(image);
+    let row_bytes = (width as usize * bits).div_ceil(8);
+    let mut data = vec![0u8; row_bytes * height as usize];
+    for (row, row_indices) in indices.chunks(width as usize).enumerate() {
+        let packed = &mut data[row * row_bytes..(row + 1) * row_bytes];
+        for (x, &index) in row_indices.iter().enumerate() {
+            let bit = x * bits;
+            packed[bit / 8] |= (index as u8) << (8 - bits - bit % 8);
+        }
+    }
+
+    let rgb: Vec<u8> = palette
+        .colors()
+        .iter()
+        .flat_map(|c| [c[0], c[1], c[2]])
+        .collect();
+    let alpha: Vec<u8> = palette
+        .colors()
+        .iter()
+        .map(|c| c[3])
+        .take_while(|&a| a < 255)
+        .collect();
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(bit_depth);
+    encoder.set_palette(rgb);
+    if !alpha.is_empty() {
+        encoder.set_trns(alpha);
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Image with `count` distinct colours, the first one translucent
+    fn image_with_colors(count: u32) -> RgbaImage {
+        RgbaImage::from_fn(count, 3, |x, _| {
+            let alpha = if x == 0 { 128 } else { 255 };
+            image::Rgba([
+                (x * 7) as u8,
+                (x * 13 / 5) as u8,
+                (x / 256) as u8 * 90,
+                alpha,
+            ])
+        })
+    }
+
+    /// Path in the temp directory that no other test uses
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("v-upscale-palette-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn remap_only_uses_palette_colors() {
+        let source = RgbaImage::from_fn(4, 4, |x, y| match (x + y) % 4 {
+            0 => image::Rgba([255, 0, 0, 255]),
+            1 => image::Rgba([0, 200, 40, 255]),
+            2 => image::Rgba([20, 20, 240, 160]),
+            _ => image::Rgba([0, 0, 0, 0]),
+        });
+        let palette = Palette::extract(&source, MAX_PALETTE_COLORS).unwrap();
+        assert_eq!(palette.len(), 4);
+
+        let blended = image::imageops::resize(&source, 13, 13, image::imageops::Triangle);
+        let remapped = palette.remap(&blended);
+        assert!(remapped.pixels().all(|p| palette.colors().contains(&p.0)));
+        assert_eq!(palette.remap(&source), source);
+    }
+
+    #[test]
+    fn indexed_png_round_trips_at_the_smallest_bit_depth() {
+        for (colors, depth) in [
+            (2, png::BitDepth::One),
+            (4, png::BitDepth::Two),
+            (5, png::BitDepth::Four),
+            (16, png::BitDepth::Four),
+            (17, png::BitDepth::Eight),
+            (256, png::BitDepth::Eight),
+        ] {
+            let image = image_with_colors(colors);
+            let path = temp_path(&format!("{}.png", colors));
+            assert!(save_indexed_png(&image, path.to_str().unwrap()).unwrap());
+
+            let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+            let reader = decoder.read_info().unwrap();
+            let info = reader.info();
+            assert_eq!(info.color_type, png::ColorType::Indexed);
+            assert_eq!(info.bit_depth, depth, "{} colours", colors);
+            // Only the translucent entry needs a tRNS value
+            assert_eq!(info.trns.as_deref().map(<[u8]>::len), Some(1));
+
+            let decoded = image::open(&path).unwrap().to_rgba8();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(decoded, image, "{} colours", colors);
+        }
+    }
+
+    #[test]
+    fn rejects_too_many_colors() {
+        let image = image_with_colors(MAX_PALETTE_COLORS as u32 + 1);
+        assert!(Palette::extract(&image, MAX_PALETTE_COLORS).is_none());
+        assert!(Palette::extract(&image_with_colors(1024), MAX_PALETTE_COLORS).is_some());
+
+        let path = temp_path("257.png");
+        let image = image_with_colors(MAX_INDEXED_COLORS as u32 + 1);
+        assert!(!save_indexed_png(&image, path.to_str().unwrap()).unwrap());
+        assert!(!path.exists());
+    }
+}
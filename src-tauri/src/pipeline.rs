@@ -0,0 +1,1109 @@
+//! Complete upscale jobs driven by a single serializable set of options
+//!
+//! [`UpscaleOptions`] bundles everything needed to reproduce a result: the
+//! factor, the method, the interpolation kernel, post-processing settings,
+//! self-ensembling, palette lock and the output format. The Tauri commands,
+//! presets and the CLI all run jobs through [`upscale_file`].
+//!
+//! Animated GIF, APNG and WebP inputs are upscaled frame by frame through the
+//! same context and written back as an animation in their own format.
+
+use crate::animation::{self, Animation, AnimationFrame};
+use crate::model::{self, ModelSettings, OnnxModel};
+use crate::palette::{self, Palette, MAX_PALETTE_COLORS};
+use crate::pixel_art::{self, PixelArtScaler, PixelGrid};
+use crate::vulkan::{
+    self, downscale, line_art, neural,
+    post_processing::{self, ShotContrast, TemporalDenoise},
+    ContrastMode, DenoiseMode, InterpolationKernel, LineArtSettings, NeuralSettings,
+    PostProcessSettings, SharpenMode, StageTimings, VulkanContext,
+};
+use image::{
+    imageops::{self, FilterType},
+    RgbaImage,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Instant;
+
+/// Smallest accepted `factor`
+pub const MIN_DOWNSCALE_FACTOR: f32 = 0.01;
+
+/// How the image is enlarged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpscaleMethod {
+    /// GPU interpolation only, like `upscale_image`
+    Standard,
+    /// GPU interpolation followed by post-processing, like `upscale_image_enhanced`
+    #[default]
+    Enhanced,
+    /// GPU interpolation followed by the line-art passes, like `upscale_image_line_art`
+    LineArt,
+    /// Pixel-perfect nearest neighbor on the CPU, like `upscale_image_nearest_neighbor`
+    NearestNeighbor,
+    /// Pixel-art scaler on the CPU, like `upscale_image_pixel_art`
+    PixelArt,
+    /// Super-resolution network on the GPU, like `upscale_image_neural`
+    Neural,
+    /// ONNX super-resolution model on the CPU followed by post-processing,
+    /// like `upscale_image_model`
+    Model,
+}
+
+impl UpscaleMethod {
+    /// Whether this method needs a [`VulkanContext`]
+    pub fn uses_gpu(self) -> bool {
+        !matches!(
+            self,
+            UpscaleMethod::NearestNeighbor | UpscaleMethod::PixelArt
+        )
+    }
+}
+
+/// File format of the upscaled image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Lossless PNG with alpha
+    #[default]
+    Png,
+    /// JPEG at `jpeg_quality`; alpha is dropped
+    Jpeg,
+    /// Lossless WebP with alpha
+    Webp,
+}
+
+impl OutputFormat {
+    /// File extension without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+        }
+    }
+}
+
+/// Everything that determines the output of an upscale job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpscaleOptions {
+    /// Scale factor: a whole number in `1..=8` to upscale, or a fraction in
+    /// `0.01..1` to downscale with an anti-aliasing kernel. Default: `2`.
+    pub factor: f32,
+    /// Default: `enhanced`.
+    pub method: UpscaleMethod,
+    /// Interpolation kernel for the GPU methods. Default: `auto`.
+    pub kernel: InterpolationKernel,
+    /// Scaler for the `pixel_art` method. Default: `xbr`.
+    pub pixel_art_scaler: PixelArtScaler,
+    /// Undo earlier nearest-neighbor enlargement before the `nearest_neighbor`
+    /// and `pixel_art` methods, so `factor` applies to the native pixels.
+    /// Default: `true`.
+    pub detect_grid: bool,
+    /// Post-processing for the `enhanced` and `model` methods.
+    pub post_processing: PostProcessSettings,
+    /// Line thinning and refinement for the `line_art` method.
+    pub line_art: LineArtSettings,
+    /// Model and tiling for the `neural` method.
+    pub neural: NeuralSettings,
+    /// ONNX model and tiling for the `model` method.
+    pub model: ModelSettings,
+    /// Upscale the input under all 8 flips and rotations, undo each transform
+    /// and average the results. Takes 8 times as long but cancels the
+    /// directional artefacts of kernels and models. Default: `false`.
+    pub self_ensemble: bool,
+    /// Map every output pixel back to the closest colour of the source image.
+    /// The source may have at most 1024 colours. PNG output is written as an
+    /// indexed PNG when the result has at most 256 colours. Default: `false`.
+    pub palette_lock: bool,
+    /// Default: `png`.
+    pub output_format: OutputFormat,
+    /// JPEG quality, `1..=100`. Default: `90`.
+    pub jpeg_quality: u8,
+}
+
+impl Default for UpscaleOptions {
+    fn default() -> Self {
+        UpscaleOptions {
+            factor: 2.0,
+            method: UpscaleMethod::Enhanced,
+            kernel: InterpolationKernel::Auto,
+            pixel_art_scaler: PixelArtScaler::Xbr,
+            detect_grid: true,
+            post_processing: PostProcessSettings::default(),
+            line_art: LineArtSettings::default(),
+            neural: NeuralSettings::default(),
+            model: ModelSettings::default(),
+            self_ensemble: false,
+            palette_lock: false,
+            output_format: OutputFormat::Png,
+            jpeg_quality: 90,
+        }
+    }
+}
+
+impl UpscaleOptions {
+    /// Whether the factor shrinks the image
+    pub fn is_downscale(&self) -> bool {
+        self.factor < 1.0
+    }
+
+    /// The factor as a whole number, for upscaling. Downscales count as 1.
+    pub fn upscale_factor(&self) -> u32 {
+        self.factor.max(1.0) as u32
+    }
+
+    /// Size of the result for a `width` x `height` input. Downscaled sides
+    /// are rounded and at least 1 pixel.
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.is_downscale() {
+            let scale = |size: u32| ((size as f32 * self.factor).round() as u32).max(1);
+            (scale(width), scale(height))
+        } else {
+            let factor = self.upscale_factor();
+            (width * factor, height * factor)
+        }
+    }
+
+    /// Check the factor, JPEG quality, line-art, model and post-processing
+    /// ranges
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_DOWNSCALE_FACTOR..=8.0).contains(&self.factor)
+            || (self.factor > 1.0 && self.factor.fract() != 0.0)
+        {
+            return Err(format!(
+                "Invalid scale factor: {}. Must be a whole number between 1 and 8, or \
+                 between {} and 1 to downscale.",
+                self.factor, MIN_DOWNSCALE_FACTOR
+            ));
+        }
+        if self.is_downscale() {
+            if !matches!(
+                self.method,
+                UpscaleMethod::Standard | UpscaleMethod::Enhanced
+            ) {
+                return Err(format!(
+                    "Downscaling needs the standard or enhanced method, not {:?}",
+                    self.method
+                ));
+            }
+            if matches!(
+                self.kernel,
+                InterpolationKernel::EdgeDirected | InterpolationKernel::Fsr
+            ) {
+                return Err(format!(
+                    "The {:?} kernel only upscales; use area, mitchell or lanczos to downscale",
+                    self.kernel
+                ));
+            }
+        } else if self.kernel == InterpolationKernel::Area {
+            return Err("The area kernel only downscales".into());
+        }
+        if self.method == UpscaleMethod::PixelArt {
+            pixel_art::passes(self.pixel_art_scaler, self.upscale_factor())?;
+        }
+        if self.method == UpscaleMethod::Neural {
+            self.neural.validate()?;
+        }
+        if self.method == UpscaleMethod::Model {
+            self.model.validate()?;
+        }
+        if self.jpeg_quality == 0 || self.jpeg_quality > 100 {
+            return Err(format!(
+                "Invalid JPEG quality: {}. Must be between 1 and 100.",
+                self.jpeg_quality
+            ));
+        }
+        self.line_art.validate()?;
+        self.post_processing.validate()
+    }
+}
+
+/// What an upscale job found out about its input, and where its time went
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UpscaleReport {
+    /// Native pixel grid, when grid detection ran
+    pub grid: Option<PixelGrid>,
+    /// Number of source colours the output was locked to, with palette lock
+    pub palette_colors: Option<usize>,
+    /// Time of each stage, summed over self-ensemble passes and animation
+    /// frames
+    pub timings: StageTimings,
+}
+
+fn elapsed_ms(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Rectangle of the source image, in source pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// Check that the region is non-empty and inside a `width` x `height` image
+    pub fn validate(&self, width: u32, height: u32) -> Result<(), String> {
+        let inside = self
+            .x
+            .checked_add(self.width)
+            .is_some_and(|end| end <= width)
+            && self
+                .y
+                .checked_add(self.height)
+                .is_some_and(|end| end <= height);
+        if self.width == 0 || self.height == 0 || !inside {
+            return Err(format!(
+                "Invalid region {}x{} at ({}, {}) for a {}x{} image",
+                self.width, self.height, self.x, self.y, width, height
+            ));
+        }
+        Ok(())
+    }
+
+    /// This region grown by `margin` on every side, clipped to the image
+    fn expand(&self, margin: u32, width: u32, height: u32) -> Region {
+        let x = self.x.saturating_sub(margin);
+        let y = self.y.saturating_sub(margin);
+        Region {
+            x,
+            y,
+            width: (self.x + self.width + margin).min(width) - x,
+            height: (self.y + self.height + margin).min(height) - y,
+        }
+    }
+}
+
+/// Shrink pre-enlarged pixel art back to its native grid, if detection is on
+fn undo_pixel_grid(image: &RgbaImage, options: &UpscaleOptions) -> (RgbaImage, Option<PixelGrid>) {
+    if !options.detect_grid {
+        return (image.clone(), None);
+    }
+
+    let grid = pixel_art::detect_grid(image);
+    if !grid.is_scaled() {
+        return (image.clone(), Some(grid));
+    }
+
+    println!(
+        "🔍 Found {}x{} pixel blocks at offset ({}, {}); native size {}x{}",
+        grid.block_width,
+        grid.block_height,
+        grid.offset_x,
+        grid.offset_y,
+        grid.native_width,
+        grid.native_height
+    );
+    (pixel_art::downsample_to_grid(image, &grid), Some(grid))
+}
+
+/// What a job loads or measures once and shares between all the images it
+/// upscales: animation and sequence frames and self-ensemble passes
+#[derive(Default)]
+pub struct JobResources {
+    /// ONNX model of the model method
+    pub model: Option<OnnxModel>,
+    /// Contrast curve fitted to a whole sequence, replacing the per-frame
+    /// contrast step
+    pub shot_contrast: Option<ShotContrast>,
+    /// Temporal filter run after noise reduction on each frame of a
+    /// sequence, which has to see the frames in order
+    pub temporal_denoise: Option<RefCell<TemporalDenoise>>,
+}
+
+impl JobResources {
+    /// Load what `options` needs for images of `width` x `height`
+    pub fn load(
+        options: &UpscaleOptions,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let model = match options.method {
+            UpscaleMethod::Model => Some(model::load(&options.model, width, height)?),
+            _ => None,
+        };
+        Ok(JobResources {
+            model,
+            shot_contrast: None,
+            temporal_denoise: None,
+        })
+    }
+}
+
+/// Upscale an in-memory image. `context` must be provided when
+/// [`UpscaleMethod::uses_gpu`] is true.
+pub fn upscale(
+    context: Option<&VulkanContext>,
+    image: &RgbaImage,
+    options: &UpscaleOptions,
+) -> Result<(RgbaImage, UpscaleReport), Box<dyn std::error::Error>> {
+    options.validate()?;
+    let resources = JobResources::load(options, image.width(), image.height())?;
+    upscale_with_resources(context, image, options, &resources)
+}
+
+/// [`upscale`] with resources loaded once for the whole job
+pub fn upscale_with_resources(
+    context: Option<&VulkanContext>,
+    image: &RgbaImage,
+    options: &UpscaleOptions,
+    resources: &JobResources,
+) -> Result<(RgbaImage, UpscaleReport), Box<dyn std::error::Error>> {
+    options.validate()?;
+
+    if !options.palette_lock {
+        return enlarge_ensemble(context, image, options, resources);
+    }
+
+    let palette = Palette::extract(image, MAX_PALETTE_COLORS).ok_or_else(|| {
+        format!(
+            "Palette lock needs a source image with at most {} colours",
+            MAX_PALETTE_COLORS
+        )
+    })?;
+    let (upscaled, mut report) = enlarge_ensemble(context, image, options, resources)?;
+    println!("🎨 Locking output to {} source colours", palette.len());
+    let started = Instant::now();
+    let locked = palette.remap(&upscaled);
+    report.timings.post_processing_ms += elapsed_ms(started);
+    report.palette_colors = Some(palette.len());
+    Ok((locked, report))
+}
+
+/// One of the 8 symmetries of the square: a horizontal flip if `flip`,
+/// followed by `rotation` clockwise quarter turns
+#[derive(Debug, Clone, Copy)]
+struct Symmetry {
+    flip: bool,
+    rotation: u8,
+}
+
+impl Symmetry {
+    fn all() -> impl Iterator<Item = Symmetry> {
+        (0..8).map(|i| Symmetry {
+            flip: i >= 4,
+            rotation: i % 4,
+        })
+    }
+
+    fn rotate(image: &RgbaImage, quarter_turns: u8) -> RgbaImage {
+        match quarter_turns % 4 {
+            1 => imageops::rotate90(image),
+            2 => imageops::rotate180(image),
+            3 => imageops::rotate270(image),
+            _ => image.clone(),
+        }
+    }
+
+    fn apply(self, image: &RgbaImage) -> RgbaImage {
+        if self.flip {
+            Self::rotate(&imageops::flip_horizontal(image), self.rotation)
+        } else {
+            Self::rotate(image, self.rotation)
+        }
+    }
+
+    fn invert(self, image: &RgbaImage) -> RgbaImage {
+        let image = Self::rotate(image, 4 - self.rotation);
+        if self.flip {
+            imageops::flip_horizontal(&image)
+        } else {
+            image
+        }
+    }
+}
+
+/// Run the upscale method, averaged over all 8 symmetries of the input when
+/// `self_ensemble` is set
+fn enlarge_ensemble(
+    context: Option<&VulkanContext>,
+    image: &RgbaImage,
+    options: &UpscaleOptions,
+    resources: &JobResources,
+) -> Result<(RgbaImage, UpscaleReport), Box<dyn std::error::Error>> {
+    if !options.self_ensemble {
+        return enlarge(context, image, options, resources);
+    }
+    // The CLAHE tiles are fixed to the frame's orientation
+    if let Some(ShotContrast::Clahe { .. }) = resources.shot_contrast {
+        return Err("Shot-wide CLAHE can't be combined with self-ensemble".into());
+    }
+    // The passes would be blended with each other instead of with the
+    // previous frame
+    if resources.temporal_denoise.is_some() {
+        return Err("Temporal denoise can't be combined with self-ensemble".into());
+    }
+
+    let mut sum: Vec<u32> = Vec::new();
+    let mut first = None;
+    let mut timings = StageTimings::default();
+    for (index, symmetry) in Symmetry::all().enumerate() {
+        println!(
+            "🔁 Self-ensemble pass {}/8 (flip: {}, rotation: {}°)",
+            index + 1,
+            symmetry.flip,
+            symmetry.rotation as u32 * 90
+        );
+        let (upscaled, report) = enlarge(context, &symmetry.apply(image), options, resources)?;
+        let upscaled = symmetry.invert(&upscaled);
+        timings.add(&report.timings);
+
+        // The identity pass comes first; its report describes the input as given
+        match &first {
+            Some((size, _)) if upscaled.dimensions() != *size => {
+                return Err(format!(
+                    "Self-ensemble pass {} produced {}x{} instead of {}x{}",
+                    index + 1,
+                    upscaled.width(),
+                    upscaled.height(),
+                    size.0,
+                    size.1
+                )
+                .into());
+            }
+            Some(_) => {}
+            None => first = Some((upscaled.dimensions(), report)),
+        }
+
+        if sum.is_empty() {
+            sum = vec![0; upscaled.as_raw().len()];
+        }
+        for (total, &value) in sum.iter_mut().zip(upscaled.as_raw()) {
+            *total += value as u32;
+        }
+    }
+
+    let ((width, height), mut report) = first.ok_or("Self-ensemble ran no passes")?;
+    report.timings = timings;
+    let data = sum.iter().map(|&total| ((total + 4) / 8) as u8).collect();
+    let averaged = RgbaImage::from_raw(width, height, data).ok_or("Invalid upscaled image")?;
+    Ok((averaged, report))
+}
+
+/// Run the upscale method itself
+fn enlarge(
+    context: Option<&VulkanContext>,
+    image: &RgbaImage,
+    options: &UpscaleOptions,
+    resources: &JobResources,
+) -> Result<(RgbaImage, UpscaleReport), Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    match options.method {
+        UpscaleMethod::NearestNeighbor => {
+            let (native, grid) = undo_pixel_grid(image, options);
+            let (width, height) = native.dimensions();
+            let upscaled = image::imageops::resize(
+                &native,
+                width * options.upscale_factor(),
+                height * options.upscale_factor(),
+                FilterType::Nearest,
+            );
+            return Ok((
+                upscaled,
+                UpscaleReport {
+                    grid,
+                    timings: StageTimings {
+                        dispatch_ms: elapsed_ms(started),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            ));
+        }
+        UpscaleMethod::PixelArt => {
+            let (native, grid) = undo_pixel_grid(image, options);
+            let upscaled =
+                pixel_art::upscale(&native, options.upscale_factor(), options.pixel_art_scaler)?;
+            return Ok((
+                upscaled,
+                UpscaleReport {
+                    grid,
+                    timings: StageTimings {
+                        dispatch_ms: elapsed_ms(started),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            ));
+        }
+        UpscaleMethod::Standard
+        | UpscaleMethod::Enhanced
+        | UpscaleMethod::LineArt
+        | UpscaleMethod::Neural
+        | UpscaleMethod::Model => {}
+    }
+
+    let (width, height) = image.dimensions();
+    let (output_width, output_height) = options.output_size(width, height);
+
+    let context = context.ok_or("This upscale method requires a Vulkan context")?;
+
+    let mut timings = StageTimings::default();
+    let mut data = match options.method {
+        UpscaleMethod::Neural => {
+            let upscaled = neural::upscale_gpu(context, image, &options.neural)?;
+            let data = fit_to(upscaled, output_width, output_height).into_raw();
+            timings.dispatch_ms = elapsed_ms(started);
+            data
+        }
+        UpscaleMethod::Model => {
+            let model = resources
+                .model
+                .as_ref()
+                .ok_or("The model method needs a loaded model")?;
+            let upscaled = model::upscale_tiled(model, image, options.model.tile_overlap)?;
+            let data = fit_to(upscaled, output_width, output_height).into_raw();
+            timings.dispatch_ms = elapsed_ms(started);
+            data
+        }
+        _ if options.is_downscale() => {
+            println!(
+                "📉 Downscaling {}x{} to {}x{} ({:?} kernel)",
+                width, height, output_width, output_height, options.kernel
+            );
+            let data = downscale::downscale_gpu(
+                context,
+                image.as_raw(),
+                width,
+                height,
+                output_width,
+                output_height,
+                options.kernel,
+            )?;
+            timings.dispatch_ms = elapsed_ms(started);
+            data
+        }
+        _ => vulkan::upscale_rgba_timed(
+            context,
+            image.as_raw(),
+            width,
+            height,
+            options.upscale_factor(),
+            options.kernel,
+            &mut timings,
+        )?,
+    };
+
+    let post_started = Instant::now();
+    match options.method {
+        UpscaleMethod::Enhanced | UpscaleMethod::Model => {
+            let mut temporal = resources.temporal_denoise.as_ref().map(RefCell::borrow_mut);
+            data = post_processing::process_shot_frame(
+                context,
+                &data,
+                output_width,
+                output_height,
+                options.upscale_factor(),
+                &options.post_processing,
+                resources.shot_contrast.as_ref(),
+                temporal.as_deref_mut(),
+            )?;
+        }
+        UpscaleMethod::LineArt => {
+            println!(
+                "✏️  Refining line art ({} passes)...",
+                options.line_art.passes
+            );
+            data = line_art::process_gpu(
+                context,
+                &data,
+                output_width,
+                output_height,
+                &options.line_art,
+            )?;
+        }
+        _ => {}
+    }
+    timings.post_processing_ms = elapsed_ms(post_started);
+
+    let upscaled =
+        RgbaImage::from_raw(output_width, output_height, data).ok_or("Invalid upscaled image")?;
+    Ok((
+        upscaled,
+        UpscaleReport {
+            timings,
+            ..Default::default()
+        },
+    ))
+}
+
+/// Resample a model's output to the requested size. Models have a fixed
+/// scale, which differs from the factor when e.g. a 4x model is used for 2x.
+fn fit_to(image: RgbaImage, width: u32, height: u32) -> RgbaImage {
+    if image.dimensions() == (width, height) {
+        return image;
+    }
+    println!(
+        "📐 Resampling model output {}x{} to {}x{}",
+        image.width(),
+        image.height(),
+        width,
+        height
+    );
+    image::imageops::resize(&image, width, height, FilterType::Lanczos3)
+}
+
+/// Source pixels around a region that can change the region's output. Every
+/// filter clamps reads at the image border, so outside this margin a crop
+/// and the full image give the same result.
+fn region_margin(options: &UpscaleOptions) -> Result<u32, String> {
+    let factor = options.upscale_factor();
+    let upscale = match options.method {
+        UpscaleMethod::NearestNeighbor => 0,
+        // Each pass reads up to 2 pixels away in its own input
+        UpscaleMethod::PixelArt => {
+            2 * pixel_art::passes(options.pixel_art_scaler, factor)?.len() as u32
+        }
+        // 4x4 taps, edge-directed adds its gradient window and EASU its 12 taps
+        UpscaleMethod::Standard | UpscaleMethod::Enhanced | UpscaleMethod::LineArt => 4,
+        UpscaleMethod::Neural | UpscaleMethod::Model => {
+            return Err(format!(
+                "Regions of {:?} upscales can't match the full image: the model's tiles \
+                 depend on the image size",
+                options.method
+            ))
+        }
+    };
+
+    // Radius of the filters that run on the upscaled image, in output pixels
+    let settings = &options.post_processing;
+    let mut post = 0;
+    if matches!(options.method, UpscaleMethod::Enhanced) {
+        if settings.noise_reduction {
+            post += match settings.denoise_mode {
+                DenoiseMode::Classic => (settings.denoise_strength * 2.0) as u32,
+                DenoiseMode::Bilateral => (settings.bilateral_sigma_spatial * 2.0).ceil() as u32,
+                DenoiseMode::NonLocalMeans => {
+                    settings.nlm_search_radius + settings.nlm_patch_radius
+                }
+            };
+        }
+        if settings.contrast_enhancement && settings.contrast_mode != ContrastMode::Classic {
+            return Err(format!(
+                "Regions can't match the full image with {:?} contrast, which depends on \
+                 the whole image",
+                settings.contrast_mode
+            ));
+        }
+        if settings.sharpening {
+            post += match settings.sharpen_mode {
+                SharpenMode::UnsharpMask => {
+                    (settings.effective_unsharp_radius(factor) * 3.0).ceil() as u32
+                }
+                SharpenMode::Classic | SharpenMode::Cas | SharpenMode::Rcas => 1,
+            };
+        }
+    }
+    if options.method == UpscaleMethod::LineArt {
+        // Thinning, gradient and refinement each read a 3x3 window
+        post += 3 * options.line_art.passes;
+    }
+
+    Ok(upscale + post.div_ceil(factor))
+}
+
+/// Upscale only `region` of `image`. The result matches the same rectangle of
+/// [`upscale`] on the whole image pixel for pixel: the region is padded with
+/// enough source context for every filter, and palette lock and grid
+/// detection still look at the whole image.
+pub fn upscale_region(
+    context: Option<&VulkanContext>,
+    image: &RgbaImage,
+    region: &Region,
+    options: &UpscaleOptions,
+) -> Result<(RgbaImage, UpscaleReport), Box<dyn std::error::Error>> {
+    options.validate()?;
+    let (width, height) = image.dimensions();
+    region.validate(width, height)?;
+    if options.is_downscale() {
+        return Err("Regions can only be taken from upscales (factor 1 or more)".into());
+    }
+
+    let mut crop_options = options.clone();
+    crop_options.palette_lock = false;
+    crop_options.detect_grid = false;
+    let mut report = UpscaleReport::default();
+    if options.detect_grid
+        && matches!(
+            options.method,
+            UpscaleMethod::NearestNeighbor | UpscaleMethod::PixelArt
+        )
+    {
+        let grid = pixel_art::detect_grid(image);
+        if grid.is_scaled() {
+            return Err(format!(
+                "The image is already enlarged {}x{}; upscale a region with grid detection \
+                 off, or upscale the whole image",
+                grid.block_width, grid.block_height
+            )
+            .into());
+        }
+        report.grid = Some(grid);
+    }
+
+    let padded = region.expand(region_margin(options)?, width, height);
+    println!(
+        "✂️  Upscaling region {}x{} at ({}, {}) with context {}x{} at ({}, {})",
+        region.width,
+        region.height,
+        region.x,
+        region.y,
+        padded.width,
+        padded.height,
+        padded.x,
+        padded.y
+    );
+    let crop =
+        imageops::crop_imm(image, padded.x, padded.y, padded.width, padded.height).to_image();
+    let resources = JobResources::load(&crop_options, crop.width(), crop.height())?;
+    let (upscaled, crop_report) = enlarge_ensemble(context, &crop, &crop_options, &resources)?;
+    report.timings = crop_report.timings;
+
+    let factor = options.upscale_factor();
+    let upscaled = imageops::crop_imm(
+        &upscaled,
+        (region.x - padded.x) * factor,
+        (region.y - padded.y) * factor,
+        region.width * factor,
+        region.height * factor,
+    )
+    .to_image();
+
+    if !options.palette_lock {
+        return Ok((upscaled, report));
+    }
+    let palette = Palette::extract(image, MAX_PALETTE_COLORS).ok_or_else(|| {
+        format!(
+            "Palette lock needs a source image with at most {} colours",
+            MAX_PALETTE_COLORS
+        )
+    })?;
+    let started = Instant::now();
+    let locked = palette.remap(&upscaled);
+    report.timings.post_processing_ms += elapsed_ms(started);
+    report.palette_colors = Some(palette.len());
+    Ok((locked, report))
+}
+
+/// Encode `image` to `path` in the given format
+pub fn save_image(
+    image: &RgbaImage,
+    path: &str,
+    format: OutputFormat,
+    jpeg_quality: u8,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        OutputFormat::Png => image.save_with_format(path, image::ImageFormat::Png)?,
+        OutputFormat::Webp => image.save_with_format(path, image::ImageFormat::WebP)?,
+        OutputFormat::Jpeg => {
+            // JPEG has no alpha channel
+            let rgb = image::DynamicImage::ImageRgba8(image.clone()).to_rgb8();
+            let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            image::codecs::jpeg::JpegEncoder::new_with_quality(file, jpeg_quality)
+                .encode_image(&rgb)?;
+        }
+    }
+    Ok(())
+}
+
+/// Upscale every frame of `animation` (or only `region` of it), keeping the
+/// delays and loop count. The report is the first frame's, with the timings
+/// of all frames.
+pub fn upscale_animation(
+    context: Option<&VulkanContext>,
+    animation: &Animation,
+    region: Option<&Region>,
+    options: &UpscaleOptions,
+) -> Result<(Animation, UpscaleReport), Box<dyn std::error::Error>> {
+    let mut frames = Vec::with_capacity(animation.frames.len());
+    let mut report = None;
+    let mut timings = StageTimings::default();
+    // Frames are composited onto the full canvas, so they share one size
+    let resources = match (region, animation.frames.first()) {
+        (None, Some(frame)) => {
+            options.validate()?;
+            JobResources::load(options, frame.image.width(), frame.image.height())?
+        }
+        _ => JobResources::default(),
+    };
+    for (index, frame) in animation.frames.iter().enumerate() {
+        println!("🎞️  Frame {}/{}", index + 1, animation.frames.len());
+        let (image, frame_report) = match region {
+            Some(region) => upscale_region(context, &frame.image, region, options)?,
+            None => upscale_with_resources(context, &frame.image, options, &resources)?,
+        };
+        timings.add(&frame_report.timings);
+        report.get_or_insert(frame_report);
+        frames.push(AnimationFrame {
+            image,
+            delay: frame.delay,
+        });
+    }
+
+    let animation = Animation {
+        format: animation.format,
+        frames,
+        plays: animation.plays,
+    };
+    let mut report = report.unwrap_or_default();
+    report.timings = timings;
+    Ok((animation, report))
+}
+
+/// Extension of the file [`upscale_file`] writes for `input_path`: the
+/// input's own for animations, otherwise that of `options.output_format`
+pub fn output_extension(input_path: &str, options: &UpscaleOptions) -> &'static str {
+    match animation::detect(input_path) {
+        Ok(Some(format)) => format.extension(),
+        _ => options.output_format.extension(),
+    }
+}
+
+/// Load `input_path`, upscale it (or only `region` of it) with `options` and
+/// write the result to `output_path` in `options.output_format`. Animations
+/// are written in their own format instead; see [`output_extension`].
+pub fn upscale_file(
+    context: Option<&VulkanContext>,
+    input_path: &str,
+    output_path: &str,
+    region: Option<&Region>,
+    options: &UpscaleOptions,
+) -> Result<UpscaleReport, Box<dyn std::error::Error>> {
+    println!(
+        "Processing {} -> {} ({:?}, {}x)",
+        input_path, output_path, options.method, options.factor
+    );
+
+    let started = Instant::now();
+    if let Some(animation) = animation::load(input_path)? {
+        let decode_ms = elapsed_ms(started);
+        println!(
+            "🎞️  Animated {:?} with {} frames",
+            animation.format,
+            animation.frames.len()
+        );
+        let (upscaled, mut report) = upscale_animation(context, &animation, region, options)?;
+        let started = Instant::now();
+        upscaled
+            .save(output_path)
+            .map_err(|e| format!("Failed to save output animation: {}", e))?;
+        report.timings.decode_ms = decode_ms;
+        report.timings.encode_ms = elapsed_ms(started);
+        println!("✓ Saved {}", output_path);
+        return Ok(report);
+    }
+
+    let input_image = image::open(input_path)?.to_rgba8();
+    let decode_ms = elapsed_ms(started);
+    let (upscaled, mut report) = match region {
+        Some(region) => upscale_region(context, &input_image, region, options)?,
+        None => upscale(context, &input_image, options)?,
+    };
+
+    // A palette-locked PNG is written indexed when its colours fit
+    let started = Instant::now();
+    let indexed = options.palette_lock
+        && options.output_format == OutputFormat::Png
+        && palette::save_indexed_png(&upscaled, output_path)
+            .map_err(|e| format!("Failed to save output image: {}", e))?;
+    if indexed {
+        println!("🎨 Wrote indexed PNG");
+    } else {
+        save_image(
+            &upscaled,
+            output_path,
+            options.output_format,
+            options.jpeg_quality,
+        )
+        .map_err(|e| format!("Failed to save output image: {}", e))?;
+    }
+
+    report.timings.decode_ms = decode_ms;
+    report.timings.encode_ms = elapsed_ms(started);
+
+    println!("✓ Saved {}", output_path);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 13x9 image of pseudo-random colours with a few hard edges, so no
+    /// pixel grid is detected
+    fn test_image() -> RgbaImage {
+        let mut seed = 0x9e37_79b9u32;
+        RgbaImage::from_fn(13, 9, |x, y| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let edge = if x > y { 200 } else { 30 };
+            image::Rgba([
+                edge,
+                seed as u8,
+                (seed >> 8) as u8 / 2 + edge / 2,
+                (seed >> 16) as u8 | 0x80,
+            ])
+        })
+    }
+
+    /// Regions touching each border and corner, and one in the interior
+    const REGIONS: [Region; 4] = [
+        Region {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 3,
+        },
+        Region {
+            x: 9,
+            y: 6,
+            width: 4,
+            height: 3,
+        },
+        Region {
+            x: 0,
+            y: 4,
+            width: 13,
+            height: 2,
+        },
+        Region {
+            x: 4,
+            y: 3,
+            width: 5,
+            height: 3,
+        },
+    ];
+
+    fn crop(image: &RgbaImage, region: &Region, factor: u32) -> RgbaImage {
+        imageops::crop_imm(
+            image,
+            region.x * factor,
+            region.y * factor,
+            region.width * factor,
+            region.height * factor,
+        )
+        .to_image()
+    }
+
+    #[test]
+    fn regions_match_the_full_upscale() {
+        let image = test_image();
+        let cases = [
+            UpscaleOptions {
+                factor: 4.0,
+                method: UpscaleMethod::PixelArt,
+                pixel_art_scaler: PixelArtScaler::Xbr,
+                ..Default::default()
+            },
+            UpscaleOptions {
+                factor: 3.0,
+                method: UpscaleMethod::NearestNeighbor,
+                ..Default::default()
+            },
+        ];
+        for options in &cases {
+            let (full, _) = upscale(None, &image, options).unwrap();
+            let factor = options.upscale_factor();
+            for region in &REGIONS {
+                let (upscaled, _) = upscale_region(None, &image, region, options).unwrap();
+                assert!(
+                    upscaled == crop(&full, region, factor),
+                    "{:?} differs in {:?}",
+                    options.method,
+                    region
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn region_margin_covers_the_post_processing_radius() {
+        // The enhanced method needs a GPU, so stand in for it with a nearest
+        // upscale followed by the CPU unsharp mask the margin accounts for
+        let options = UpscaleOptions {
+            factor: 2.0,
+            method: UpscaleMethod::Enhanced,
+            post_processing: PostProcessSettings {
+                sharpen_mode: SharpenMode::UnsharpMask,
+                unsharp_radius: 2.5,
+                unsharp_threshold: 0.0,
+                contrast_enhancement: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let settings = &options.post_processing;
+        let factor = options.upscale_factor();
+        let radius = settings.effective_unsharp_radius(factor);
+        let enhance = |image: &RgbaImage| {
+            let (width, height) = (image.width() * factor, image.height() * factor);
+            let upscaled = imageops::resize(image, width, height, FilterType::Nearest);
+            let data = post_processing::unsharp_mask(
+                upscaled.as_raw(),
+                width,
+                height,
+                radius,
+                settings.unsharp_amount,
+                settings.unsharp_threshold,
+            )
+            .unwrap();
+            RgbaImage::from_raw(width, height, data).unwrap()
+        };
+
+        let image = test_image();
+        let full = enhance(&image);
+        let margin = region_margin(&options).unwrap();
+        for region in &REGIONS {
+            let padded = region.expand(margin, image.width(), image.height());
+            let context =
+                imageops::crop_imm(&image, padded.x, padded.y, padded.width, padded.height);
+            let offset = Region {
+                x: region.x - padded.x,
+                y: region.y - padded.y,
+                ..*region
+            };
+            let upscaled = crop(&enhance(&context.to_image()), &offset, factor);
+            assert!(
+                upscaled == crop(&full, region, factor),
+                "differs in {:?}",
+                region
+            );
+        }
+    }
+
+    #[test]
+    fn symmetries_invert_on_non_square_images() {
+        let image = test_image();
+        for symmetry in Symmetry::all() {
+            let transformed = symmetry.apply(&image);
+            if symmetry.rotation % 2 == 1 {
+                assert_eq!(transformed.dimensions(), (9, 13));
+            }
+            assert!(symmetry.invert(&transformed) == image, "{:?}", symmetry);
+        }
+    }
+
+    #[test]
+    fn ensemble_of_nearest_neighbor_equals_a_single_pass() {
+        let image = test_image();
+        let options = UpscaleOptions {
+            factor: 3.0,
+            method: UpscaleMethod::NearestNeighbor,
+            detect_grid: false,
+            ..Default::default()
+        };
+        let ensemble = UpscaleOptions {
+            self_ensemble: true,
+            ..options.clone()
+        };
+        let (single, _) = upscale(None, &image, &options).unwrap();
+        let (averaged, _) = upscale(None, &image, &ensemble).unwrap();
+        assert!(averaged == single);
+    }
+}
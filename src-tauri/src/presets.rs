@@ -0,0 +1,282 @@
+//! Named processing presets
+//!
+//! A preset is a name plus the [`UpscaleOptions`] of a job. A few presets are
+//! built in; user presets are stored as JSON in `presets.json` under the app
+//! data directory. Built-in presets can't be overwritten or deleted.
+
+use crate::pipeline::{OutputFormat, UpscaleMethod, UpscaleOptions};
+use crate::vulkan::{
+    ContrastMode, DenoiseMode, InterpolationKernel, PostProcessSettings, SharpenMode,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// File in the app data directory holding the user presets
+const PRESETS_FILE: &str = "presets.json";
+
+/// Tauri bundle identifier, used to locate the app data directory outside the app
+const APP_IDENTIFIER: &str = "com.v-upscale.upscaler";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    /// Set for presets that ship with the app
+    #[serde(default, skip_deserializing)]
+    pub builtin: bool,
+    #[serde(flatten)]
+    pub options: UpscaleOptions,
+}
+
+/// Presets that ship with the app
+pub fn builtin_presets() -> Vec<Preset> {
+    let pixel_art = UpscaleOptions {
+        factor: 4.0,
+        method: UpscaleMethod::NearestNeighbor,
+        ..Default::default()
+    };
+
+    let old_photo = UpscaleOptions {
+        factor: 2.0,
+        method: UpscaleMethod::Enhanced,
+        kernel: InterpolationKernel::Mitchell,
+        post_processing: PostProcessSettings {
+            noise_reduction: true,
+            denoise_mode: DenoiseMode::NonLocalMeans,
+            nlm_strength: 12.0,
+            contrast_mode: ContrastMode::AutoLevels,
+            levels_clip_percent: 1.0,
+            sharpen_mode: SharpenMode::UnsharpMask,
+            unsharp_amount: 0.6,
+            unsharp_threshold: 4.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let web_jpeg = UpscaleOptions {
+        factor: 2.0,
+        method: UpscaleMethod::Enhanced,
+        post_processing: PostProcessSettings {
+            contrast_enhancement: false,
+            sharpen_mode: SharpenMode::UnsharpMask,
+            unsharp_amount: 0.5,
+            ..Default::default()
+        },
+        output_format: OutputFormat::Jpeg,
+        jpeg_quality: 85,
+        ..Default::default()
+    };
+
+    let anime = UpscaleOptions {
+        factor: 2.0,
+        method: UpscaleMethod::LineArt,
+        kernel: InterpolationKernel::Mitchell,
+        ..Default::default()
+    };
+
+    [
+        ("Pixel art 4x", pixel_art),
+        ("Anime 2x", anime),
+        ("Old photo restore", old_photo),
+        ("Web 2x JPEG", web_jpeg),
+    ]
+    .into_iter()
+    .map(|(name, options)| Preset {
+        name: name.to_string(),
+        builtin: true,
+        options,
+    })
+    .collect()
+}
+
+/// The app data directory Tauri uses for this app, for callers without an
+/// `AppHandle` such as the CLI
+pub fn default_app_data_dir() -> Option<PathBuf> {
+    let data_dir = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+    };
+    data_dir.map(|dir| dir.join(APP_IDENTIFIER))
+}
+
+fn load_user_presets(app_data_dir: &Path) -> Result<Vec<Preset>, String> {
+    let path = app_data_dir.join(PRESETS_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let json = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&json).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+fn write_user_presets(app_data_dir: &Path, presets: &[Preset]) -> Result<(), String> {
+    std::fs::create_dir_all(app_data_dir)
+        .map_err(|e| format!("Failed to create {}: {}", app_data_dir.display(), e))?;
+
+    let json = serde_json::to_string_pretty(presets)
+        .map_err(|e| format!("Failed to serialize presets: {}", e))?;
+
+    // Write to a temporary file first so a failed write can't truncate the presets
+    let path = app_data_dir.join(PRESETS_FILE);
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, json)
+        .map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+    std::fs::rename(&temp_path, &path)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn is_builtin(name: &str) -> bool {
+    builtin_presets()
+        .iter()
+        .any(|preset| preset.name.eq_ignore_ascii_case(name))
+}
+
+/// Built-in presets followed by the user presets
+pub fn list_presets(app_data_dir: &Path) -> Result<Vec<Preset>, String> {
+    let mut presets = builtin_presets();
+    presets.extend(load_user_presets(app_data_dir)?);
+    Ok(presets)
+}
+
+/// Look up a preset by name, ignoring ASCII case
+pub fn find_preset(app_data_dir: &Path, name: &str) -> Result<Preset, String> {
+    list_presets(app_data_dir)?
+        .into_iter()
+        .find(|preset| preset.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("Unknown preset: {}", name))
+}
+
+/// Add a user preset, replacing any user preset with the same name
+pub fn save_preset(app_data_dir: &Path, mut preset: Preset) -> Result<(), String> {
+    preset.name = preset.name.trim().to_string();
+    if preset.name.is_empty() {
+        return Err("Preset name cannot be empty".to_string());
+    }
+    if is_builtin(&preset.name) {
+        return Err(format!("Cannot overwrite built-in preset: {}", preset.name));
+    }
+    preset
+        .options
+        .validate()
+        .map_err(|e| format!("Invalid preset {}: {}", preset.name, e))?;
+    preset.builtin = false;
+
+    let mut presets = load_user_presets(app_data_dir)?;
+    match presets
+        .iter_mut()
+        .find(|existing| existing.name.eq_ignore_ascii_case(&preset.name))
+    {
+        Some(existing) => *existing = preset,
+        None => presets.push(preset),
+    }
+    write_user_presets(app_data_dir, &presets)
+}
+
+/// Remove a user preset
+pub fn delete_preset(app_data_dir: &Path, name: &str) -> Result<(), String> {
+    if is_builtin(name) {
+        return Err(format!("Cannot delete built-in preset: {}", name));
+    }
+
+    let mut presets = load_user_presets(app_data_dir)?;
+    let count = presets.len();
+    presets.retain(|preset| !preset.name.eq_ignore_ascii_case(name));
+    if presets.len() == count {
+        return Err(format!("Unknown preset: {}", name));
+    }
+    write_user_presets(app_data_dir, &presets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory in the temp directory that no other test uses
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("v-upscale-presets-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn preset(name: &str, factor: f32) -> Preset {
+        Preset {
+            name: name.to_string(),
+            builtin: false,
+            options: UpscaleOptions {
+                factor,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn user_presets(dir: &Path) -> Vec<(String, f32)> {
+        list_presets(dir)
+            .unwrap()
+            .into_iter()
+            .filter(|preset| !preset.builtin)
+            .map(|preset| (preset.name, preset.options.factor))
+            .collect()
+    }
+
+    #[test]
+    fn builtin_presets_are_protected() {
+        let dir = temp_dir("builtin");
+        for name in ["Pixel art 4x", "pixel ART 4X", "  Anime 2x  "] {
+            assert!(save_preset(&dir, preset(name, 3.0)).is_err(), "{}", name);
+            assert!(delete_preset(&dir, name.trim()).is_err(), "{}", name);
+        }
+        assert!(user_presets(&dir).is_empty());
+        assert_eq!(
+            find_preset(&dir, "pixel art 4x").unwrap().options.factor,
+            4.0
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn saving_an_existing_name_replaces_it() {
+        let dir = temp_dir("replace");
+        save_preset(&dir, preset("Mine", 2.0)).unwrap();
+        save_preset(&dir, preset("Other", 4.0)).unwrap();
+        save_preset(&dir, preset(" MINE ", 3.0)).unwrap();
+        assert_eq!(
+            user_presets(&dir),
+            [("MINE".to_string(), 3.0), ("Other".to_string(), 4.0)]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn invalid_presets_are_rejected() {
+        let dir = temp_dir("invalid");
+        assert!(save_preset(&dir, preset("Too big", 9.0)).is_err());
+        assert!(save_preset(&dir, preset("   ", 2.0)).is_err());
+        assert!(user_presets(&dir).is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn deleting_removes_only_that_preset() {
+        let dir = temp_dir("delete");
+        save_preset(&dir, preset("Keep", 2.0)).unwrap();
+        save_preset(&dir, preset("Drop", 3.0)).unwrap();
+        delete_preset(&dir, "drop").unwrap();
+        assert_eq!(user_presets(&dir), [("Keep".to_string(), 2.0)]);
+
+        let error = delete_preset(&dir, "Drop").unwrap_err();
+        assert!(error.contains("Unknown preset"), "{}", error);
+        assert!(delete_preset(&dir, "Never saved").is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,215 @@
+//! Interactive previews
+//!
+//! A preview runs the normal pipeline on either a viewport-sized region of the
+//! source or a reduced proxy of the whole image, and hands the encoded PNG back
+//! in memory instead of writing a file. The decoded source and its last proxy
+//! are cached between calls, so moving a slider only pays for the upscale.
+
+use crate::pipeline::{self, Region, UpscaleMethod, UpscaleOptions};
+use crate::vulkan::{downscale, InterpolationKernel, VulkanContext};
+use image::codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder};
+use image::{imageops, ImageEncoder, RgbaImage};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Default longest side of a proxy preview's output, in pixels
+pub const DEFAULT_PREVIEW_SIZE: u32 = 1024;
+
+/// Range accepted for the longest side of a proxy preview's output
+const PREVIEW_SIZE_RANGE: std::ops::RangeInclusive<u32> = 64..=4096;
+
+/// State kept between preview calls
+#[derive(Default)]
+pub struct PreviewCache {
+    pub source: Mutex<Option<PreviewSource>>,
+    /// Created by the first preview that needs the GPU
+    pub context: Mutex<Option<VulkanContext>>,
+}
+
+/// Decoded source image, plus the proxy made from it last
+pub struct PreviewSource {
+    path: String,
+    modified: Option<SystemTime>,
+    image: RgbaImage,
+    /// Last proxy and whether it was made with nearest-neighbour sampling
+    proxy: Option<(bool, RgbaImage)>,
+}
+
+impl PreviewSource {
+    /// Decode `path`, or keep `cached` if it holds the same unmodified file
+    pub fn load(cached: Option<PreviewSource>, path: &str) -> Result<Self, String> {
+        let modified = std::fs::metadata(path)
+            .map_err(|e| format!("Input file does not exist: {} ({})", path, e))?
+            .modified()
+            .ok();
+        if let Some(source) = cached {
+            if source.path == path && source.modified == modified {
+                return Ok(source);
+            }
+        }
+
+        println!("📂 Decoding preview source: {}", path);
+        let image = image::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path, e))?
+            .to_rgba8();
+        Ok(PreviewSource {
+            path: path.to_string(),
+            modified,
+            image,
+            proxy: None,
+        })
+    }
+
+    /// The source reduced so its upscale fits `max_size`, or the source itself
+    /// when it already fits
+    fn proxy(&mut self, options: &UpscaleOptions, max_size: u32) -> Result<&RgbaImage, String> {
+        let (width, height) = self.image.dimensions();
+        let output = options.output_size(width, height);
+        let scale = (max_size as f32 / output.0.max(output.1) as f32).min(1.0);
+        if scale >= 1.0 {
+            return Ok(&self.image);
+        }
+
+        let size = (
+            ((width as f32 * scale).round() as u32).max(1),
+            ((height as f32 * scale).round() as u32).max(1),
+        );
+        // Averaging would invent colours that pixel art and palette lock can't
+        // handle, so those keep the nearest source pixel instead
+        let nearest = options.palette_lock
+            || matches!(
+                options.method,
+                UpscaleMethod::NearestNeighbor | UpscaleMethod::PixelArt
+            );
+        let cached = self
+            .proxy
+            .as_ref()
+            .map(|(cached_nearest, proxy)| (*cached_nearest, proxy.dimensions()));
+        if cached != Some((nearest, size)) {
+            println!(
+                "📉 Preview proxy {}x{} of {}x{}",
+                size.0, size.1, width, height
+            );
+            let proxy = if nearest {
+                imageops::resize(&self.image, size.0, size.1, imageops::FilterType::Nearest)
+            } else {
+                let data = downscale::downscale(
+                    self.image.as_raw(),
+                    width,
+                    height,
+                    size.0,
+                    size.1,
+                    InterpolationKernel::Area,
+                )?;
+                RgbaImage::from_raw(size.0, size.1, data).ok_or("Invalid preview proxy")?
+            };
+            self.proxy = Some((nearest, proxy));
+        }
+        Ok(self.proxy.as_ref().map_or(&self.image, |(_, proxy)| proxy))
+    }
+}
+
+/// Upscale `region` of the source, or a proxy whose result fits in
+/// `max_size` x `max_size`, and return it encoded as PNG. A region's result
+/// may not be larger than the largest `max_size`.
+pub fn render(
+    context: Option<&VulkanContext>,
+    source: &mut PreviewSource,
+    options: &UpscaleOptions,
+    region: Option<&Region>,
+    max_size: u32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if !PREVIEW_SIZE_RANGE.contains(&max_size) {
+        return Err(format!(
+            "Invalid preview size: {}. Must be between {} and {}.",
+            max_size,
+            PREVIEW_SIZE_RANGE.start(),
+            PREVIEW_SIZE_RANGE.end()
+        )
+        .into());
+    }
+
+    if let Some(region) = region {
+        let (width, height) = options.output_size(region.width, region.height);
+        if width.max(height) > *PREVIEW_SIZE_RANGE.end() {
+            return Err(format!(
+                "Preview region would be {}x{}; previews can be at most {} pixels on a side",
+                width,
+                height,
+                PREVIEW_SIZE_RANGE.end()
+            )
+            .into());
+        }
+    }
+
+    let started = std::time::Instant::now();
+    let (preview, _) = match region {
+        Some(region) => pipeline::upscale_region(context, &source.image, region, options)?,
+        None => pipeline::upscale(context, source.proxy(options, max_size)?, options)?,
+    };
+
+    let mut png = Vec::new();
+    PngEncoder::new_with_quality(&mut png, CompressionType::Fast, PngFilter::Sub).write_image(
+        preview.as_raw(),
+        preview.width(),
+        preview.height(),
+        image::ExtendedColorType::Rgba8,
+    )?;
+    println!(
+        "👁️  Preview {}x{} ({} KiB) in {} ms",
+        preview.width(),
+        preview.height(),
+        png.len() / 1024,
+        started.elapsed().as_millis()
+    );
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(width: u32, height: u32) -> PreviewSource {
+        PreviewSource {
+            path: String::new(),
+            modified: None,
+            image: RgbaImage::from_pixel(width, height, image::Rgba([10, 20, 30, 255])),
+            proxy: None,
+        }
+    }
+
+    #[test]
+    fn rejects_regions_larger_than_a_preview() {
+        let options = UpscaleOptions {
+            method: UpscaleMethod::NearestNeighbor,
+            factor: 8.0,
+            ..Default::default()
+        };
+        let mut source = source(1000, 600);
+        let region = |width, height| Region {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+
+        let png = render(None, &mut source, &options, Some(&region(512, 100)), 1024).unwrap();
+        let preview = image::load_from_memory(&png).unwrap();
+        assert_eq!((preview.width(), preview.height()), (4096, 800));
+
+        let error = render(None, &mut source, &options, Some(&region(513, 10)), 1024);
+        assert!(error.unwrap_err().to_string().contains("4104x80"));
+    }
+
+    #[test]
+    fn proxies_fit_max_size() {
+        let options = UpscaleOptions {
+            method: UpscaleMethod::NearestNeighbor,
+            factor: 4.0,
+            ..Default::default()
+        };
+        let png = render(None, &mut source(1000, 600), &options, None, 256).unwrap();
+        let preview = image::load_from_memory(&png).unwrap();
+        assert_eq!((preview.width(), preview.height()), (256, 152));
+    }
+}
@@ -0,0 +1,360 @@
+//! Numbered image sequences, such as frames extracted from a video
+//!
+//! [`upscale_sequence`] reads `frame_00001.png`, `frame_00002.png`, ... one at
+//! a time, upscales each through the same [`VulkanContext`] and writes a
+//! matching numbered output sequence, so memory use doesn't grow with the
+//! length of the shot.
+//!
+//! Post-processing has to treat every frame alike or the result flickers.
+//! Classic contrast uses fixed parameters and already does; auto-levels and
+//! CLAHE would fit their curve to each frame's histogram, so for a sequence
+//! they are fitted once to the histograms of all source frames (see
+//! [`ShotStatistics`]) and that curve is applied to every frame. The spatial
+//! denoisers leave a little noise that differs from frame to frame, so with
+//! noise reduction on every frame is also blended with the previous output
+//! where the two differ by no more than the noise level measured over the
+//! whole shot (see [`ShotNoise`] and [`TemporalDenoise`]).
+
+use crate::pipeline::{self, JobResources, UpscaleMethod, UpscaleOptions};
+use crate::vulkan::{
+    post_processing::{ShotContrast, ShotNoise, ShotStatistics, TemporalDenoise},
+    StageTimings, VulkanContext,
+};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Instant;
+
+/// Most frames one sequence may have, about 11 hours at 25 fps. Every frame
+/// is checked for existence before the first one is upscaled.
+pub const MAX_SEQUENCE_FRAMES: u32 = 1_000_000;
+
+/// A `printf`-style frame path such as `frame_%05d.png`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramePattern {
+    prefix: String,
+    /// Zero-padded width of the frame number, 0 for none
+    digits: usize,
+    suffix: String,
+}
+
+impl FramePattern {
+    /// Parse a pattern with exactly one `%d` or `%0Nd` placeholder. `%%`
+    /// stands for a literal `%`.
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let mut prefix = String::new();
+        let mut suffix = String::new();
+        let mut digits = None;
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            let part = if digits.is_some() {
+                &mut suffix
+            } else {
+                &mut prefix
+            };
+            if c != '%' {
+                part.push(c);
+                continue;
+            }
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                part.push('%');
+                continue;
+            }
+
+            let mut width = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                width.push(digit);
+            }
+            if chars.next() != Some('d') || (!width.is_empty() && !width.starts_with('0')) {
+                return Err(format!(
+                    "Frame pattern {} must use %d or %0Nd for the frame number",
+                    pattern
+                ));
+            }
+            if digits.is_some() {
+                return Err(format!(
+                    "Frame pattern {} has more than one frame number",
+                    pattern
+                ));
+            }
+            let width = if width.is_empty() {
+                0
+            } else {
+                width
+                    .parse()
+                    .map_err(|_| format!("Invalid frame number width in {}", pattern))?
+            };
+            if width > 10 {
+                return Err(format!(
+                    "Frame number width in {} must be at most 10",
+                    pattern
+                ));
+            }
+            digits = Some(width);
+        }
+
+        match digits {
+            Some(digits) => Ok(FramePattern {
+                prefix,
+                digits,
+                suffix,
+            }),
+            None => Err(format!(
+                "Frame pattern {} has no frame number, e.g. frame_%05d.png",
+                pattern
+            )),
+        }
+    }
+
+    /// Path of frame number `frame`
+    pub fn path(&self, frame: u32) -> String {
+        format!(
+            "{}{:0width$}{}",
+            self.prefix,
+            frame,
+            self.suffix,
+            width = self.digits
+        )
+    }
+}
+
+/// Output pattern used when none is given: `<input stem>_<factor>x` with the
+/// extension of `options.output_format`, next to the input frames
+pub fn default_output_pattern(input_pattern: &str, options: &UpscaleOptions) -> String {
+    let input = Path::new(input_pattern);
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    input
+        .with_file_name(format!(
+            "{}_{}x.{}",
+            stem,
+            options.factor,
+            options.output_format.extension()
+        ))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Result of [`upscale_sequence`]
+#[derive(Debug, Clone, Serialize)]
+pub struct SequenceReport {
+    /// Number of frames written
+    pub frames: u32,
+    /// Size of every output frame
+    pub width: u32,
+    pub height: u32,
+    /// Time of each stage, summed over all frames
+    pub timings: StageTimings,
+}
+
+/// Upscale frames `first..=last` of `input` with `options` and write them to
+/// the same frame numbers of `output` in `options.output_format`. Every frame
+/// must have the size of the first. `context` must be provided when
+/// [`UpscaleMethod::uses_gpu`] is true.
+pub fn upscale_sequence(
+    context: Option<&VulkanContext>,
+    input: &FramePattern,
+    output: &FramePattern,
+    first: u32,
+    last: u32,
+    options: &UpscaleOptions,
+) -> Result<SequenceReport, Box<dyn std::error::Error>> {
+    options.validate()?;
+    if first > last {
+        return Err(format!("Frame range {}-{} is empty", first, last).into());
+    }
+    if input == output {
+        return Err("The output sequence would overwrite the input frames".into());
+    }
+
+    let frame_count = (last - first)
+        .checked_add(1)
+        .filter(|&count| count <= MAX_SEQUENCE_FRAMES)
+        .ok_or_else(|| {
+            format!(
+                "Frame range {}-{} is too long; a sequence has at most {} frames",
+                first, last, MAX_SEQUENCE_FRAMES
+            )
+        })?;
+
+    // Fail before any work rather than on a gap halfway through the shot
+    for frame in first..=last {
+        let path = input.path(frame);
+        if !Path::new(&path).exists() {
+            return Err(format!("Input frame does not exist: {}", path).into());
+        }
+    }
+
+    let (width, height) = image::image_dimensions(input.path(first))?;
+    let (output_width, output_height) = options.output_size(width, height);
+    let mut resources = JobResources::load(options, width, height)?;
+    let (shot_contrast, temporal_denoise) =
+        measure_shot(input, first..=last, options, (width, height))?;
+    resources.shot_contrast = shot_contrast;
+    resources.temporal_denoise = temporal_denoise.map(RefCell::new);
+
+    let elapsed_ms = |since: Instant| since.elapsed().as_secs_f64() * 1000.0;
+    let mut timings = StageTimings::default();
+
+    for (index, frame) in (first..=last).enumerate() {
+        let input_path = input.path(frame);
+        let output_path = output.path(frame);
+        println!(
+            "🎬 Frame {}/{}: {} -> {}",
+            index + 1,
+            frame_count,
+            input_path,
+            output_path
+        );
+
+        let started = Instant::now();
+        let image = image::open(&input_path)?.to_rgba8();
+        timings.decode_ms += elapsed_ms(started);
+        if image.dimensions() != (width, height) {
+            return Err(format!(
+                "{} is {}x{}, but the sequence starts at {}x{}",
+                input_path,
+                image.width(),
+                image.height(),
+                width,
+                height
+            )
+            .into());
+        }
+        let (upscaled, report) =
+            pipeline::upscale_with_resources(context, &image, options, &resources)?;
+        timings.add(&report.timings);
+
+        let started = Instant::now();
+        pipeline::save_image(
+            &upscaled,
+            &output_path,
+            options.output_format,
+            options.jpeg_quality,
+        )
+        .map_err(|e| format!("Failed to save {}: {}", output_path, e))?;
+        timings.encode_ms += elapsed_ms(started);
+    }
+
+    Ok(SequenceReport {
+        frames: frame_count,
+        width: output_width,
+        height: output_height,
+        timings,
+    })
+}
+
+/// Measure what post-processing needs from the whole shot: the contrast
+/// curve when the contrast step fits one, and the temporal denoise filter
+/// when noise reduction is on. Both are `None` when the method has no
+/// post-processing. The statistics are taken from the source frames;
+/// upscaling barely changes the histograms and only lowers the noise.
+///
+/// This decodes every frame, which the upscale loop then does again: keeping
+/// the decoded frames would make memory grow with the shot. A sequence that
+/// needs shot statistics therefore spends about twice the decode time of one
+/// that doesn't.
+fn measure_shot(
+    input: &FramePattern,
+    frames: RangeInclusive<u32>,
+    options: &UpscaleOptions,
+    (width, height): (u32, u32),
+) -> Result<(Option<ShotContrast>, Option<TemporalDenoise>), Box<dyn std::error::Error>> {
+    if !matches!(
+        options.method,
+        UpscaleMethod::Enhanced | UpscaleMethod::Model
+    ) {
+        return Ok((None, None));
+    }
+
+    // The tile grid has to fit both the source frames and the output
+    let settings = &options.post_processing;
+    let (output_width, output_height) = options.output_size(width, height);
+    let mut statistics =
+        ShotStatistics::new(settings, width.min(output_width), height.min(output_height));
+    let mut noise = settings.noise_reduction.then(ShotNoise::default);
+    if statistics.is_none() && noise.is_none() {
+        return Ok((None, None));
+    }
+
+    println!(
+        "📊 Measuring shot statistics over frames {}-{}...",
+        frames.start(),
+        frames.end()
+    );
+    for frame in frames {
+        let path = input.path(frame);
+        let image = image::open(&path)?.to_rgba8();
+        if let Some(statistics) = &mut statistics {
+            statistics.add_frame(image.as_raw(), image.width(), image.height())?;
+        }
+        if let Some(noise) = &mut noise {
+            noise.add_frame(image.as_raw(), image.width(), image.height())?;
+        }
+    }
+
+    let temporal = noise.map(|noise| {
+        println!("📊 Shot noise level: {:.2}", noise.sigma());
+        TemporalDenoise::new(noise.sigma())
+    });
+    Ok((
+        statistics.map(|statistics| statistics.contrast(settings)),
+        temporal,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_frame_numbers() {
+        let pattern = FramePattern::parse("shots/frame_%05d.png").unwrap();
+        assert_eq!(pattern.path(7), "shots/frame_00007.png");
+        assert_eq!(pattern.path(123456), "shots/frame_123456.png");
+
+        let pattern = FramePattern::parse("%d_100%%.jpg").unwrap();
+        assert_eq!(pattern.path(42), "42_100%.jpg");
+    }
+
+    #[test]
+    fn default_output_keeps_the_frame_number() {
+        let options = UpscaleOptions::default();
+        let output = default_output_pattern("shots/frame_%05d.jpg", &options);
+        assert_eq!(output, "shots/frame_%05d_2x.png");
+        assert_eq!(
+            FramePattern::parse(&output).unwrap().path(3),
+            "shots/frame_00003_2x.png"
+        );
+    }
+
+    #[test]
+    fn rejects_ranges_that_are_too_long() {
+        let input = FramePattern::parse("frame_%05d.png").unwrap();
+        let output = FramePattern::parse("frame_%05d_2x.png").unwrap();
+        let options = UpscaleOptions::default();
+        for last in [u32::MAX, 4_000_000_000, MAX_SEQUENCE_FRAMES] {
+            let error = upscale_sequence(None, &input, &output, 0, last, &options).unwrap_err();
+            assert!(error.to_string().contains("too long"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_patterns() {
+        for pattern in [
+            "frame.png",
+            "frame_%s.png",
+            "frame_%5d.png",
+            "frame_%d_%d.png",
+            "frame_%011d.png",
+            "frame_%",
+        ] {
+            assert!(
+                FramePattern::parse(pattern).is_err(),
+                "{} was accepted",
+                pattern
+            );
+        }
+    }
+}
@@ -1,7 +1,183 @@
 use ash::vk;
-use image::{ImageBuffer, Rgba};
+use serde::{Deserialize, Serialize};
 use std::ffi::CString;
 
+pub mod compute;
+pub mod downscale;
+pub mod edge_directed;
+pub mod fsr;
+pub mod interpolation;
+pub mod line_art;
+pub mod neural;
+pub mod post_processing;
+
+pub use line_art::LineArtSettings;
+pub use neural::NeuralSettings;
+pub use post_processing::{ContrastMode, DenoiseMode, PostProcessSettings, SharpenMode};
+
+/// Interpolation kernel used by the upscale shader
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpolationKernel {
+    /// Mitchell-Netravali below 4x, Lanczos-2 from 4x up
+    #[default]
+    Auto,
+    /// Mitchell-Netravali bicubic (B=1/3, C=1/3)
+    Mitchell,
+    /// Lanczos-2
+    Lanczos,
+    /// Gradient-guided interpolation along edges, see [`edge_directed`]
+    EdgeDirected,
+    /// AMD FSR 1 edge-adaptive spatial upsampling (EASU), see [`fsr`]
+    Fsr,
+    /// Area averaging; downscaling only, see [`downscale`]
+    Area,
+}
+
+impl InterpolationKernel {
+    /// Value of the `kernel` push constant in `upscale.comp`. Edge-directed
+    /// interpolation and FSR run their own shaders and area averaging only
+    /// downscales, so none of them reach `upscale.comp`.
+    fn shader_id(self) -> u32 {
+        match self {
+            InterpolationKernel::Auto
+            | InterpolationKernel::EdgeDirected
+            | InterpolationKernel::Fsr
+            | InterpolationKernel::Area => 0,
+            InterpolationKernel::Mitchell => 1,
+            InterpolationKernel::Lanczos => 2,
+        }
+    }
+}
+
+/// Timing report of an upscale job: wall-clock time of each stage on the
+/// CPU, in milliseconds, and the GPU's own time for the upload, dispatch and
+/// readback commands. Stages a job doesn't have are 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct StageTimings {
+    /// Loading and decoding the input file
+    pub decode_ms: f64,
+    /// Creating the buffers and copying the pixels into the staging buffer
+    pub upload_ms: f64,
+    /// Recording and submitting the commands, and waiting for the queue. For
+    /// methods that don't go through [`upscale_rgba_timed`] (the CPU scalers,
+    /// neural and ONNX models, downscales) the whole enlarge step.
+    pub dispatch_ms: f64,
+    /// Copying the result out of the readback buffer
+    pub readback_ms: f64,
+    /// The filters run after the enlarge step: the post-processing chain, the
+    /// line-art passes and palette lock
+    pub post_processing_ms: f64,
+    /// Encoding and writing the output file
+    pub encode_ms: f64,
+    /// GPU time of the interpolation upscale, from timestamp queries. Only
+    /// the Mitchell and Lanczos kernels of [`upscale_rgba_timed`] (including
+    /// `auto`) are timed; this is `None` for the edge-directed and FSR
+    /// kernels, downscales, the neural method, and queues that can't write
+    /// timestamps. The GPU post-processing filters and line-art passes are
+    /// never included.
+    pub gpu: Option<GpuTimings>,
+}
+
+impl StageTimings {
+    /// Add the stage and GPU times of `other`, e.g. another frame of the job
+    pub fn add(&mut self, other: &StageTimings) {
+        self.decode_ms += other.decode_ms;
+        self.upload_ms += other.upload_ms;
+        self.dispatch_ms += other.dispatch_ms;
+        self.readback_ms += other.readback_ms;
+        self.post_processing_ms += other.post_processing_ms;
+        self.encode_ms += other.encode_ms;
+        if let Some(other) = &other.gpu {
+            let gpu = self.gpu.get_or_insert_with(GpuTimings::default);
+            gpu.upload_ms += other.upload_ms;
+            gpu.dispatch_ms += other.dispatch_ms;
+            gpu.readback_ms += other.readback_ms;
+        }
+    }
+
+    /// Print the stage times on one line, and the GPU times when known
+    pub fn log(&self) {
+        println!(
+            "⏱️  decode {:.1} ms, upload {:.1} ms, dispatch {:.1} ms, readback {:.1} ms, \
+             post-processing {:.1} ms, encode {:.1} ms",
+            self.decode_ms,
+            self.upload_ms,
+            self.dispatch_ms,
+            self.readback_ms,
+            self.post_processing_ms,
+            self.encode_ms
+        );
+        if let Some(gpu) = &self.gpu {
+            println!(
+                "⏱️  GPU: upload {:.2} ms, dispatch {:.2} ms, readback {:.2} ms",
+                gpu.upload_ms, gpu.dispatch_ms, gpu.readback_ms
+            );
+        }
+    }
+}
+
+/// GPU execution time of the commands of an interpolation upscale, in
+/// milliseconds, from timestamp queries. See [`StageTimings::gpu`] for which
+/// methods have them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct GpuTimings {
+    /// Copying the staging buffer into the device-local input buffer
+    pub upload_ms: f64,
+    /// Running the upscale shader
+    pub dispatch_ms: f64,
+    /// Copying the device-local output into the readback buffer
+    pub readback_ms: f64,
+}
+
+/// Number of timestamps written by [`upscale_rgba_timed`]
+const TIMESTAMP_COUNT: u32 = 4;
+
+/// Converts timestamp query results of the context's queue to milliseconds
+#[derive(Debug, Clone, Copy)]
+struct TimestampClock {
+    /// Nanoseconds per tick, `timestampPeriod`
+    period_ns: f64,
+    /// Bits of the timestamp the queue actually writes
+    mask: u64,
+}
+
+impl TimestampClock {
+    /// `None` when the queue can't write timestamps
+    fn new(context: &VulkanContext) -> Option<Self> {
+        let properties = unsafe {
+            context
+                .instance
+                .get_physical_device_properties(context.physical_device)
+        };
+        let queue_families = unsafe {
+            context
+                .instance
+                .get_physical_device_queue_family_properties(context.physical_device)
+        };
+        let valid_bits = queue_families
+            .get(context.queue_family_index as usize)?
+            .timestamp_valid_bits;
+        Self::from_limits(properties.limits.timestamp_period as f64, valid_bits)
+    }
+
+    /// Clock for a queue writing `valid_bits` bits, ticking every `period_ns`
+    fn from_limits(period_ns: f64, valid_bits: u32) -> Option<Self> {
+        if valid_bits == 0 || period_ns <= 0.0 {
+            return None;
+        }
+        Some(TimestampClock {
+            period_ns,
+            mask: u64::MAX >> (64 - valid_bits.min(64)),
+        })
+    }
+
+    /// Time between two timestamps, allowing for one wrap-around
+    fn elapsed_ms(self, start: u64, end: u64) -> f64 {
+        (end.wrapping_sub(start) & self.mask) as f64 * self.period_ns / 1e6
+    }
+}
+
 pub struct VulkanContext {
     pub entry: ash::Entry,
     pub instance: ash::Instance,
@@ -77,9 +253,12 @@ impl VulkanContext {
         let (physical_device, queue_family_index) = Self::pick_physical_device(&instance)?;
         let device = Self::create_logical_device(&instance, physical_device, queue_family_index)?;
         let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
-        let shader_module = Self::create_shader_module(&device)?;
-        let descriptor_set_layout = Self::create_descriptor_set_layout(&device)?;
-        let pipeline_layout = Self::create_pipeline_layout(&device, &descriptor_set_layout)?;
+        let shader_module =
+            Self::create_shader_module(&device, include_bytes!("../shaders/upscale.spv"))?;
+        // Input and output image buffers
+        let descriptor_set_layout = Self::create_descriptor_set_layout(&device, 2)?;
+        // 4 uint32 values: input_width, input_height, upscale_factor, kernel
+        let pipeline_layout = Self::create_pipeline_layout(&device, &descriptor_set_layout, 16)?;
         let pipeline = Self::create_compute_pipeline(&device, &pipeline_layout, &shader_module)?;
 
         Ok(VulkanContext {
@@ -96,6 +275,18 @@ impl VulkanContext {
         })
     }
 
+    /// Name of the physical device, e.g. for benchmark reports
+    pub fn device_name(&self) -> String {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+        };
+        properties
+            .device_name_as_c_str()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
     fn create_instance(entry: &ash::Entry) -> Result<ash::Instance, Box<dyn std::error::Error>> {
         let app_name = std::ffi::CString::new("V-Upscale")?;
         let engine_name = std::ffi::CString::new("No Engine")?;
@@ -137,18 +328,30 @@ impl VulkanContext {
                 .map_err(|e| format!("Failed to enumerate physical devices: {}", e))?
         };
 
-        for device in physical_devices {
-            let queue_family_properties =
-                unsafe { instance.get_physical_device_queue_family_properties(device) };
-            if let Some(index) = queue_family_properties
-                .iter()
-                .position(|p| p.queue_flags.contains(vk::QueueFlags::COMPUTE) && p.queue_count > 0)
-            {
-                return Ok((device, index as u32));
-            }
-        }
+        // Any device with a compute queue works, software ones like lavapipe
+        // included, but hardware GPUs are preferred when there is a choice
+        let rank = |device_type: vk::PhysicalDeviceType| match device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 4,
+            _ => 3,
+        };
 
-        Err("No suitable physical device found".to_string())
+        physical_devices
+            .into_iter()
+            .filter_map(|device| {
+                let queue_family_properties =
+                    unsafe { instance.get_physical_device_queue_family_properties(device) };
+                let index = queue_family_properties.iter().position(|p| {
+                    p.queue_flags.contains(vk::QueueFlags::COMPUTE) && p.queue_count > 0
+                })?;
+                let properties = unsafe { instance.get_physical_device_properties(device) };
+                Some((rank(properties.device_type), device, index as u32))
+            })
+            .min_by_key(|&(rank, _, _)| rank)
+            .map(|(_, device, index)| (device, index))
+            .ok_or_else(|| "No suitable physical device found".to_string())
     }
 
     fn create_logical_device(
@@ -185,14 +388,16 @@ impl VulkanContext {
 
     fn create_shader_module(
         device: &ash::Device,
+        shader_code: &[u8],
     ) -> Result<vk::ShaderModule, Box<dyn std::error::Error>> {
-        let shader_code = include_bytes!("../shaders/upscale.spv");
+        // Copy into a u32 buffer, since included bytes aren't guaranteed to be aligned
+        let shader_code = ash::util::read_spv(&mut std::io::Cursor::new(shader_code))?;
         let create_info = vk::ShaderModuleCreateInfo {
             s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::ShaderModuleCreateFlags::empty(),
-            code_size: shader_code.len(),
-            p_code: shader_code.as_ptr() as *const u32,
+            code_size: shader_code.len() * 4,
+            p_code: shader_code.as_ptr(),
             ..Default::default()
         };
 
@@ -200,29 +405,21 @@ impl VulkanContext {
         Ok(shader_module)
     }
 
+    /// Layout with `binding_count` storage buffers at bindings `0..binding_count`
     fn create_descriptor_set_layout(
         device: &ash::Device,
+        binding_count: u32,
     ) -> Result<vk::DescriptorSetLayout, vk::Result> {
-        let bindings = [
-            // Input image buffer
-            vk::DescriptorSetLayoutBinding {
-                binding: 0,
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..binding_count)
+            .map(|binding| vk::DescriptorSetLayoutBinding {
+                binding,
                 descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
                 descriptor_count: 1,
                 stage_flags: vk::ShaderStageFlags::COMPUTE,
                 p_immutable_samplers: std::ptr::null(),
                 _marker: std::marker::PhantomData,
-            },
-            // Output image buffer
-            vk::DescriptorSetLayoutBinding {
-                binding: 1,
-                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
-                descriptor_count: 1,
-                stage_flags: vk::ShaderStageFlags::COMPUTE,
-                p_immutable_samplers: std::ptr::null(),
-                _marker: std::marker::PhantomData,
-            },
-        ];
+            })
+            .collect();
 
         let create_info = vk::DescriptorSetLayoutCreateInfo {
             s_type: vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
@@ -238,12 +435,13 @@ impl VulkanContext {
     fn create_pipeline_layout(
         device: &ash::Device,
         descriptor_set_layout: &vk::DescriptorSetLayout,
+        push_constant_size: u32,
     ) -> Result<vk::PipelineLayout, vk::Result> {
         let layouts = [*descriptor_set_layout];
         let push_constant_ranges = [vk::PushConstantRange {
             stage_flags: vk::ShaderStageFlags::COMPUTE,
             offset: 0,
-            size: 12, // 3 uint32 values: input_width, input_height, upscale_factor
+            size: push_constant_size,
         }];
         let create_info = vk::PipelineLayoutCreateInfo {
             s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
@@ -295,40 +493,134 @@ impl VulkanContext {
     }
 }
 
-pub fn process_image(
+/// Upscale raw RGBA8 pixels by `factor` on the GPU with the given kernel and
+/// return the `width * factor` x `height * factor` RGBA8 result
+pub fn upscale_rgba(
     context: &VulkanContext,
-    input_image_path: &str,
-    output_image_path: &str,
+    input_image_data: &[u8],
+    width: u32,
+    height: u32,
     factor: u32,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Processing {} -> {}", input_image_path, output_image_path);
+    kernel: InterpolationKernel,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut timings = StageTimings::default();
+    upscale_rgba_timed(
+        context,
+        input_image_data,
+        width,
+        height,
+        factor,
+        kernel,
+        &mut timings,
+    )
+}
 
-    // Check if input file exists
-    if !std::path::Path::new(input_image_path).exists() {
-        return Err(format!("Input image file does not exist: {}", input_image_path).into());
+/// [`upscale_rgba`], adding the time of the upload, dispatch and readback
+/// stages to `timings`, and their GPU time when the queue supports timestamp
+/// queries. The edge-directed and FSR kernels run their own shaders, so all of
+/// their time counts as dispatch and they have no GPU times.
+pub fn upscale_rgba_timed(
+    context: &VulkanContext,
+    input_image_data: &[u8],
+    width: u32,
+    height: u32,
+    factor: u32,
+    kernel: InterpolationKernel,
+    timings: &mut StageTimings,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let started = std::time::Instant::now();
+    let elapsed_ms = |since: std::time::Instant| since.elapsed().as_secs_f64() * 1000.0;
+
+    if width == 0 || height == 0 || input_image_data.len() != width as usize * height as usize * 4 {
+        return Err("Input image data does not match its dimensions".into());
     }
 
-    // 1. Load the image
-    let input_image = image::open(input_image_path)?.to_rgba8();
-    let (width, height) = input_image.dimensions();
-    let input_image_data = input_image.into_raw();
+    match kernel {
+        InterpolationKernel::EdgeDirected => {
+            let output =
+                edge_directed::upscale_gpu(context, input_image_data, width, height, factor);
+            timings.dispatch_ms += elapsed_ms(started);
+            return output;
+        }
+        InterpolationKernel::Fsr => {
+            let output = fsr::easu_gpu(
+                context,
+                input_image_data,
+                width,
+                height,
+                width * factor,
+                height * factor,
+            );
+            timings.dispatch_ms += elapsed_ms(started);
+            return output;
+        }
+        InterpolationKernel::Area => {
+            return Err("The area kernel only downscales".into());
+        }
+        InterpolationKernel::Auto
+        | InterpolationKernel::Mitchell
+        | InterpolationKernel::Lanczos => {}
+    }
 
     let output_width = width * factor;
     let output_height = height * factor;
-    let output_image_data = vec![0u8; (output_width * output_height * 4) as usize];
-
-    // 2. Create Buffers
-    let (input_buffer, input_memory) = create_buffer(
+    let input_size = input_image_data.len() as u64;
+    let output_size = output_width as u64 * output_height as u64 * 4;
+
+    // 2. Create Buffers. The shader reads and writes device-local buffers,
+    // which are filled and read back with copy commands through host-visible
+    // staging buffers, so the GPU can time the transfers.
+    let mut resources = UpscaleResources::new(context);
+    let (staging_buffer, _) = resources.add_buffer(create_buffer(
         context,
-        &input_image_data,
-        vk::BufferUsageFlags::STORAGE_BUFFER,
-    )?;
-
-    let (output_buffer, output_memory) = create_buffer(
+        input_image_data,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+    )?);
+    let (input_buffer, _) = resources.add_buffer(create_device_buffer(
         context,
-        &output_image_data,
-        vk::BufferUsageFlags::STORAGE_BUFFER,
-    )?;
+        input_size,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+    )?);
+    let (output_buffer, _) = resources.add_buffer(create_device_buffer(
+        context,
+        output_size,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC,
+    )?);
+    let (readback_buffer, readback_memory) = resources.add_buffer(allocate_buffer(
+        context,
+        output_size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        &[vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT],
+    )?);
+
+    // Timestamps before the upload, after the upload, after the dispatch and
+    // after the readback
+    let clock = TimestampClock::new(context);
+    let query_pool = match clock {
+        Some(_) => {
+            let query_pool_info = vk::QueryPoolCreateInfo {
+                s_type: vk::StructureType::QUERY_POOL_CREATE_INFO,
+                query_type: vk::QueryType::TIMESTAMP,
+                query_count: TIMESTAMP_COUNT,
+                ..Default::default()
+            };
+            resources.query_pool =
+                unsafe { context.device.create_query_pool(&query_pool_info, None)? };
+            Some(resources.query_pool)
+        }
+        None => None,
+    };
+    let write_timestamp = |command_buffer, stage, query| {
+        if let Some(query_pool) = query_pool {
+            unsafe {
+                context
+                    .device
+                    .cmd_write_timestamp(command_buffer, stage, query_pool, query)
+            };
+        }
+    };
+    timings.upload_ms += elapsed_ms(started);
+    let dispatch_started = std::time::Instant::now();
 
     // 3. Update Descriptor Sets
     let pool_sizes = [vk::DescriptorPoolSize {
@@ -344,7 +636,8 @@ pub fn process_image(
         p_pool_sizes: pool_sizes.as_ptr(),
         ..Default::default()
     };
-    let descriptor_pool = unsafe { context.device.create_descriptor_pool(&pool_info, None)? };
+    resources.descriptor_pool = unsafe { context.device.create_descriptor_pool(&pool_info, None)? };
+    let descriptor_pool = resources.descriptor_pool;
 
     let layouts = [context.descriptor_set_layout];
     let alloc_info = vk::DescriptorSetAllocateInfo {
@@ -406,7 +699,8 @@ pub fn process_image(
         queue_family_index: context.queue_family_index,
         ..Default::default()
     };
-    let command_pool = unsafe { context.device.create_command_pool(&pool_info, None)? };
+    resources.command_pool = unsafe { context.device.create_command_pool(&pool_info, None)? };
+    let command_pool = resources.command_pool;
 
     let alloc_info = vk::CommandBufferAllocateInfo {
         s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
@@ -433,6 +727,36 @@ pub fn process_image(
     };
 
     unsafe {
+        if let Some(query_pool) = query_pool {
+            context
+                .device
+                .cmd_reset_query_pool(command_buffer, query_pool, 0, TIMESTAMP_COUNT);
+        }
+        write_timestamp(command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, 0);
+        context.device.cmd_copy_buffer(
+            command_buffer,
+            staging_buffer,
+            input_buffer,
+            &[vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size: input_size,
+            }],
+        );
+        memory_barrier(
+            context,
+            command_buffer,
+            (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+        );
+        write_timestamp(command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, 1);
+
         context.device.cmd_bind_pipeline(
             command_buffer,
             vk::PipelineBindPoint::COMPUTE,
@@ -446,17 +770,17 @@ pub fn process_image(
             &descriptor_sets,
             &[],
         );
-        let push_constants = [width, height, factor];
+        let push_constants = [width, height, factor, kernel.shader_id()];
         context.device.cmd_push_constants(
             command_buffer,
             context.pipeline_layout,
             vk::ShaderStageFlags::COMPUTE,
             0,
-            std::slice::from_raw_parts(push_constants.as_ptr() as *const u8, 12),
+            std::slice::from_raw_parts(push_constants.as_ptr() as *const u8, 16),
         );
         // Calculate dispatch groups with proper rounding
-        let group_x = (output_width + 15) / 16; // Round up to nearest multiple of 16
-        let group_y = (output_height + 15) / 16; // Round up to nearest multiple of 16
+        let group_x = output_width.div_ceil(16);
+        let group_y = output_height.div_ceil(16);
         println!(
             "Dispatching compute shader: {}x{} groups for {}x{} image",
             group_x, group_y, output_width, output_height
@@ -464,6 +788,40 @@ pub fn process_image(
         context
             .device
             .cmd_dispatch(command_buffer, group_x, group_y, 1);
+        write_timestamp(command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, 2);
+
+        memory_barrier(
+            context,
+            command_buffer,
+            (
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+        );
+        context.device.cmd_copy_buffer(
+            command_buffer,
+            output_buffer,
+            readback_buffer,
+            &[vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size: output_size,
+            }],
+        );
+        memory_barrier(
+            context,
+            command_buffer,
+            (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+            (vk::PipelineStageFlags::HOST, vk::AccessFlags::HOST_READ),
+        );
+        write_timestamp(command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, 3);
     }
 
     unsafe { context.device.end_command_buffer(command_buffer)? };
@@ -488,72 +846,105 @@ pub fn process_image(
         )?;
         context.device.queue_wait_idle(context.queue)?;
     }
+    timings.dispatch_ms += elapsed_ms(dispatch_started);
+    let readback_started = std::time::Instant::now();
 
     // 5. Read back the result
-    let mut output_data = vec![0u8; output_image_data.len()];
+    let mut output_data = vec![0u8; output_size as usize];
     let ptr = unsafe {
-        context.device.map_memory(
-            output_memory,
-            0,
-            output_image_data.len() as u64,
-            vk::MemoryMapFlags::empty(),
-        )?
+        context
+            .device
+            .map_memory(readback_memory, 0, output_size, vk::MemoryMapFlags::empty())?
     };
     unsafe {
         std::ptr::copy_nonoverlapping(
             ptr as *const u8,
             output_data.as_mut_ptr(),
-            output_image_data.len(),
+            output_data.len(),
         );
-        context.device.unmap_memory(output_memory);
+        context.device.unmap_memory(readback_memory);
     }
+    timings.readback_ms += elapsed_ms(readback_started);
 
-    // 6. Save the image
-    println!("Saving upscaled image to: {}", output_image_path);
-    image::save_buffer(
-        output_image_path,
-        &output_data,
-        output_width,
-        output_height,
-        image::ColorType::Rgba8,
-    )
-    .map_err(|e| format!("Failed to save output image: {}", e))?;
+    if let (Some(clock), Some(query_pool)) = (clock, query_pool) {
+        let mut ticks = [0u64; TIMESTAMP_COUNT as usize];
+        unsafe {
+            context.device.get_query_pool_results(
+                query_pool,
+                0,
+                &mut ticks,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )?
+        };
+        let gpu = timings.gpu.get_or_insert_with(GpuTimings::default);
+        gpu.upload_ms += clock.elapsed_ms(ticks[0], ticks[1]);
+        gpu.dispatch_ms += clock.elapsed_ms(ticks[1], ticks[2]);
+        gpu.readback_ms += clock.elapsed_ms(ticks[2], ticks[3]);
+    }
 
-    // Verify the file was created
-    if std::path::Path::new(output_image_path).exists() {
-        println!(
-            "✓ Successfully created upscaled image: {}",
-            output_image_path
-        );
-    } else {
-        return Err("Output file was not created despite successful processing".into());
+    Ok(output_data)
+}
+
+/// Vulkan objects created by one [`upscale_rgba_timed`] call. They are
+/// destroyed when it returns, including on errors; handles that were never
+/// created are null, which Vulkan ignores.
+struct UpscaleResources<'a> {
+    context: &'a VulkanContext,
+    buffers: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    query_pool: vk::QueryPool,
+    descriptor_pool: vk::DescriptorPool,
+    command_pool: vk::CommandPool,
+}
+
+impl<'a> UpscaleResources<'a> {
+    fn new(context: &'a VulkanContext) -> Self {
+        UpscaleResources {
+            context,
+            buffers: Vec::new(),
+            query_pool: vk::QueryPool::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            command_pool: vk::CommandPool::null(),
+        }
     }
 
-    // Cleanup
-    unsafe {
-        context.device.destroy_command_pool(command_pool, None);
-        context
-            .device
-            .destroy_descriptor_pool(descriptor_pool, None);
-        context.device.destroy_buffer(input_buffer, None);
-        context.device.free_memory(input_memory, None);
-        context.device.destroy_buffer(output_buffer, None);
-        context.device.free_memory(output_memory, None);
+    /// Take ownership of a buffer and its memory
+    fn add_buffer(
+        &mut self,
+        buffer: (vk::Buffer, vk::DeviceMemory),
+    ) -> (vk::Buffer, vk::DeviceMemory) {
+        self.buffers.push(buffer);
+        buffer
     }
+}
 
-    Ok(())
+impl Drop for UpscaleResources<'_> {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_command_pool(self.command_pool, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_query_pool(self.query_pool, None);
+            for &(buffer, memory) in &self.buffers {
+                device.destroy_buffer(buffer, None);
+                device.free_memory(memory, None);
+            }
+        }
+    }
 }
 
-fn create_buffer(
+/// Create a buffer of `size` bytes bound to new memory with the first of
+/// `preferences` that the device offers for it
+fn allocate_buffer(
     context: &VulkanContext,
-    data: &[u8],
+    size: u64,
     usage: vk::BufferUsageFlags,
+    preferences: &[vk::MemoryPropertyFlags],
 ) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
     let buffer_info = vk::BufferCreateInfo {
         s_type: vk::StructureType::BUFFER_CREATE_INFO,
         p_next: std::ptr::null(),
         flags: vk::BufferCreateFlags::empty(),
-        size: data.len() as u64,
+        size,
         usage,
         sharing_mode: vk::SharingMode::EXCLUSIVE,
         queue_family_index_count: 0,
@@ -570,18 +961,21 @@ fn create_buffer(
             .get_physical_device_memory_properties(context.physical_device)
     };
 
-    let memory_type_index = mem_properties
-        .memory_types
-        .iter()
-        .enumerate()
-        .find(|(i, mem_type)| {
-            (mem_requirements.memory_type_bits & (1 << i)) != 0
-                && mem_type.property_flags.contains(
-                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-                )
-        })
-        .map(|(i, _)| i as u32)
-        .ok_or("Failed to find suitable memory type")?;
+    let memory_type_index = preferences.iter().find_map(|&properties| {
+        mem_properties
+            .memory_types
+            .iter()
+            .enumerate()
+            .find(|(i, mem_type)| {
+                (mem_requirements.memory_type_bits & (1 << i)) != 0
+                    && mem_type.property_flags.contains(properties)
+            })
+            .map(|(i, _)| i as u32)
+    });
+    let Some(memory_type_index) = memory_type_index else {
+        unsafe { context.device.destroy_buffer(buffer, None) };
+        return Err("Failed to find suitable memory type".into());
+    };
 
     let alloc_info = vk::MemoryAllocateInfo {
         s_type: vk::StructureType::MEMORY_ALLOCATE_INFO,
@@ -591,245 +985,102 @@ fn create_buffer(
         ..Default::default()
     };
 
-    let memory = unsafe { context.device.allocate_memory(&alloc_info, None)? };
-    unsafe { context.device.bind_buffer_memory(buffer, memory, 0)? };
-
-    let ptr = unsafe {
-        context
-            .device
-            .map_memory(memory, 0, data.len() as u64, vk::MemoryMapFlags::empty())?
+    let memory = match unsafe { context.device.allocate_memory(&alloc_info, None) } {
+        Ok(memory) => memory,
+        Err(e) => {
+            unsafe { context.device.destroy_buffer(buffer, None) };
+            return Err(e.into());
+        }
     };
-    unsafe {
-        std::ptr::copy_nonoverlapping(data.as_ptr(), ptr as *mut u8, data.len());
-        context.device.unmap_memory(memory);
+    if let Err(e) = unsafe { context.device.bind_buffer_memory(buffer, memory, 0) } {
+        unsafe {
+            context.device.destroy_buffer(buffer, None);
+            context.device.free_memory(memory, None);
+        }
+        return Err(e.into());
     }
 
     Ok((buffer, memory))
 }
 
-/// Post-processing functions for enhanced image quality
-pub mod post_processing {
-    use super::*;
-
-    /// Apply sharpening filter to enhance details
-    pub fn apply_sharpening(
-        image_data: &[u8],
-        width: u32,
-        height: u32,
-        intensity: f32,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let img: ImageBuffer<Rgba<u8>, Vec<u8>> =
-            ImageBuffer::from_raw(width, height, image_data.to_vec())
-                .ok_or("Failed to create image buffer")?;
-
-        // Simple unsharp masking implementation
-        let result = ImageBuffer::from_fn(width, height, |x, y| {
-            let pixel = img.get_pixel(x, y);
-
-            // Get neighborhood pixels for edge detection
-            let get_safe_pixel = |px: i32, py: i32| -> Rgba<u8> {
-                let px = px.clamp(0, width as i32 - 1) as u32;
-                let py = py.clamp(0, height as i32 - 1) as u32;
-                *img.get_pixel(px, py)
-            };
+/// Create a host-visible buffer holding a copy of `data`
+fn create_buffer(
+    context: &VulkanContext,
+    data: &[u8],
+    usage: vk::BufferUsageFlags,
+) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
+    let (buffer, memory) = allocate_buffer(
+        context,
+        data.len() as u64,
+        usage,
+        &[vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT],
+    )?;
 
-            // High-pass sharpening kernel (more aggressive)
-            let kernel = [
-                [-0.25, -0.5, -0.25],
-                [-0.5, 4.0 + intensity * 2.0, -0.5],
-                [-0.25, -0.5, -0.25],
-            ];
-
-            let mut r_sum = 0.0;
-            let mut g_sum = 0.0;
-            let mut b_sum = 0.0;
-
-            for dy in -1..=1 {
-                for dx in -1..=1 {
-                    let neighbor = get_safe_pixel(x as i32 + dx, y as i32 + dy);
-                    let weight = kernel[(dy + 1) as usize][(dx + 1) as usize];
-                    r_sum += neighbor[0] as f32 * weight;
-                    g_sum += neighbor[1] as f32 * weight;
-                    b_sum += neighbor[2] as f32 * weight;
-                }
+    let mapped = unsafe {
+        context
+            .device
+            .map_memory(memory, 0, data.len() as u64, vk::MemoryMapFlags::empty())
+    };
+    let ptr = match mapped {
+        Ok(ptr) => ptr,
+        Err(e) => {
+            unsafe {
+                context.device.destroy_buffer(buffer, None);
+                context.device.free_memory(memory, None);
             }
-
-            Rgba([
-                (r_sum.clamp(0.0, 255.0)) as u8,
-                (g_sum.clamp(0.0, 255.0)) as u8,
-                (b_sum.clamp(0.0, 255.0)) as u8,
-                pixel[3], // Preserve alpha
-            ])
-        });
-
-        Ok(result.into_raw())
-    }
-
-    /// Enhance contrast and brightness for old/faded images
-    pub fn enhance_contrast(
-        image_data: &[u8],
-        width: u32,
-        height: u32,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let img: ImageBuffer<Rgba<u8>, Vec<u8>> =
-            ImageBuffer::from_raw(width, height, image_data.to_vec())
-                .ok_or("Failed to create image buffer")?;
-
-        // Convert to grayscale for histogram analysis, but keep RGB data
-        let enhanced_img = ImageBuffer::from_fn(width, height, |x, y| {
-            let pixel = img.get_pixel(x, y);
-
-            // Enhanced contrast and clarity boost
-            let gamma = 1.15; // Slightly brighten
-            let contrast = 1.25; // More aggressive contrast boost
-            let saturation = 1.1; // Slight saturation increase
-
-            // Apply gamma correction first
-            let r_gamma = (pixel[0] as f32 / 255.0).powf(1.0 / gamma);
-            let g_gamma = (pixel[1] as f32 / 255.0).powf(1.0 / gamma);
-            let b_gamma = (pixel[2] as f32 / 255.0).powf(1.0 / gamma);
-
-            // Apply contrast enhancement
-            let r_contrast = (r_gamma - 0.5) * contrast + 0.5;
-            let g_contrast = (g_gamma - 0.5) * contrast + 0.5;
-            let b_contrast = (b_gamma - 0.5) * contrast + 0.5;
-
-            // Apply saturation boost
-            let luminance = 0.299 * r_contrast + 0.587 * g_contrast + 0.114 * b_contrast;
-            let r = ((r_contrast - luminance) * saturation + luminance).clamp(0.0, 1.0) * 255.0;
-            let g = ((g_contrast - luminance) * saturation + luminance).clamp(0.0, 1.0) * 255.0;
-            let b = ((b_contrast - luminance) * saturation + luminance).clamp(0.0, 1.0) * 255.0;
-
-            Rgba([r as u8, g as u8, b as u8, pixel[3]])
-        });
-
-        Ok(enhanced_img.into_raw())
-    }
-
-    /// Apply noise reduction using a simple bilateral-like filter
-    pub fn reduce_noise(
-        image_data: &[u8],
-        width: u32,
-        height: u32,
-        strength: f32,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        if strength <= 0.0 {
-            return Ok(image_data.to_vec());
+            return Err(e.into());
         }
-
-        let img: ImageBuffer<Rgba<u8>, Vec<u8>> =
-            ImageBuffer::from_raw(width, height, image_data.to_vec())
-                .ok_or("Failed to create image buffer")?;
-
-        // Simple noise reduction using mean filtering
-        let result = ImageBuffer::from_fn(width, height, |x, y| {
-            let pixel = img.get_pixel(x, y);
-
-            // Apply simple box filter for noise reduction
-            let radius = (strength * 2.0) as i32;
-            let mut r_sum = 0u32;
-            let mut g_sum = 0u32;
-            let mut b_sum = 0u32;
-            let mut count = 0u32;
-
-            for dy in -radius..=radius {
-                for dx in -radius..=radius {
-                    let px = (x as i32 + dx).clamp(0, width as i32 - 1) as u32;
-                    let py = (y as i32 + dy).clamp(0, height as i32 - 1) as u32;
-                    let neighbor = img.get_pixel(px, py);
-
-                    r_sum += neighbor[0] as u32;
-                    g_sum += neighbor[1] as u32;
-                    b_sum += neighbor[2] as u32;
-                    count += 1;
-                }
-            }
-
-            // Simple edge preservation: blend based on variance
-            let avg_r = r_sum / count;
-            let avg_g = g_sum / count;
-            let avg_b = b_sum / count;
-
-            let edge_factor = ((pixel[0] as i32 - avg_r as i32).abs()
-                + (pixel[1] as i32 - avg_g as i32).abs()
-                + (pixel[2] as i32 - avg_b as i32).abs()) as f32
-                / 3.0;
-
-            let blend = (1.0 - (edge_factor / 100.0).min(1.0)) * strength * 0.5;
-
-            Rgba([
-                ((pixel[0] as f32 * (1.0 - blend) + avg_r as f32 * blend).clamp(0.0, 255.0)) as u8,
-                ((pixel[1] as f32 * (1.0 - blend) + avg_g as f32 * blend).clamp(0.0, 255.0)) as u8,
-                ((pixel[2] as f32 * (1.0 - blend) + avg_b as f32 * blend).clamp(0.0, 255.0)) as u8,
-                pixel[3], // Preserve alpha
-            ])
-        });
-
-        Ok(result.into_raw())
+    };
+    unsafe {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), ptr as *mut u8, data.len());
+        context.device.unmap_memory(memory);
     }
+
+    Ok((buffer, memory))
 }
 
-/// Enhanced image processing with post-processing options
-pub fn process_image_enhanced(
+/// Create an uninitialized buffer of `size` bytes in device-local memory, or
+/// in any memory the device allows when it has none for this buffer
+fn create_device_buffer(
     context: &VulkanContext,
-    input_image_path: &str,
-    output_image_path: &str,
-    factor: u32,
-    apply_sharpening: bool,
-    apply_contrast_enhancement: bool,
-    apply_noise_reduction: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!(
-        "Enhanced processing {} -> {}",
-        input_image_path, output_image_path
-    );
-
-    // First, do the Vulkan upscaling
-    let temp_output = format!("{}.temp", output_image_path);
-    process_image(context, input_image_path, &temp_output, factor)?;
-
-    // Load the upscaled image for post-processing
-    let upscaled_image = image::open(&temp_output)?.to_rgba8();
-    let (width, height) = upscaled_image.dimensions();
-    let mut image_data = upscaled_image.into_raw();
-
-    println!("Applying post-processing filters...");
-
-    // Apply noise reduction first (if enabled)
-    if apply_noise_reduction {
-        println!("  - Reducing noise...");
-        image_data = post_processing::reduce_noise(&image_data, width, height, 0.5)?;
-    }
-
-    // Apply contrast enhancement
-    if apply_contrast_enhancement {
-        println!("  - Enhancing contrast...");
-        image_data = post_processing::enhance_contrast(&image_data, width, height)?;
-    }
-
-    // Apply sharpening last (more aggressive to counter any remaining blur)
-    if apply_sharpening {
-        println!("  - Applying sharpening...");
-        image_data = post_processing::apply_sharpening(&image_data, width, height, 1.8)?;
-    }
-
-    // Save the final processed image
-    image::save_buffer(
-        output_image_path,
-        &image_data,
-        width,
-        height,
-        image::ColorType::Rgba8,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+) -> Result<(vk::Buffer, vk::DeviceMemory), Box<dyn std::error::Error>> {
+    allocate_buffer(
+        context,
+        size,
+        usage,
+        &[
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::MemoryPropertyFlags::empty(),
+        ],
     )
-    .map_err(|e| format!("Failed to save final image: {}", e))?;
-
-    // Clean up temp file
-    if std::path::Path::new(&temp_output).exists() {
-        std::fs::remove_file(&temp_output)?;
-    }
+}
 
-    println!("✓ Enhanced processing complete: {}", output_image_path);
-    Ok(())
+/// Make the `src` stage's writes visible to the `dst` stage
+fn memory_barrier(
+    context: &VulkanContext,
+    command_buffer: vk::CommandBuffer,
+    src: (vk::PipelineStageFlags, vk::AccessFlags),
+    dst: (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let barrier = vk::MemoryBarrier {
+        s_type: vk::StructureType::MEMORY_BARRIER,
+        src_access_mask: src.1,
+        dst_access_mask: dst.1,
+        ..Default::default()
+    };
+    unsafe {
+        context.device.cmd_pipeline_barrier(
+            command_buffer,
+            src.0,
+            dst.0,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+        )
+    };
 }
 
 impl Drop for VulkanContext {
@@ -848,3 +1099,39 @@ impl Drop for VulkanContext {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_mask_keeps_the_valid_bits() {
+        let mask = |valid_bits| TimestampClock::from_limits(1.0, valid_bits).map(|c| c.mask);
+        assert_eq!(mask(0), None);
+        assert_eq!(mask(1), Some(1));
+        assert_eq!(mask(36), Some((1 << 36) - 1));
+        assert_eq!(mask(64), Some(u64::MAX));
+        assert!(TimestampClock::from_limits(0.0, 64).is_none());
+    }
+
+    #[test]
+    fn timestamps_convert_to_milliseconds() {
+        // 1 ms per tick, so the results read as tick counts
+        let clock = TimestampClock::from_limits(1e6, 64).unwrap();
+        assert_eq!(clock.elapsed_ms(100, 350), 250.0);
+        assert_eq!(clock.elapsed_ms(u64::MAX - 1, 3), 5.0);
+
+        let clock = TimestampClock::from_limits(0.5, 64).unwrap();
+        assert_eq!(clock.elapsed_ms(0, 4_000_000), 2.0);
+    }
+
+    #[test]
+    fn timestamps_wrap_at_the_valid_bits() {
+        let clock = TimestampClock::from_limits(1e6, 36).unwrap();
+        let last = (1u64 << 36) - 1;
+        assert_eq!(clock.elapsed_ms(last - 9, 5), 15.0);
+        // Bits above the valid ones are undefined and must not count
+        assert_eq!(clock.elapsed_ms(0xABC << 36 | 10, 20), 10.0);
+        assert_eq!(clock.elapsed_ms(10, 0xABC << 36 | 20), 10.0);
+    }
+}
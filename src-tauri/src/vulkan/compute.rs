@@ -0,0 +1,354 @@
+//! Reusable compute kernels and storage buffers on top of [`VulkanContext`]
+//!
+//! [`upscale_rgba_timed`](super::upscale_rgba_timed) owns the main upscale
+//! pipeline. The extra filters (denoisers, alternative upscalers, ...) are
+//! small shaders that all follow the same pattern: a handful of storage
+//! buffers, a push constant block and a 2D dispatch. [`ComputeKernel`] and [`GpuBuffer`] wrap
+//! that pattern so each filter only has to supply its SPIR-V and parameters.
+
+use super::{create_buffer, VulkanContext};
+use ash::vk;
+
+/// Host-visible storage buffer, freed on drop
+pub struct GpuBuffer {
+    device: ash::Device,
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: usize,
+}
+
+impl GpuBuffer {
+    /// Create a buffer initialized with `data`
+    pub fn from_bytes(
+        context: &VulkanContext,
+        data: &[u8],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if data.is_empty() {
+            return Err("Cannot create an empty GPU buffer".into());
+        }
+
+        let (buffer, memory) = create_buffer(context, data, vk::BufferUsageFlags::STORAGE_BUFFER)?;
+        Ok(GpuBuffer {
+            device: context.device.clone(),
+            buffer,
+            memory,
+            size: data.len(),
+        })
+    }
+
+    /// Create a zero-filled buffer of `size` bytes
+    pub fn zeroed(
+        context: &VulkanContext,
+        size: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_bytes(context, &vec![0u8; size])
+    }
+
+    /// Copy the buffer contents back to the host
+    pub fn read(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut data = vec![0u8; self.size];
+        unsafe {
+            let ptr = self.device.map_memory(
+                self.memory,
+                0,
+                self.size as u64,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            std::ptr::copy_nonoverlapping(ptr as *const u8, data.as_mut_ptr(), self.size);
+            self.device.unmap_memory(self.memory);
+        }
+        Ok(data)
+    }
+
+    /// Overwrite the start of the buffer with `data`
+    pub fn write(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        if data.len() > self.size {
+            return Err(format!(
+                "Data ({} bytes) does not fit in GPU buffer ({} bytes)",
+                data.len(),
+                self.size
+            )
+            .into());
+        }
+        unsafe {
+            let ptr = self.device.map_memory(
+                self.memory,
+                0,
+                data.len() as u64,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), ptr as *mut u8, data.len());
+            self.device.unmap_memory(self.memory);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GpuBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(self.buffer, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// A compute pipeline whose bindings are all storage buffers, freed on drop
+pub struct ComputeKernel {
+    device: ash::Device,
+    pub shader_module: vk::ShaderModule,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    binding_count: u32,
+    push_constant_size: u32,
+}
+
+impl ComputeKernel {
+    /// Build a pipeline from SPIR-V with `binding_count` storage buffers and a
+    /// push constant block of `push_constant_size` bytes
+    pub fn new(
+        context: &VulkanContext,
+        shader_code: &[u8],
+        binding_count: u32,
+        push_constant_size: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &context.device;
+        let shader_module = VulkanContext::create_shader_module(device, shader_code)?;
+        let descriptor_set_layout =
+            VulkanContext::create_descriptor_set_layout(device, binding_count)?;
+        let pipeline_layout = VulkanContext::create_pipeline_layout(
+            device,
+            &descriptor_set_layout,
+            push_constant_size,
+        )?;
+        let pipeline =
+            VulkanContext::create_compute_pipeline(device, &pipeline_layout, &shader_module)?;
+
+        Ok(ComputeKernel {
+            device: device.clone(),
+            shader_module,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+            binding_count,
+            push_constant_size,
+        })
+    }
+
+    /// Bind `buffers` to bindings `0..n` in order, dispatch `groups` workgroups
+    /// and wait for the queue to finish
+    pub fn dispatch(
+        &self,
+        context: &VulkanContext,
+        buffers: &[&GpuBuffer],
+        push_constants: &[u8],
+        groups: [u32; 3],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if buffers.len() != self.binding_count as usize {
+            return Err(format!(
+                "Kernel expects {} buffers, got {}",
+                self.binding_count,
+                buffers.len()
+            )
+            .into());
+        }
+        if push_constants.len() != self.push_constant_size as usize {
+            return Err(format!(
+                "Kernel expects {} bytes of push constants, got {}",
+                self.push_constant_size,
+                push_constants.len()
+            )
+            .into());
+        }
+
+        let device = &context.device;
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: self.binding_count,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: vk::StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+
+        let command_pool_info = vk::CommandPoolCreateInfo {
+            s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
+            queue_family_index: context.queue_family_index,
+            ..Default::default()
+        };
+        let command_pool = match unsafe { device.create_command_pool(&command_pool_info, None) } {
+            Ok(pool) => pool,
+            Err(e) => {
+                unsafe { device.destroy_descriptor_pool(descriptor_pool, None) };
+                return Err(e.into());
+            }
+        };
+
+        let result = self.record_and_submit(
+            context,
+            descriptor_pool,
+            command_pool,
+            buffers,
+            push_constants,
+            groups,
+        );
+
+        unsafe {
+            device.destroy_command_pool(command_pool, None);
+            device.destroy_descriptor_pool(descriptor_pool, None);
+        }
+
+        result
+    }
+
+    fn record_and_submit(
+        &self,
+        context: &VulkanContext,
+        descriptor_pool: vk::DescriptorPool,
+        command_pool: vk::CommandPool,
+        buffers: &[&GpuBuffer],
+        push_constants: &[u8],
+        groups: [u32; 3],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let device = &context.device;
+
+        let layouts = [self.descriptor_set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            descriptor_pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info)? };
+
+        let buffer_infos: Vec<vk::DescriptorBufferInfo> = buffers
+            .iter()
+            .map(|buffer| vk::DescriptorBufferInfo {
+                buffer: buffer.buffer,
+                offset: 0,
+                range: vk::WHOLE_SIZE,
+            })
+            .collect();
+        let writes: Vec<vk::WriteDescriptorSet> = buffer_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, info)| vk::WriteDescriptorSet {
+                s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+                dst_set: descriptor_sets[0],
+                dst_binding: binding as u32,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: info,
+                ..Default::default()
+            })
+            .collect();
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: 1,
+            ..Default::default()
+        };
+        let command_buffer = unsafe { device.allocate_command_buffers(&alloc_info)? }[0];
+
+        let begin_info = vk::CommandBufferBeginInfo {
+            s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            ..Default::default()
+        };
+
+        unsafe {
+            device.begin_command_buffer(command_buffer, &begin_info)?;
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &descriptor_sets,
+                &[],
+            );
+            if !push_constants.is_empty() {
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_constants,
+                );
+            }
+            device.cmd_dispatch(command_buffer, groups[0], groups[1], groups[2]);
+            device.end_command_buffer(command_buffer)?;
+
+            let submit_info = vk::SubmitInfo {
+                s_type: vk::StructureType::SUBMIT_INFO,
+                command_buffer_count: 1,
+                p_command_buffers: &command_buffer,
+                ..Default::default()
+            };
+            device.queue_submit(
+                context.queue,
+                std::slice::from_ref(&submit_info),
+                vk::Fence::null(),
+            )?;
+            device.queue_wait_idle(context.queue)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ComputeKernel {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.device.destroy_shader_module(self.shader_module, None);
+        }
+    }
+}
+
+/// Number of 16x16 workgroups needed to cover a `width` x `height` image
+pub fn image_groups(width: u32, height: u32) -> [u32; 3] {
+    [width.div_ceil(16), height.div_ceil(16), 1]
+}
+
+/// Run a single-pass image filter: RGBA8 `image_data` goes to binding 0, the
+/// `output_width` x `output_height` RGBA8 result is read back from binding 1
+pub fn run_image_kernel(
+    context: &VulkanContext,
+    kernel: &ComputeKernel,
+    image_data: &[u8],
+    output_width: u32,
+    output_height: u32,
+    push_constants: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let input = GpuBuffer::from_bytes(context, image_data)?;
+    let output = GpuBuffer::zeroed(context, output_width as usize * output_height as usize * 4)?;
+
+    kernel.dispatch(
+        context,
+        &[&input, &output],
+        push_constants,
+        image_groups(output_width, output_height),
+    )?;
+
+    output.read()
+}
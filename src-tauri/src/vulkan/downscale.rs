@@ -0,0 +1,327 @@
+//! Anti-aliased downscaling
+//!
+//! The upscale shader samples a fixed 4x4 neighbourhood, which skips most
+//! source pixels when the image shrinks and aliases badly. Here the kernel is
+//! stretched by the reduction ratio instead, so its footprint covers every
+//! source pixel that falls under an output pixel:
+//!
+//! - `area` averages the source pixels weighted by how much of each the output
+//!   pixel covers. No ringing; the softest result.
+//! - `mitchell` is Mitchell-Netravali stretched to 4 output pixels.
+//! - `auto` and `lanczos` use Lanczos-3 stretched to 6 output pixels, the
+//!   sharpest option.
+//!
+//! The filter is separable and runs as two passes, rows then columns, with a
+//! float image in between. Colours are premultiplied by alpha while filtered,
+//! so transparent pixels don't bleed their colour into the edges. [`downscale`]
+//! is the CPU reference for `shaders/downscale.comp`, which [`downscale_gpu`]
+//! runs.
+
+use super::compute::{image_groups, ComputeKernel, GpuBuffer};
+use super::{InterpolationKernel, VulkanContext};
+
+/// Kernel used by the downscaler, matching `filter_id` in `downscale.comp`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Filter {
+    Area,
+    Mitchell,
+    Lanczos3,
+}
+
+impl Filter {
+    fn from_kernel(kernel: InterpolationKernel) -> Result<Self, String> {
+        match kernel {
+            InterpolationKernel::Area => Ok(Filter::Area),
+            InterpolationKernel::Mitchell => Ok(Filter::Mitchell),
+            InterpolationKernel::Auto | InterpolationKernel::Lanczos => Ok(Filter::Lanczos3),
+            InterpolationKernel::EdgeDirected | InterpolationKernel::Fsr => Err(format!(
+                "The {:?} kernel only upscales; use area, mitchell or lanczos to downscale",
+                kernel
+            )),
+        }
+    }
+
+    fn shader_id(self) -> u32 {
+        match self {
+            Filter::Area => 0,
+            Filter::Mitchell => 1,
+            Filter::Lanczos3 => 2,
+        }
+    }
+
+    /// Half-width of the unstretched kernel
+    fn radius(self) -> f32 {
+        match self {
+            Filter::Area => 0.5,
+            Filter::Mitchell => 2.0,
+            Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    /// Weight of source pixel `i` for an output pixel centred at `center`,
+    /// both in source pixels, when the source shrinks by `ratio`
+    fn weight(self, i: i32, center: f32, ratio: f32) -> f32 {
+        if self == Filter::Area {
+            // Fraction of the source pixel covered by the output pixel
+            let start = (i as f32).max(center - 0.5 * ratio);
+            let end = (i as f32 + 1.0).min(center + 0.5 * ratio);
+            return (end - start).max(0.0);
+        }
+        let x = (i as f32 + 0.5 - center) / ratio;
+        match self {
+            Filter::Mitchell => mitchell_weight(x),
+            _ => lanczos3_weight(x),
+        }
+    }
+}
+
+fn mitchell_weight(x: f32) -> f32 {
+    let x = x.abs();
+    if x < 1.0 {
+        (16.0 + x * x * (21.0 * x - 36.0)) / 18.0
+    } else if x < 2.0 {
+        (32.0 + x * (-60.0 + x * (36.0 - 7.0 * x))) / 18.0
+    } else {
+        0.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    let x = std::f32::consts::PI * x;
+    x.sin() / x
+}
+
+fn lanczos3_weight(x: f32) -> f32 {
+    if x.abs() < 3.0 {
+        sinc(x) * sinc(x / 3.0)
+    } else {
+        0.0
+    }
+}
+
+/// Normalized taps of one output position along an axis: the clamped source
+/// index and its weight
+fn axis_taps(filter: Filter, out_pos: u32, out_size: u32, size: u32) -> Vec<(usize, f32)> {
+    let ratio = (size as f32 / out_size as f32).max(1.0);
+    let center = (out_pos as f32 + 0.5) * size as f32 / out_size as f32;
+    let support = filter.radius() * ratio;
+    let first = (center - support).floor() as i32;
+    let last = (center + support).ceil() as i32;
+
+    let mut taps: Vec<(usize, f32)> = (first..=last)
+        .filter_map(|i| {
+            let weight = filter.weight(i, center, ratio);
+            (weight != 0.0).then(|| (i.clamp(0, size as i32 - 1) as usize, weight))
+        })
+        .collect();
+    let weight_sum: f32 = taps.iter().map(|&(_, weight)| weight).sum();
+    if weight_sum > 0.0 {
+        for tap in &mut taps {
+            tap.1 /= weight_sum;
+        }
+    }
+    taps
+}
+
+fn check_sizes(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    output_width: u32,
+    output_height: u32,
+) -> Result<(), String> {
+    if width == 0 || height == 0 || image_data.len() != width as usize * height as usize * 4 {
+        return Err("Input image data does not match its dimensions".to_string());
+    }
+    if output_width == 0 || output_height == 0 || output_width > width || output_height > height {
+        return Err(format!(
+            "Cannot downscale {}x{} to {}x{}",
+            width, height, output_width, output_height
+        ));
+    }
+    Ok(())
+}
+
+/// Shrink RGBA8 `image_data` to `output_width` x `output_height` on the CPU
+pub fn downscale(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    output_width: u32,
+    output_height: u32,
+    kernel: InterpolationKernel,
+) -> Result<Vec<u8>, String> {
+    check_sizes(image_data, width, height, output_width, output_height)?;
+    let filter = Filter::from_kernel(kernel)?;
+    let (width, output_width) = (width as usize, output_width as usize);
+
+    // Rows: premultiplied floats, output_width x height
+    let mut rows = vec![[0.0f32; 4]; output_width * height as usize];
+    for x in 0..output_width {
+        let taps = axis_taps(filter, x as u32, output_width as u32, width as u32);
+        for y in 0..height as usize {
+            let mut sum = [0.0f32; 4];
+            for &(source_x, weight) in &taps {
+                let idx = (y * width + source_x) * 4;
+                let alpha = image_data[idx + 3] as f32 / 255.0;
+                for c in 0..3 {
+                    sum[c] += image_data[idx + c] as f32 / 255.0 * alpha * weight;
+                }
+                sum[3] += alpha * weight;
+            }
+            rows[y * output_width + x] = sum;
+        }
+    }
+
+    // Columns, then back to straight alpha
+    let mut output = vec![0u8; output_width * output_height as usize * 4];
+    for y in 0..output_height {
+        let taps = axis_taps(filter, y, output_height, height);
+        for x in 0..output_width {
+            let mut sum = [0.0f32; 4];
+            for &(source_y, weight) in &taps {
+                let value = rows[source_y * output_width + x];
+                for c in 0..4 {
+                    sum[c] += value[c] * weight;
+                }
+            }
+
+            // Lanczos lobes can overshoot; keep the premultiplied colour valid
+            let alpha = sum[3].clamp(0.0, 1.0);
+            let idx = (y as usize * output_width + x) * 4;
+            for c in 0..3 {
+                let value = sum[c].clamp(0.0, alpha);
+                let value = if alpha > 0.0 { value / alpha } else { value };
+                output[idx + c] = (value * 255.0 + 0.5) as u8;
+            }
+            output[idx + 3] = (alpha * 255.0 + 0.5) as u8;
+        }
+    }
+
+    Ok(output)
+}
+
+/// GPU version of [`downscale`]
+pub fn downscale_gpu(
+    context: &VulkanContext,
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    output_width: u32,
+    output_height: u32,
+    kernel: InterpolationKernel,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_sizes(image_data, width, height, output_width, output_height)?;
+    let filter = Filter::from_kernel(kernel)?;
+
+    let shader = ComputeKernel::new(
+        context,
+        include_bytes!("../../shaders/downscale.spv"),
+        3,
+        24,
+    )?;
+    let input = GpuBuffer::from_bytes(context, image_data)?;
+    // One vec4 of floats per pixel
+    let intermediate = GpuBuffer::zeroed(context, output_width as usize * height as usize * 16)?;
+    let output = GpuBuffer::zeroed(context, output_width as usize * output_height as usize * 4)?;
+
+    for (pass, groups) in [
+        image_groups(output_width, height),
+        image_groups(output_width, output_height),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let push_constants = [
+            width.to_ne_bytes(),
+            height.to_ne_bytes(),
+            output_width.to_ne_bytes(),
+            output_height.to_ne_bytes(),
+            filter.shader_id().to_ne_bytes(),
+            (pass as u32).to_ne_bytes(),
+        ]
+        .concat();
+        shader.dispatch(
+            context,
+            &[&input, &intermediate, &output],
+            &push_constants,
+            groups,
+        )?;
+    }
+
+    output.read()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KERNELS: [InterpolationKernel; 3] = [
+        InterpolationKernel::Area,
+        InterpolationKernel::Mitchell,
+        InterpolationKernel::Lanczos,
+    ];
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let pixel = [90u8, 160, 40, 200];
+        let image = pixel.repeat(13 * 9);
+        for kernel in KERNELS {
+            for (output_width, output_height) in [(6, 4), (5, 3), (1, 1)] {
+                let output = downscale(&image, 13, 9, output_width, output_height, kernel).unwrap();
+                assert_eq!(
+                    output,
+                    pixel.repeat((output_width * output_height) as usize),
+                    "{:?} to {}x{}",
+                    kernel,
+                    output_width,
+                    output_height
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn halving_with_area_averages_each_2x2_block() {
+        let (width, height) = (8, 6);
+        let image: Vec<u8> = (0..width * height * 4)
+            .map(|i| {
+                if i % 4 == 3 {
+                    255
+                } else {
+                    (i * 37 % 251) as u8
+                }
+            })
+            .collect();
+        let output = downscale(&image, 8, 6, 4, 3, InterpolationKernel::Area).unwrap();
+
+        for y in 0..3 {
+            for x in 0..4 {
+                for c in 0..3 {
+                    let block: u32 = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .iter()
+                        .map(|(dx, dy)| image[((2 * y + dy) * width + 2 * x + dx) * 4 + c] as u32)
+                        .sum();
+                    let mean = block as f32 / 4.0;
+                    let value = output[(y * 4 + x) * 4 + c] as f32;
+                    assert!((value - mean).abs() <= 0.5, "({}, {}) channel {}", x, y, c);
+                }
+                assert_eq!(output[(y * 4 + x) * 4 + 3], 255);
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_colour_does_not_bleed() {
+        let (red, blue) = ([255, 0, 0, 0], [0, 0, 255, 255]);
+        let image = [red, blue, blue, blue].concat();
+        for kernel in KERNELS {
+            let output = downscale(&image, 2, 2, 1, 1, kernel).unwrap();
+            assert_eq!(output[..3], [0, 0, 255], "{:?}", kernel);
+            assert!(output[3] > 0 && output[3] < 255, "{:?}", kernel);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! AMD FidelityFX Super Resolution 1 (EASU + RCAS)
+//!
+//! Port of the scalar float path of `ffx_fsr1.h` (MIT licensed, Copyright (c)
+//! 2021 Advanced Micro Devices, Inc.) to still images:
+//!
+//! - EASU (edge-adaptive spatial upsampling) analyses the luma gradients of
+//!   the 2x2 source cell around each output pixel and shapes an approximate
+//!   Lanczos-2 kernel over a 12-tap window: stretched along edges, with a
+//!   stronger negative lobe on them. The result is clamped to the 2x2 cell to
+//!   avoid ringing. It works for any output size, not just integer factors.
+//! - RCAS (robust contrast-adaptive sharpening) sharpens with a 5-tap cross
+//!   whose strength is limited per pixel so it can't clip, and is reduced in
+//!   noisy areas.
+//!
+//! The original approximated reciprocals are replaced by exact ones. Alpha is
+//! filtered with the colour weights by EASU and passed through by RCAS.
+//! [`easu`] and [`rcas`] are the CPU references for `shaders/easu.comp` and
+//! `shaders/rcas.comp`, which [`easu_gpu`] and [`rcas_gpu`] run.
+
+use super::compute::{run_image_kernel, ComputeKernel};
+use super::VulkanContext;
+
+/// Strongest negative lobe RCAS may use
+const RCAS_LIMIT: f32 = 0.25 - 1.0 / 16.0;
+
+/// Smallest value passed to a reciprocal, so flat areas don't divide by zero
+const RCP_EPSILON: f32 = 1.0 / 32768.0;
+
+fn load_pixel(image_data: &[u8], width: u32, height: u32, x: i32, y: i32) -> [f32; 4] {
+    let x = x.clamp(0, width as i32 - 1) as usize;
+    let y = y.clamp(0, height as i32 - 1) as usize;
+    let idx = (y * width as usize + x) * 4;
+    [
+        image_data[idx] as f32 / 255.0,
+        image_data[idx + 1] as f32 / 255.0,
+        image_data[idx + 2] as f32 / 255.0,
+        image_data[idx + 3] as f32 / 255.0,
+    ]
+}
+
+/// FSR's cheap luma approximation (luma times 2)
+fn luma(pixel: [f32; 4]) -> f32 {
+    pixel[2] * 0.5 + (pixel[0] * 0.5 + pixel[1])
+}
+
+fn check_dimensions(image_data: &[u8], width: u32, height: u32) -> Result<(), String> {
+    if width == 0 || height == 0 || image_data.len() != width as usize * height as usize * 4 {
+        return Err("Input image data does not match its dimensions".to_string());
+    }
+    Ok(())
+}
+
+/// Add the direction and edge length of one corner of the 2x2 cell, weighted
+/// bilinearly. `a`..`e` are the lumas of the '+' around the corner:
+///
+/// ```text
+///   a
+/// b c d
+///   e
+/// ```
+fn easu_set(dir: &mut [f32; 2], len: &mut f32, weight: f32, lumas: [f32; 5]) {
+    let [a, b, c, d, e] = lumas;
+
+    // Direction is the '+' difference; length turns gradient reversal into 0
+    let len_x = (d - c).abs().max((c - b).abs()).max(RCP_EPSILON);
+    let dir_x = d - b;
+    dir[0] += dir_x * weight;
+    let len_x = (dir_x.abs() / len_x).clamp(0.0, 1.0);
+    *len += len_x * len_x * weight;
+
+    let len_y = (e - c).abs().max((c - a).abs()).max(RCP_EPSILON);
+    let dir_y = e - a;
+    dir[1] += dir_y * weight;
+    let len_y = (dir_y.abs() / len_y).clamp(0.0, 1.0);
+    *len += len_y * len_y * weight;
+}
+
+/// Weight of one tap of the shaped Lanczos-2 approximation
+fn easu_tap_weight(offset: [f32; 2], dir: [f32; 2], len: [f32; 2], lobe: f32, clip: f32) -> f32 {
+    // Rotate the offset into the edge direction and apply the anisotropy
+    let vx = (offset[0] * dir[0] + offset[1] * dir[1]) * len[0];
+    let vy = (offset[0] * -dir[1] + offset[1] * dir[0]) * len[1];
+    let d2 = (vx * vx + vy * vy).min(clip);
+
+    // (25/16 * (2/5 * x^2 - 1)^2 - (25/16 - 1)) * (lobe * x^2 - 1)^2
+    let base = 2.0 / 5.0 * d2 - 1.0;
+    let window = lobe * d2 - 1.0;
+    (25.0 / 16.0 * base * base - (25.0 / 16.0 - 1.0)) * window * window
+}
+
+/// Upscale RGBA8 pixels to `output_width` x `output_height` with EASU
+pub fn easu(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    output_width: u32,
+    output_height: u32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_dimensions(image_data, width, height)?;
+    if output_width == 0 || output_height == 0 {
+        return Err("Output size must be at least 1x1".into());
+    }
+
+    let scale_x = width as f32 / output_width as f32;
+    let scale_y = height as f32 / output_height as f32;
+    let mut output = vec![0u8; output_width as usize * output_height as usize * 4];
+
+    for out_y in 0..output_height {
+        for out_x in 0..output_width {
+            let px = out_x as f32 * scale_x + (0.5 * scale_x - 0.5);
+            let py = out_y as f32 * scale_y + (0.5 * scale_y - 0.5);
+            let (fx, fy) = (px.floor(), py.floor());
+            let (ppx, ppy) = (px - fx, py - fy);
+            let (ix, iy) = (fx as i32, fy as i32);
+
+            // 12-tap window around the 2x2 cell f g / j k:
+            //     b c
+            //   e f g h
+            //   i j k l
+            //     n o
+            let tap = |x: i32, y: i32| load_pixel(image_data, width, height, ix + x, iy + y);
+            let taps = [
+                ((0, -1), tap(0, -1)),
+                ((1, -1), tap(1, -1)),
+                ((-1, 0), tap(-1, 0)),
+                ((0, 0), tap(0, 0)),
+                ((1, 0), tap(1, 0)),
+                ((2, 0), tap(2, 0)),
+                ((-1, 1), tap(-1, 1)),
+                ((0, 1), tap(0, 1)),
+                ((1, 1), tap(1, 1)),
+                ((2, 1), tap(2, 1)),
+                ((0, 2), tap(0, 2)),
+                ((1, 2), tap(1, 2)),
+            ];
+            let [b, c, e, f, g, h, i, j, k, l, n, o] = taps.map(|(_, pixel)| luma(pixel));
+
+            let mut dir = [0.0f32; 2];
+            let mut len = 0.0f32;
+            easu_set(
+                &mut dir,
+                &mut len,
+                (1.0 - ppx) * (1.0 - ppy),
+                [b, e, f, g, j],
+            );
+            easu_set(&mut dir, &mut len, ppx * (1.0 - ppy), [c, f, g, h, k]);
+            easu_set(&mut dir, &mut len, (1.0 - ppx) * ppy, [f, i, j, k, n]);
+            easu_set(&mut dir, &mut len, ppx * ppy, [g, j, k, l, o]);
+
+            // Normalize the direction, falling back to horizontal when flat
+            let dir_r = dir[0] * dir[0] + dir[1] * dir[1];
+            if dir_r < RCP_EPSILON {
+                dir = [1.0, 0.0];
+            } else {
+                let inv = 1.0 / dir_r.sqrt();
+                dir = [dir[0] * inv, dir[1] * inv];
+            }
+
+            // Map length from 0..2 to 0..1 and shape it
+            let len = (len * 0.5) * (len * 0.5);
+            // Stretch from 1 on horizontal/vertical edges to sqrt(2) on diagonals
+            let stretch = (dir[0] * dir[0] + dir[1] * dir[1]) / dir[0].abs().max(dir[1].abs());
+            let len2 = [1.0 + (stretch - 1.0) * len, 1.0 - 0.5 * len];
+            // The negative lobe grows with the amount of edge
+            let lobe = 0.5 + ((1.0 / 4.0 - 0.04) - 0.5) * len;
+            let clip = 1.0 / lobe;
+
+            let mut sum = [0.0f32; 4];
+            let mut weight_sum = 0.0f32;
+            for ((x, y), pixel) in &taps {
+                let offset = [*x as f32 - ppx, *y as f32 - ppy];
+                let weight = easu_tap_weight(offset, dir, len2, lobe, clip);
+                for ch in 0..4 {
+                    sum[ch] += pixel[ch] * weight;
+                }
+                weight_sum += weight;
+            }
+
+            // Dering: clamp to the range of the 2x2 cell
+            let cell = [taps[3].1, taps[4].1, taps[7].1, taps[8].1];
+            let idx = (out_y as usize * output_width as usize + out_x as usize) * 4;
+            for ch in 0..4 {
+                let min = cell.iter().map(|p| p[ch]).fold(f32::MAX, f32::min);
+                let max = cell.iter().map(|p| p[ch]).fold(f32::MIN, f32::max);
+                let value = (sum[ch] / weight_sum).clamp(min, max);
+                output[idx + ch] = (value * 255.0 + 0.5) as u8;
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Sharpen RGBA8 pixels with RCAS. `sharpness` is in stops: `0.0` is the
+/// strongest, every `1.0` halves the effect.
+pub fn rcas(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    sharpness: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_dimensions(image_data, width, height)?;
+
+    let sharpness = (-sharpness).exp2();
+    let mut output = Vec::with_capacity(image_data.len());
+
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            //   b
+            // d e f
+            //   h
+            let b = load_pixel(image_data, width, height, x, y - 1);
+            let d = load_pixel(image_data, width, height, x - 1, y);
+            let e = load_pixel(image_data, width, height, x, y);
+            let f = load_pixel(image_data, width, height, x + 1, y);
+            let h = load_pixel(image_data, width, height, x, y + 1);
+
+            // Noise detection: a pixel far from its ring average is treated as noise
+            let (bl, dl, el, fl, hl) = (luma(b), luma(d), luma(e), luma(f), luma(h));
+            let range = bl.max(dl).max(el).max(fl).max(hl) - bl.min(dl).min(el).min(fl).min(hl);
+            let noise = (0.25 * (bl + dl + fl + hl) - el).abs() / range.max(RCP_EPSILON);
+            let noise = -0.5 * noise.clamp(0.0, 1.0) + 1.0;
+
+            // Largest negative lobe that doesn't push any channel out of 0..1
+            let mut lobe = f32::MIN;
+            for ch in 0..3 {
+                let ring_min = b[ch].min(d[ch]).min(f[ch]).min(h[ch]);
+                let ring_max = b[ch].max(d[ch]).max(f[ch]).max(h[ch]);
+                let hit_min = ring_min.min(e[ch]) / (4.0 * ring_max).max(RCP_EPSILON);
+                let hit_max =
+                    (1.0 - ring_max.max(e[ch])) / (4.0 * ring_min - 4.0).min(-RCP_EPSILON);
+                lobe = lobe.max((-hit_min).max(hit_max));
+            }
+            let lobe = lobe.clamp(-RCAS_LIMIT, 0.0) * sharpness * noise;
+
+            let rcp = 1.0 / (4.0 * lobe + 1.0);
+            for ch in 0..3 {
+                let value = (lobe * (b[ch] + d[ch] + f[ch] + h[ch]) + e[ch]) * rcp;
+                output.push((value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8);
+            }
+            output.push((e[3] * 255.0 + 0.5) as u8);
+        }
+    }
+
+    Ok(output)
+}
+
+/// GPU version of [`easu`]
+pub fn easu_gpu(
+    context: &VulkanContext,
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    output_width: u32,
+    output_height: u32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_dimensions(image_data, width, height)?;
+    if output_width == 0 || output_height == 0 {
+        return Err("Output size must be at least 1x1".into());
+    }
+
+    let kernel = ComputeKernel::new(context, include_bytes!("../../shaders/easu.spv"), 2, 16)?;
+    let push_constants = [
+        width.to_ne_bytes(),
+        height.to_ne_bytes(),
+        output_width.to_ne_bytes(),
+        output_height.to_ne_bytes(),
+    ]
+    .concat();
+
+    run_image_kernel(
+        context,
+        &kernel,
+        image_data,
+        output_width,
+        output_height,
+        &push_constants,
+    )
+}
+
+/// GPU version of [`rcas`]
+pub fn rcas_gpu(
+    context: &VulkanContext,
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    sharpness: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_dimensions(image_data, width, height)?;
+
+    let kernel = ComputeKernel::new(context, include_bytes!("../../shaders/rcas.spv"), 2, 12)?;
+    let push_constants = [
+        width.to_ne_bytes(),
+        height.to_ne_bytes(),
+        (-sharpness).exp2().to_ne_bytes(),
+    ]
+    .concat();
+
+    run_image_kernel(context, &kernel, image_data, width, height, &push_constants)
+}
@@ -0,0 +1,204 @@
+//! CPU reference for `shaders/upscale.comp`
+//!
+//! [`upscale`] follows the shader step by step in `f32`: the same coordinate
+//! mapping, the same 4x4 neighbourhood with clamp-to-edge sampling, the same
+//! weight expressions (including the shader's truncated pi) and the same
+//! packing, so it matches [`super::upscale_rgba`] to within the rounding of
+//! the GPU's `sin`. It is used by the golden-image tests; the app always
+//! upscales on the GPU.
+
+use super::InterpolationKernel;
+
+/// The shader's value of pi; kept as is so the Lanczos weights match
+#[allow(clippy::approx_constant)]
+const SHADER_PI: f32 = 3.14159;
+
+/// Mitchell-Netravali (B = C = 1/3), as `mitchell_weight`
+fn mitchell_weight(x: f32) -> f32 {
+    let x = x.abs();
+    if x < 1.0 {
+        (16.0 + x * x * (21.0 * x - 36.0)) / 18.0
+    } else if x < 2.0 {
+        (32.0 + x * (-60.0 + x * (36.0 - 7.0 * x))) / 18.0
+    } else {
+        0.0
+    }
+}
+
+/// Lanczos-2, as the inline expression in `lanczos_interpolate`
+fn lanczos_weight(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (2.0 * (SHADER_PI * x).sin() * (SHADER_PI * x / 2.0).sin())
+            / (SHADER_PI * SHADER_PI * x * x)
+    }
+}
+
+/// Source pixel at (x, y) clamped to the image, as `sample_pixel`
+fn sample_pixel(image_data: &[u8], width: u32, height: u32, x: i32, y: i32) -> [f32; 4] {
+    let x = x.clamp(0, width as i32 - 1) as usize;
+    let y = y.clamp(0, height as i32 - 1) as usize;
+    let index = (y * width as usize + x) * 4;
+    let mut pixel = [0.0; 4];
+    for (channel, value) in pixel.iter_mut().enumerate() {
+        *value = image_data[index + channel] as f32 / 255.0;
+    }
+    pixel
+}
+
+/// Filter the 4x4 neighbourhood of (fx, fy), as `bicubic_interpolate` and
+/// `lanczos_interpolate`. Lanczos divides by the weight sum, Mitchell
+/// doesn't.
+fn interpolate(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    fx: f32,
+    fy: f32,
+    lanczos: bool,
+) -> [f32; 4] {
+    let ix = fx.floor() as i32;
+    let iy = fy.floor() as i32;
+    let dx = fx - ix as f32;
+    let dy = fy - iy as f32;
+
+    let mut result = [0.0f32; 4];
+    let mut weight_sum = 0.0f32;
+    for j in -1..=2 {
+        for i in -1..=2 {
+            let dist_x = i as f32 - dx;
+            let dist_y = j as f32 - dy;
+            let weight = if lanczos {
+                lanczos_weight(dist_x) * lanczos_weight(dist_y)
+            } else {
+                mitchell_weight(dist_x) * mitchell_weight(dist_y)
+            };
+            let pixel = sample_pixel(image_data, width, height, ix + i, iy + j);
+            for (value, sample) in result.iter_mut().zip(pixel) {
+                *value += sample * weight;
+            }
+            weight_sum += weight;
+        }
+    }
+
+    if lanczos && weight_sum > 0.0 {
+        for value in &mut result {
+            *value /= weight_sum;
+        }
+    }
+    result.map(|value| value.clamp(0.0, 1.0))
+}
+
+/// Upscale RGBA8 pixels by `factor` with `kernel` the way `upscale.comp`
+/// does. Only the kernels the shader implements are accepted: `auto`,
+/// `mitchell` and `lanczos`.
+pub fn upscale(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    factor: u32,
+    kernel: InterpolationKernel,
+) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 || image_data.len() != width as usize * height as usize * 4 {
+        return Err("Input image data does not match its dimensions".to_string());
+    }
+    if factor == 0 {
+        return Err("Upscale factor must be at least 1".to_string());
+    }
+    let lanczos = match kernel {
+        InterpolationKernel::Auto => factor >= 4,
+        InterpolationKernel::Mitchell => false,
+        InterpolationKernel::Lanczos => true,
+        other => {
+            return Err(format!(
+                "The {:?} kernel doesn't run in upscale.comp",
+                other
+            ))
+        }
+    };
+
+    let output_width = width * factor;
+    let output_height = height * factor;
+    let mut output = Vec::with_capacity(output_width as usize * output_height as usize * 4);
+    for out_y in 0..output_height {
+        let in_y = (out_y as f32 + 0.5) / factor as f32 - 0.5;
+        for out_x in 0..output_width {
+            let in_x = (out_x as f32 + 0.5) / factor as f32 - 0.5;
+            let color = interpolate(image_data, width, height, in_x, in_y, lanczos);
+            output.extend(color.map(|value| (value * 255.0 + 0.5) as u8));
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 4x3 image with a different colour and alpha in every pixel
+    fn varied_image() -> Vec<u8> {
+        (0..12u8)
+            .flat_map(|i| [i * 21, 255 - i * 17, (i * 73) % 251, 128 + i * 10])
+            .collect()
+    }
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let image = [90u8, 160, 220, 200].repeat(5 * 5);
+        for kernel in [InterpolationKernel::Mitchell, InterpolationKernel::Lanczos] {
+            for factor in [2, 3, 4] {
+                let output = upscale(&image, 5, 5, factor, kernel).unwrap();
+                assert_eq!(output.len(), (5 * factor * 5 * factor * 4) as usize);
+                assert!(
+                    output.chunks_exact(4).all(|p| p == [90, 160, 220, 200]),
+                    "{:?} at {}x changed a flat image",
+                    kernel,
+                    factor
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn auto_switches_to_lanczos_at_4x() {
+        let image = varied_image();
+        for factor in [2, 3] {
+            assert_eq!(
+                upscale(&image, 4, 3, factor, InterpolationKernel::Auto),
+                upscale(&image, 4, 3, factor, InterpolationKernel::Mitchell)
+            );
+        }
+        for factor in [4, 8] {
+            assert_eq!(
+                upscale(&image, 4, 3, factor, InterpolationKernel::Auto),
+                upscale(&image, 4, 3, factor, InterpolationKernel::Lanczos)
+            );
+        }
+    }
+
+    #[test]
+    fn lanczos_at_1x_is_the_identity() {
+        let image = varied_image();
+        let output = upscale(&image, 4, 3, 1, InterpolationKernel::Lanczos).unwrap();
+        assert_eq!(output, image);
+    }
+
+    #[test]
+    fn single_pixel_images_are_replicated() {
+        for kernel in [InterpolationKernel::Mitchell, InterpolationKernel::Lanczos] {
+            let output = upscale(&[12, 34, 56, 78], 1, 1, 3, kernel).unwrap();
+            assert_eq!(output, [12, 34, 56, 78].repeat(9));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let image = varied_image();
+        assert!(upscale(&image, 4, 4, 2, InterpolationKernel::Auto).is_err());
+        assert!(upscale(&[], 0, 0, 2, InterpolationKernel::Auto).is_err());
+        assert!(upscale(&image, 4, 3, 0, InterpolationKernel::Auto).is_err());
+        assert!(upscale(&image, 4, 3, 2, InterpolationKernel::Fsr).is_err());
+        assert!(upscale(&image, 4, 3, 2, InterpolationKernel::EdgeDirected).is_err());
+    }
+}
@@ -0,0 +1,393 @@
+//! Line-art refinement for anime frames and flat-shaded illustrations
+//!
+//! Interpolation smears thin dark outlines into wide grey ramps. After the
+//! normal upscale, this module runs a chain of passes in the style of the
+//! original Anime4K (0.9):
+//!
+//! 1. **Line thinning** (`line_art_thin.comp`): a pixel with darker
+//!    neighbours on one side and lighter ones on the other is pushed towards
+//!    the lighter side, which eats into the blurred outline from both edges.
+//! 2. **Gradient** (`line_art_gradient.comp`): Sobel magnitude of the
+//!    luminance, stored as `1 - |gradient|` so flat areas are high.
+//! 3. **Gradient refinement** (`line_art_refine.comp`): the same
+//!    neighbourhood patterns on the gradient map. A pixel on the soft part of
+//!    an edge takes the colour of the flat side next to it, which makes the
+//!    transition a pixel or two wide again.
+//!
+//! Thinning and refinement can be repeated for stronger results. [`process`]
+//! is the CPU reference for [`process_gpu`].
+
+use super::compute::{image_groups, ComputeKernel, GpuBuffer};
+use super::VulkanContext;
+use serde::{Deserialize, Serialize};
+
+/// Strengths of the line-art passes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LineArtSettings {
+    /// How far a pixel next to a line moves towards its lighter neighbours,
+    /// `0.0..=1.0`. Anime4K uses `factor / 6`. Default: `0.33`.
+    pub thinning: f32,
+    /// How far a pixel on a soft edge moves towards the flat side,
+    /// `0.0..=1.0`. Anime4K uses `factor / 2`. Default: `1.0`.
+    pub refinement: f32,
+    /// Number of thinning + refinement rounds, `1..=4`. Default: `1`.
+    pub passes: u32,
+}
+
+impl Default for LineArtSettings {
+    fn default() -> Self {
+        LineArtSettings {
+            thinning: 0.33,
+            refinement: 1.0,
+            passes: 1,
+        }
+    }
+}
+
+impl LineArtSettings {
+    /// Check that every parameter is inside its documented range
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [("thinning", self.thinning), ("refinement", self.refinement)] {
+            if !value.is_finite() || !(0.0..=1.0).contains(&value) {
+                return Err(format!(
+                    "Invalid line art {}: {}. Must be between 0 and 1.",
+                    name, value
+                ));
+            }
+        }
+        if self.passes == 0 || self.passes > 4 {
+            return Err(format!(
+                "Invalid line art passes: {}. Must be between 1 and 4.",
+                self.passes
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 3x3 neighbourhood, indexed `[row][column]`
+type Window<T> = [[T; 3]; 3];
+
+/// Three (row, column) positions in a [`Window`]
+type Positions = [(usize, usize); 3];
+
+/// The two neighbourhood patterns, as (row, column) positions for the
+/// unrotated case. Pattern A: lighter row above, darker row below. Pattern B:
+/// lighter top-right corner, darker bottom-left corner including the centre.
+/// Each is checked in four 90° rotations.
+const PATTERN_A_LIGHT: Positions = [(0, 0), (0, 1), (0, 2)];
+const PATTERN_A_DARK: Positions = [(2, 0), (2, 1), (2, 2)];
+const PATTERN_B_LIGHT: Positions = [(1, 2), (0, 1), (0, 2)];
+const PATTERN_B_DARK: Positions = [(1, 1), (1, 0), (2, 1)];
+
+/// Rotate a window position by `rotation` quarter turns
+fn rotate((row, column): (usize, usize), rotation: usize) -> (usize, usize) {
+    (0..rotation).fold((row, column), |(r, c), _| (c, 2 - r))
+}
+
+/// Patterns in the order they're tested: for each rotation, A then B. The
+/// flag marks pattern A, which also requires the centre to be darker than the
+/// light side.
+fn patterns() -> impl Iterator<Item = (Positions, Positions, bool)> {
+    (0..4).flat_map(|rotation| {
+        let rotated = move |positions: Positions| positions.map(|p| rotate(p, rotation));
+        [
+            (rotated(PATTERN_A_LIGHT), rotated(PATTERN_A_DARK), true),
+            (rotated(PATTERN_B_LIGHT), rotated(PATTERN_B_DARK), false),
+        ]
+    })
+}
+
+/// Whether the `light` side of `values` is strictly lighter than the `dark`
+/// side (and, for pattern A, than the centre)
+fn matches(values: &Window<f32>, light: &Positions, dark: &Positions, check_centre: bool) -> bool {
+    let min_light = light
+        .iter()
+        .map(|&(r, c)| values[r][c])
+        .fold(f32::MAX, f32::min);
+    let max_dark = dark
+        .iter()
+        .map(|&(r, c)| values[r][c])
+        .fold(f32::MIN, f32::max);
+    min_light > max_dark && (!check_centre || min_light > values[1][1])
+}
+
+/// Centre colour moved `strength` of the way towards the average of `light`
+fn push_towards(window: &Window<[f32; 4]>, light: &Positions, strength: f32) -> [f32; 4] {
+    let centre = window[1][1];
+    let mut result = centre;
+    for ch in 0..3 {
+        let average = light.iter().map(|&(r, c)| window[r][c][ch]).sum::<f32>() / 3.0;
+        result[ch] = centre[ch] + (average - centre[ch]) * strength;
+    }
+    result
+}
+
+/// Anime4K's luminance weighting, 0-1 for 0-255 input
+fn luminance(pixel: [f32; 4]) -> f32 {
+    (pixel[0] * 2.0 + pixel[1] * 3.0 + pixel[2]) / (6.0 * 255.0)
+}
+
+fn load_pixel(image_data: &[u8], width: u32, height: u32, x: i32, y: i32) -> [f32; 4] {
+    let x = x.clamp(0, width as i32 - 1) as usize;
+    let y = y.clamp(0, height as i32 - 1) as usize;
+    let idx = (y * width as usize + x) * 4;
+    [
+        image_data[idx] as f32,
+        image_data[idx + 1] as f32,
+        image_data[idx + 2] as f32,
+        image_data[idx + 3] as f32,
+    ]
+}
+
+fn load_window(image_data: &[u8], width: u32, height: u32, x: i32, y: i32) -> Window<[f32; 4]> {
+    let mut window = [[[0.0; 4]; 3]; 3];
+    for (row, line) in window.iter_mut().enumerate() {
+        for (column, pixel) in line.iter_mut().enumerate() {
+            *pixel = load_pixel(
+                image_data,
+                width,
+                height,
+                x + column as i32 - 1,
+                y + row as i32 - 1,
+            );
+        }
+    }
+    window
+}
+
+fn store_pixel(output: &mut Vec<u8>, pixel: [f32; 4]) {
+    output.extend(pixel.map(|value| (value + 0.5).clamp(0.0, 255.0) as u8));
+}
+
+fn check_dimensions(image_data: &[u8], width: u32, height: u32) -> Result<(), String> {
+    if width == 0 || height == 0 || image_data.len() != width as usize * height as usize * 4 {
+        return Err("Input image data does not match its dimensions".to_string());
+    }
+    Ok(())
+}
+
+/// Line thinning pass. Of all matching patterns, the pushed colour with the
+/// highest luminance wins. Alpha is preserved.
+pub fn thin_lines(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    strength: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_dimensions(image_data, width, height)?;
+
+    let mut output = Vec::with_capacity(image_data.len());
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let window = load_window(image_data, width, height, x, y);
+            let lumas = window.map(|row| row.map(luminance));
+
+            let mut lightest = window[1][1];
+            let mut lightest_luma = lumas[1][1];
+            for (light, dark, check_centre) in patterns() {
+                if matches(&lumas, &light, &dark, check_centre) {
+                    let candidate = push_towards(&window, &light, strength);
+                    if luminance(candidate) > lightest_luma {
+                        lightest_luma = luminance(candidate);
+                        lightest = candidate;
+                    }
+                }
+            }
+            store_pixel(&mut output, lightest);
+        }
+    }
+    Ok(output)
+}
+
+/// Gradient pass: `1 - |sobel(luminance)|` per pixel, clamped to 0-1
+pub fn gradient(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    check_dimensions(image_data, width, height)?;
+
+    let mut output = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let l = load_window(image_data, width, height, x, y).map(|row| row.map(luminance));
+            let gx = -l[0][0] - 2.0 * l[1][0] - l[2][0] + l[0][2] + 2.0 * l[1][2] + l[2][2];
+            let gy = -l[0][0] - 2.0 * l[0][1] - l[0][2] + l[2][0] + 2.0 * l[2][1] + l[2][2];
+            output.push(1.0 - (gx * gx + gy * gy).sqrt().clamp(0.0, 1.0));
+        }
+    }
+    Ok(output)
+}
+
+/// Gradient refinement pass. `gradient` comes from [`gradient`]; the first
+/// matching pattern decides the colour. Alpha is preserved.
+pub fn refine(
+    image_data: &[u8],
+    gradient: &[f32],
+    width: u32,
+    height: u32,
+    strength: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_dimensions(image_data, width, height)?;
+    if gradient.len() != width as usize * height as usize {
+        return Err("Gradient does not match the image dimensions".into());
+    }
+
+    let load_gradient = |x: i32, y: i32| {
+        let x = x.clamp(0, width as i32 - 1) as usize;
+        let y = y.clamp(0, height as i32 - 1) as usize;
+        gradient[y * width as usize + x]
+    };
+
+    let mut output = Vec::with_capacity(image_data.len());
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let window = load_window(image_data, width, height, x, y);
+            let mut gradients = [[0.0f32; 3]; 3];
+            for (row, line) in gradients.iter_mut().enumerate() {
+                for (column, value) in line.iter_mut().enumerate() {
+                    *value = load_gradient(x + column as i32 - 1, y + row as i32 - 1);
+                }
+            }
+
+            let pixel = patterns()
+                .find(|(light, dark, check_centre)| matches(&gradients, light, dark, *check_centre))
+                .map(|(light, _, _)| push_towards(&window, &light, strength))
+                .unwrap_or(window[1][1]);
+            store_pixel(&mut output, pixel);
+        }
+    }
+    Ok(output)
+}
+
+/// Run the thinning and refinement rounds on an already upscaled image
+pub fn process(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    settings: &LineArtSettings,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    settings.validate()?;
+
+    let mut image_data = image_data.to_vec();
+    for _ in 0..settings.passes {
+        let thinned = thin_lines(&image_data, width, height, settings.thinning)?;
+        let gradient = gradient(&thinned, width, height)?;
+        image_data = refine(&thinned, &gradient, width, height, settings.refinement)?;
+    }
+    Ok(image_data)
+}
+
+/// GPU version of [`process`]. The image stays on the GPU between passes.
+pub fn process_gpu(
+    context: &VulkanContext,
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    settings: &LineArtSettings,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    settings.validate()?;
+    check_dimensions(image_data, width, height)?;
+
+    let thin_kernel = ComputeKernel::new(
+        context,
+        include_bytes!("../../shaders/line_art_thin.spv"),
+        2,
+        12,
+    )?;
+    let gradient_kernel = ComputeKernel::new(
+        context,
+        include_bytes!("../../shaders/line_art_gradient.spv"),
+        2,
+        8,
+    )?;
+    let refine_kernel = ComputeKernel::new(
+        context,
+        include_bytes!("../../shaders/line_art_refine.spv"),
+        3,
+        12,
+    )?;
+
+    let image = GpuBuffer::from_bytes(context, image_data)?;
+    let thinned = GpuBuffer::zeroed(context, image_data.len())?;
+    let gradient = GpuBuffer::zeroed(context, width as usize * height as usize * 4)?;
+
+    let size = [width.to_ne_bytes(), height.to_ne_bytes()].concat();
+    let thin_push_constants = [size.clone(), settings.thinning.to_ne_bytes().to_vec()].concat();
+    let refine_push_constants = [size.clone(), settings.refinement.to_ne_bytes().to_vec()].concat();
+    let groups = image_groups(width, height);
+
+    for _ in 0..settings.passes {
+        thin_kernel.dispatch(context, &[&image, &thinned], &thin_push_constants, groups)?;
+        gradient_kernel.dispatch(context, &[&thinned, &gradient], &size, groups)?;
+        refine_kernel.dispatch(
+            context,
+            &[&thinned, &gradient, &image],
+            &refine_push_constants,
+            groups,
+        )?;
+    }
+
+    image.read()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16x8 white image with a vertical dark outline blurred over five
+    /// columns
+    fn blurred_outline() -> (Vec<u8>, u32, u32) {
+        let profile = [
+            255, 255, 255, 255, 255, 192, 96, 0, 96, 192, 255, 255, 255, 255, 255, 255,
+        ];
+        let (width, height) = (profile.len() as u32, 8);
+        let data = (0..height)
+            .flat_map(|_| profile.iter().flat_map(|&v| [v, v, v, 255]))
+            .collect();
+        (data, width, height)
+    }
+
+    /// Columns of the middle row noticeably darker than the background
+    fn dark_columns(data: &[u8], width: u32) -> usize {
+        let row = 4 * width as usize * 4;
+        data[row..row + width as usize * 4]
+            .chunks_exact(4)
+            .filter(|pixel| pixel[0] < 224)
+            .count()
+    }
+
+    #[test]
+    fn leaves_flat_images_unchanged() {
+        let data: Vec<u8> = [90, 140, 200, 128].repeat(7 * 5);
+        let settings = LineArtSettings {
+            passes: 4,
+            ..Default::default()
+        };
+        assert_eq!(process(&data, 7, 5, &settings).unwrap(), data);
+    }
+
+    #[test]
+    fn narrows_blurred_outlines() {
+        let (data, width, height) = blurred_outline();
+        let output = process(&data, width, height, &LineArtSettings::default()).unwrap();
+        assert!(dark_columns(&output, width) < dark_columns(&data, width));
+        // The line itself survives
+        let centre = (4 * width as usize + 7) * 4;
+        assert_eq!(output[centre..centre + 4], [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn rejects_out_of_range_passes() {
+        for passes in [0, 5] {
+            let settings = LineArtSettings {
+                passes,
+                ..Default::default()
+            };
+            assert!(settings.validate().is_err(), "passes {}", passes);
+        }
+        assert!(LineArtSettings::default().validate().is_ok());
+    }
+}
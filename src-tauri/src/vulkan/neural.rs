@@ -0,0 +1,1100 @@
+//! Convolutional super-resolution networks on Vulkan compute
+//!
+//! A small inference engine for the networks behind Real-ESRGAN, Real-CUGAN
+//! and similar upscalers. A [`Network`] is loaded from local weight files
+//! (see [`ncnn`]) and evaluated layer by layer with one compute shader per
+//! layer type:
+//!
+//! - `nn_conv.comp`: 2D convolution with groups, dilation, stride and an
+//!   optional fused activation
+//! - `nn_prelu.comp`: PReLU and leaky ReLU
+//! - `nn_pixel_shuffle.comp`: depth-to-space upscaling
+//! - `nn_upsample.comp`: nearest-neighbour upsampling
+//! - `nn_binary.comp`: element-wise add/sub/mul/... for skip connections
+//!
+//! Tensors are planar `f32` storage buffers and every shader sticks to core
+//! Vulkan 1.0 features (no fp16, int8 or subgroup operations), so a network
+//! runs unchanged on software implementations such as lavapipe.
+//!
+//! Whole images don't fit in memory at the feature-map resolutions these
+//! networks use, so [`upscale_gpu`] splits the image into overlapping tiles
+//! and shrinks the tiles until the largest intermediate tensor fits the
+//! device's storage buffer limit. [`Network::forward_cpu`] is the CPU
+//! reference for [`GpuNetwork::forward`].
+
+pub mod ncnn;
+
+use super::compute::{ComputeKernel, GpuBuffer};
+use super::VulkanContext;
+use image::{imageops, GrayImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Model and tiling of the neural upscaler
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NeuralSettings {
+    /// Path to the ncnn `.param` file. The weights are read from the `.bin`
+    /// file next to it.
+    pub model: String,
+    /// Edge length of the input tiles in pixels, `32..=1024`. Reduced
+    /// automatically when a tile would exceed the device's buffer limit.
+    /// Default: `256`.
+    pub tile_size: u32,
+    /// Context added around each tile and cut off after inference, in input
+    /// pixels, `0..=64`. Hides seams between tiles. Default: `16`.
+    pub tile_overlap: u32,
+}
+
+impl Default for NeuralSettings {
+    fn default() -> Self {
+        NeuralSettings {
+            model: String::new(),
+            tile_size: 256,
+            tile_overlap: 16,
+        }
+    }
+}
+
+impl NeuralSettings {
+    /// Check that a model is set and the tiling is inside its documented range
+    pub fn validate(&self) -> Result<(), String> {
+        if self.model.trim().is_empty() {
+            return Err("The neural method needs a model (path to an ncnn .param file)".into());
+        }
+        if !(32..=1024).contains(&self.tile_size) {
+            return Err(format!(
+                "Invalid tile size: {}. Must be between 32 and 1024.",
+                self.tile_size
+            ));
+        }
+        if self.tile_overlap > 64 {
+            return Err(format!(
+                "Invalid tile overlap: {}. Must be between 0 and 64.",
+                self.tile_overlap
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Planar `channels` x `height` x `width` feature map
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+    pub data: Vec<f32>,
+}
+
+impl Tensor {
+    pub fn new(
+        channels: usize,
+        height: usize,
+        width: usize,
+        data: Vec<f32>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if data.len() != channels * height * width {
+            return Err(format!(
+                "Tensor data has {} values, expected {}x{}x{}",
+                data.len(),
+                channels,
+                height,
+                width
+            )
+            .into());
+        }
+        Ok(Tensor {
+            channels,
+            height,
+            width,
+            data,
+        })
+    }
+
+    pub fn shape(&self) -> Shape {
+        Shape {
+            channels: self.channels,
+            height: self.height,
+            width: self.width,
+        }
+    }
+}
+
+/// Dimensions of a [`Tensor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shape {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Shape {
+    pub fn len(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Activation fused into a convolution
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activation {
+    None,
+    Relu,
+    LeakyRelu(f32),
+    Clip(f32, f32),
+    Sigmoid,
+}
+
+impl Activation {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Activation::None => x,
+            Activation::Relu => x.max(0.0),
+            Activation::LeakyRelu(slope) => {
+                if x > 0.0 {
+                    x
+                } else {
+                    x * slope
+                }
+            }
+            Activation::Clip(min, max) => x.clamp(min, max),
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+        }
+    }
+
+    /// `activation`, `activation_a` and `activation_b` push constants of
+    /// `nn_conv.comp`
+    fn shader_params(self) -> (u32, f32, f32) {
+        match self {
+            Activation::None => (0, 0.0, 0.0),
+            Activation::Relu => (1, 0.0, 0.0),
+            Activation::LeakyRelu(slope) => (2, slope, 0.0),
+            Activation::Clip(min, max) => (3, min, max),
+            Activation::Sigmoid => (4, 0.0, 0.0),
+        }
+    }
+}
+
+/// Spatial padding of a convolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    /// Fixed zero padding on each side
+    Explicit {
+        left: usize,
+        right: usize,
+        top: usize,
+        bottom: usize,
+    },
+    /// Pad so the output is `ceil(input / stride)`, extra pixel at the end
+    SameUpper,
+    /// Like `SameUpper` with the extra pixel at the start
+    SameLower,
+}
+
+/// Convolution layer parameters and weights
+#[derive(Debug, Clone, PartialEq)]
+pub struct Convolution {
+    pub out_channels: usize,
+    pub kernel_w: usize,
+    pub kernel_h: usize,
+    pub stride_w: usize,
+    pub stride_h: usize,
+    pub dilation_w: usize,
+    pub dilation_h: usize,
+    pub padding: Padding,
+    pub groups: usize,
+    /// `out_channels x (in_channels / groups) x kernel_h x kernel_w`
+    pub weights: Vec<f32>,
+    pub bias: Option<Vec<f32>>,
+    pub activation: Activation,
+}
+
+impl Convolution {
+    /// Input channels per group, implied by the weight count
+    fn group_in_channels(&self) -> usize {
+        self.weights.len() / (self.out_channels * self.kernel_w * self.kernel_h)
+    }
+
+    /// Padding as (left, right, top, bottom) for an input of the given size
+    fn resolve_padding(&self, height: usize, width: usize) -> (usize, usize, usize, usize) {
+        let same = |size: usize, kernel: usize, stride: usize, dilation: usize| {
+            let extent = dilation * (kernel - 1) + 1;
+            (extent + (size - 1) / stride * stride).saturating_sub(size)
+        };
+        match self.padding {
+            Padding::Explicit {
+                left,
+                right,
+                top,
+                bottom,
+            } => (left, right, top, bottom),
+            Padding::SameUpper | Padding::SameLower => {
+                let pad_w = same(width, self.kernel_w, self.stride_w, self.dilation_w);
+                let pad_h = same(height, self.kernel_h, self.stride_h, self.dilation_h);
+                if self.padding == Padding::SameUpper {
+                    (pad_w / 2, pad_w - pad_w / 2, pad_h / 2, pad_h - pad_h / 2)
+                } else {
+                    (pad_w - pad_w / 2, pad_w / 2, pad_h - pad_h / 2, pad_h / 2)
+                }
+            }
+        }
+    }
+
+    fn output_shape(&self, input: Shape) -> Result<Shape, Box<dyn std::error::Error>> {
+        let group_in = self.group_in_channels();
+        if input.channels != group_in * self.groups
+            || !self.out_channels.is_multiple_of(self.groups)
+        {
+            return Err(format!(
+                "Convolution expects {} input channels in {} groups, got {}",
+                group_in * self.groups,
+                self.groups,
+                input.channels
+            )
+            .into());
+        }
+        let (left, right, top, bottom) = self.resolve_padding(input.height, input.width);
+        let extent_w = self.dilation_w * (self.kernel_w - 1) + 1;
+        let extent_h = self.dilation_h * (self.kernel_h - 1) + 1;
+        let padded_w = input.width + left + right;
+        let padded_h = input.height + top + bottom;
+        if padded_w < extent_w || padded_h < extent_h {
+            return Err("Convolution kernel is larger than its input".into());
+        }
+        Ok(Shape {
+            channels: self.out_channels,
+            height: (padded_h - extent_h) / self.stride_h + 1,
+            width: (padded_w - extent_w) / self.stride_w + 1,
+        })
+    }
+}
+
+/// Element-wise operation, numbered like ncnn's BinaryOp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add = 0,
+    Sub = 1,
+    Mul = 2,
+    Div = 3,
+    Max = 4,
+    Min = 5,
+    ReverseSub = 7,
+}
+
+impl BinaryOp {
+    fn apply(self, a: f32, b: f32) -> f32 {
+        match self {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+            BinaryOp::Max => a.max(b),
+            BinaryOp::Min => a.min(b),
+            BinaryOp::ReverseSub => b - a,
+        }
+    }
+}
+
+/// What a layer computes
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerKind {
+    Convolution(Convolution),
+    /// Negative values scaled by a per-channel slope, or one shared slope
+    PRelu {
+        slopes: Vec<f32>,
+    },
+    /// Depth-to-space by `factor`, PyTorch channel order
+    PixelShuffle {
+        factor: usize,
+    },
+    /// Nearest-neighbour upsampling by integer factors
+    Upsample {
+        scale_w: usize,
+        scale_h: usize,
+    },
+    /// `op(a * coef_a, b * coef_b)` where `b` is the second input or `scalar`
+    Binary {
+        op: BinaryOp,
+        coef_a: f32,
+        coef_b: f32,
+        scalar: Option<f32>,
+    },
+    /// Every output is the input unchanged
+    Split,
+}
+
+/// One node of the network graph. Inputs and outputs are blob indices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub name: String,
+    pub kind: LayerKind,
+    pub inputs: Vec<usize>,
+    pub outputs: Vec<usize>,
+}
+
+/// A feed-forward network with one RGB input blob and one RGB output blob.
+/// Layers are stored in execution order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    pub layers: Vec<Layer>,
+    pub blob_count: usize,
+    pub input: usize,
+    pub output: usize,
+}
+
+impl Network {
+    /// Load an ncnn model, reading the weights from the `.bin` file next to
+    /// the `.param` file
+    pub fn load(param_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let bin_path = param_path.with_extension("bin");
+        ncnn::load(param_path, &bin_path)
+    }
+
+    /// Shape of every blob for an input of the given shape
+    pub fn shapes(&self, input: Shape) -> Result<Vec<Shape>, Box<dyn std::error::Error>> {
+        let mut shapes: Vec<Option<Shape>> = vec![None; self.blob_count];
+        shapes[self.input] = Some(input);
+
+        for layer in &self.layers {
+            let inputs = layer
+                .inputs
+                .iter()
+                .map(|&blob| shapes[blob].ok_or_else(|| self.unset_blob(layer)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let output = layer_output_shape(layer, &inputs)
+                .map_err(|e| format!("Layer '{}': {}", layer.name, e))?;
+            for &blob in &layer.outputs {
+                shapes[blob] = Some(output);
+            }
+        }
+
+        // Blobs nothing writes are never used, so any shape will do
+        Ok(shapes.into_iter().map(|s| s.unwrap_or(input)).collect())
+    }
+
+    /// Ratio of output to input size. Errors if the network doesn't upscale
+    /// RGB to RGB by the same integer factor on both axes.
+    pub fn scale(&self) -> Result<u32, Box<dyn std::error::Error>> {
+        let probe = 64;
+        let input = Shape {
+            channels: 3,
+            height: probe,
+            width: probe,
+        };
+        let output = self.shapes(input)?[self.output];
+        if output.channels != 3
+            || output.width != output.height
+            || output.width % probe != 0
+            || output.width == 0
+        {
+            return Err(format!(
+                "Model maps a 3x{}x{} input to {}x{}x{}, expected an RGB upscaler",
+                probe, probe, output.channels, output.height, output.width
+            )
+            .into());
+        }
+        Ok((output.width / probe) as u32)
+    }
+
+    /// Size in bytes of the largest blob for an input of the given shape
+    pub fn largest_blob_bytes(&self, input: Shape) -> Result<usize, Box<dyn std::error::Error>> {
+        let largest = self.shapes(input)?.iter().map(Shape::len).max();
+        Ok(largest.unwrap_or(0) * std::mem::size_of::<f32>())
+    }
+
+    /// Index of the last layer reading each blob. Blobs are freed after it.
+    fn last_uses(&self) -> Vec<usize> {
+        let mut last_use = vec![usize::MAX; self.blob_count];
+        for (index, layer) in self.layers.iter().enumerate() {
+            for &blob in &layer.inputs {
+                last_use[blob] = index;
+            }
+        }
+        last_use
+    }
+
+    fn unset_blob(&self, layer: &Layer) -> String {
+        format!(
+            "Layer '{}' reads a blob no earlier layer writes",
+            layer.name
+        )
+    }
+
+    /// Run the network on the CPU
+    pub fn forward_cpu(&self, input: Tensor) -> Result<Tensor, Box<dyn std::error::Error>> {
+        self.shapes(input.shape())?;
+        let last_use = self.last_uses();
+        let mut blobs: Vec<Option<Rc<Tensor>>> = vec![None; self.blob_count];
+        blobs[self.input] = Some(Rc::new(input));
+
+        for (index, layer) in self.layers.iter().enumerate() {
+            let inputs = layer
+                .inputs
+                .iter()
+                .map(|&blob| blobs[blob].clone().ok_or_else(|| self.unset_blob(layer)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let output = match &layer.kind {
+                LayerKind::Convolution(conv) => Rc::new(conv2d(&inputs[0], conv)?),
+                LayerKind::PRelu { slopes } => Rc::new(prelu(&inputs[0], slopes)),
+                LayerKind::PixelShuffle { factor } => Rc::new(pixel_shuffle(&inputs[0], *factor)),
+                LayerKind::Upsample { scale_w, scale_h } => {
+                    Rc::new(upsample_nearest(&inputs[0], *scale_w, *scale_h))
+                }
+                LayerKind::Binary {
+                    op,
+                    coef_a,
+                    coef_b,
+                    scalar,
+                } => Rc::new(binary(
+                    &inputs[0],
+                    inputs.get(1).map(|t| t.as_ref()),
+                    *op,
+                    *coef_a,
+                    *coef_b,
+                    *scalar,
+                )),
+                LayerKind::Split => inputs[0].clone(),
+            };
+            drop(inputs);
+
+            for &blob in &layer.inputs {
+                if last_use[blob] == index && blob != self.output {
+                    blobs[blob] = None;
+                }
+            }
+            for &blob in &layer.outputs {
+                blobs[blob] = Some(output.clone());
+            }
+        }
+
+        let output = blobs[self.output]
+            .take()
+            .ok_or("Network output blob was never written")?;
+        drop(blobs);
+        Ok(Rc::try_unwrap(output).unwrap_or_else(|shared| (*shared).clone()))
+    }
+}
+
+/// Output shape of `layer` for the given input shapes
+fn layer_output_shape(
+    layer: &Layer,
+    inputs: &[Shape],
+) -> Result<Shape, Box<dyn std::error::Error>> {
+    let expected_inputs = match &layer.kind {
+        LayerKind::Binary { scalar: None, .. } => 2,
+        _ => 1,
+    };
+    if inputs.len() != expected_inputs {
+        return Err(format!("expected {} inputs, got {}", expected_inputs, inputs.len()).into());
+    }
+    let input = inputs[0];
+
+    match &layer.kind {
+        LayerKind::Convolution(conv) => conv.output_shape(input),
+        LayerKind::PRelu { slopes } => {
+            if slopes.len() != 1 && slopes.len() != input.channels {
+                return Err(format!(
+                    "{} PReLU slopes for {} channels",
+                    slopes.len(),
+                    input.channels
+                )
+                .into());
+            }
+            Ok(input)
+        }
+        LayerKind::PixelShuffle { factor } => {
+            if !input.channels.is_multiple_of(factor * factor) {
+                return Err(format!(
+                    "{} channels can't be pixel-shuffled by {}",
+                    input.channels, factor
+                )
+                .into());
+            }
+            Ok(Shape {
+                channels: input.channels / (factor * factor),
+                height: input.height * factor,
+                width: input.width * factor,
+            })
+        }
+        LayerKind::Upsample { scale_w, scale_h } => Ok(Shape {
+            channels: input.channels,
+            height: input.height * scale_h,
+            width: input.width * scale_w,
+        }),
+        LayerKind::Binary { scalar, .. } => {
+            if scalar.is_none() && inputs[1] != input {
+                return Err(format!(
+                    "element-wise inputs differ in shape: {:?} and {:?}",
+                    input, inputs[1]
+                )
+                .into());
+            }
+            Ok(input)
+        }
+        LayerKind::Split => Ok(input),
+    }
+}
+
+/// Direct convolution, see `nn_conv.comp`
+fn conv2d(input: &Tensor, conv: &Convolution) -> Result<Tensor, Box<dyn std::error::Error>> {
+    let shape = conv.output_shape(input.shape())?;
+    let (left, _, top, _) = conv.resolve_padding(input.height, input.width);
+    let group_in = conv.group_in_channels();
+    let group_out = conv.out_channels / conv.groups;
+    let plane = shape.height * shape.width;
+    let mut output = vec![0.0f32; shape.len()];
+
+    for (oc, out_plane) in output.chunks_mut(plane).enumerate() {
+        out_plane.fill(conv.bias.as_ref().map_or(0.0, |bias| bias[oc]));
+        let first_ic = oc / group_out * group_in;
+        for ic in 0..group_in {
+            let in_plane = &input.data[(first_ic + ic) * input.height * input.width..];
+            for ky in 0..conv.kernel_h {
+                for kx in 0..conv.kernel_w {
+                    let weight = conv.weights
+                        [((oc * group_in + ic) * conv.kernel_h + ky) * conv.kernel_w + kx];
+                    for y in 0..shape.height {
+                        let iy = (y * conv.stride_h + ky * conv.dilation_h) as isize - top as isize;
+                        if iy < 0 || iy >= input.height as isize {
+                            continue;
+                        }
+                        let in_row = &in_plane[iy as usize * input.width..];
+                        let out_row = &mut out_plane[y * shape.width..(y + 1) * shape.width];
+                        for (x, out) in out_row.iter_mut().enumerate() {
+                            let ix =
+                                (x * conv.stride_w + kx * conv.dilation_w) as isize - left as isize;
+                            if ix >= 0 && ix < input.width as isize {
+                                *out += in_row[ix as usize] * weight;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        for value in out_plane.iter_mut() {
+            *value = conv.activation.apply(*value);
+        }
+    }
+
+    Tensor::new(shape.channels, shape.height, shape.width, output)
+}
+
+/// See `nn_prelu.comp`
+fn prelu(input: &Tensor, slopes: &[f32]) -> Tensor {
+    let plane = input.height * input.width;
+    let data = input
+        .data
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let slope = if slopes.len() > 1 {
+                slopes[i / plane]
+            } else {
+                slopes[0]
+            };
+            if x > 0.0 {
+                x
+            } else {
+                x * slope
+            }
+        })
+        .collect();
+    Tensor { data, ..*input }
+}
+
+/// See `nn_pixel_shuffle.comp`
+fn pixel_shuffle(input: &Tensor, factor: usize) -> Tensor {
+    let channels = input.channels / (factor * factor);
+    let (height, width) = (input.height * factor, input.width * factor);
+    let mut data = Vec::with_capacity(channels * height * width);
+    for c in 0..channels {
+        for y in 0..height {
+            for x in 0..width {
+                let in_c = (c * factor + y % factor) * factor + x % factor;
+                data.push(
+                    input.data[(in_c * input.height + y / factor) * input.width + x / factor],
+                );
+            }
+        }
+    }
+    Tensor {
+        channels,
+        height,
+        width,
+        data,
+    }
+}
+
+/// See `nn_upsample.comp`
+fn upsample_nearest(input: &Tensor, scale_w: usize, scale_h: usize) -> Tensor {
+    let (height, width) = (input.height * scale_h, input.width * scale_w);
+    let mut data = Vec::with_capacity(input.channels * height * width);
+    for c in 0..input.channels {
+        for y in 0..height {
+            let row = (c * input.height + y / scale_h) * input.width;
+            data.extend((0..width).map(|x| input.data[row + x / scale_w]));
+        }
+    }
+    Tensor {
+        channels: input.channels,
+        height,
+        width,
+        data,
+    }
+}
+
+/// See `nn_binary.comp`
+fn binary(
+    a: &Tensor,
+    b: Option<&Tensor>,
+    op: BinaryOp,
+    coef_a: f32,
+    coef_b: f32,
+    scalar: Option<f32>,
+) -> Tensor {
+    let data = a
+        .data
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let y = scalar.unwrap_or_else(|| b.map_or(0.0, |b| b.data[i]));
+            op.apply(x * coef_a, y * coef_b)
+        })
+        .collect();
+    Tensor { data, ..*a }
+}
+
+/// A [`Network`] with its shaders built and its weights on the GPU
+pub struct GpuNetwork<'a> {
+    network: &'a Network,
+    conv: ComputeKernel,
+    prelu: ComputeKernel,
+    pixel_shuffle: ComputeKernel,
+    upsample: ComputeKernel,
+    binary: ComputeKernel,
+    /// Parameters of each layer, uploaded once and reused for every tile:
+    /// weights and bias for convolutions, slopes for PReLU, nothing otherwise
+    parameters: Vec<Vec<GpuBuffer>>,
+    /// Bound to unused bindings (no bias, scalar operand)
+    placeholder: GpuBuffer,
+}
+
+impl<'a> GpuNetwork<'a> {
+    pub fn new(
+        context: &VulkanContext,
+        network: &'a Network,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let conv = ComputeKernel::new(context, include_bytes!("../../shaders/nn_conv.spv"), 4, 76)?;
+        let prelu =
+            ComputeKernel::new(context, include_bytes!("../../shaders/nn_prelu.spv"), 3, 12)?;
+        let pixel_shuffle = ComputeKernel::new(
+            context,
+            include_bytes!("../../shaders/nn_pixel_shuffle.spv"),
+            2,
+            16,
+        )?;
+        let upsample = ComputeKernel::new(
+            context,
+            include_bytes!("../../shaders/nn_upsample.spv"),
+            2,
+            20,
+        )?;
+        let binary = ComputeKernel::new(
+            context,
+            include_bytes!("../../shaders/nn_binary.spv"),
+            3,
+            28,
+        )?;
+        let placeholder = GpuBuffer::zeroed(context, 4)?;
+
+        let upload = |values: &[f32]| GpuBuffer::from_bytes(context, &f32_bytes(values));
+        let parameters = network
+            .layers
+            .iter()
+            .map(|layer| match &layer.kind {
+                LayerKind::Convolution(conv) => Ok(vec![
+                    upload(&conv.weights)?,
+                    upload(conv.bias.as_deref().unwrap_or(&[0.0]))?,
+                ]),
+                LayerKind::PRelu { slopes } => Ok(vec![upload(slopes)?]),
+                _ => Ok(Vec::new()),
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+        Ok(GpuNetwork {
+            network,
+            conv,
+            prelu,
+            pixel_shuffle,
+            upsample,
+            binary,
+            parameters,
+            placeholder,
+        })
+    }
+
+    /// Run the network on the GPU. Intermediate blobs stay in GPU memory and
+    /// are freed after their last reader.
+    pub fn forward(
+        &self,
+        context: &VulkanContext,
+        input: &Tensor,
+    ) -> Result<Tensor, Box<dyn std::error::Error>> {
+        let network = self.network;
+        let shapes = network.shapes(input.shape())?;
+        let last_use = network.last_uses();
+        let mut blobs: Vec<Option<Rc<GpuBuffer>>> = (0..network.blob_count).map(|_| None).collect();
+        blobs[network.input] = Some(Rc::new(GpuBuffer::from_bytes(
+            context,
+            &f32_bytes(&input.data),
+        )?));
+
+        for (index, layer) in network.layers.iter().enumerate() {
+            let inputs = layer
+                .inputs
+                .iter()
+                .map(|&blob| blobs[blob].clone().ok_or_else(|| network.unset_blob(layer)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let in_shape = shapes[layer.inputs[0]];
+            let out_shape = shapes[layer.outputs[0]];
+
+            let output = if let LayerKind::Split = layer.kind {
+                inputs[0].clone()
+            } else {
+                let output = Rc::new(GpuBuffer::zeroed(context, out_shape.len() * 4)?);
+                self.run_layer(context, index, &inputs, &output, in_shape, out_shape)
+                    .map_err(|e| format!("Layer '{}': {}", layer.name, e))?;
+                output
+            };
+            drop(inputs);
+
+            for &blob in &layer.inputs {
+                if last_use[blob] == index && blob != network.output {
+                    blobs[blob] = None;
+                }
+            }
+            for &blob in &layer.outputs {
+                blobs[blob] = Some(output.clone());
+            }
+        }
+
+        let output = blobs[network.output]
+            .as_ref()
+            .ok_or("Network output blob was never written")?;
+        let shape = shapes[network.output];
+        let data = output
+            .read()?
+            .chunks_exact(4)
+            .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        Tensor::new(shape.channels, shape.height, shape.width, data)
+    }
+
+    fn run_layer(
+        &self,
+        context: &VulkanContext,
+        index: usize,
+        inputs: &[Rc<GpuBuffer>],
+        output: &GpuBuffer,
+        in_shape: Shape,
+        out_shape: Shape,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let u = |value: usize| (value as u32).to_ne_bytes();
+        let spatial_groups = |shape: Shape| {
+            [
+                (shape.width as u32).div_ceil(16),
+                (shape.height as u32).div_ceil(16),
+                shape.channels as u32,
+            ]
+        };
+        let plane_groups = |shape: Shape| {
+            [
+                ((shape.height * shape.width) as u32).div_ceil(256),
+                1,
+                shape.channels as u32,
+            ]
+        };
+
+        match (
+            &self.network.layers[index].kind,
+            &self.parameters[index][..],
+        ) {
+            (LayerKind::Convolution(conv), [weights, bias]) => {
+                let (left, _, top, _) = conv.resolve_padding(in_shape.height, in_shape.width);
+                let (activation, a, b) = conv.activation.shader_params();
+                let push_constants = [
+                    u(in_shape.channels),
+                    u(in_shape.height),
+                    u(in_shape.width),
+                    u(out_shape.channels),
+                    u(out_shape.height),
+                    u(out_shape.width),
+                    u(conv.kernel_w),
+                    u(conv.kernel_h),
+                    u(conv.stride_w),
+                    u(conv.stride_h),
+                    u(conv.dilation_w),
+                    u(conv.dilation_h),
+                    (left as i32).to_ne_bytes(),
+                    (top as i32).to_ne_bytes(),
+                    u(conv.groups),
+                    u(conv.bias.is_some() as usize),
+                    activation.to_ne_bytes(),
+                    a.to_ne_bytes(),
+                    b.to_ne_bytes(),
+                ]
+                .concat();
+                self.conv.dispatch(
+                    context,
+                    &[&inputs[0], weights, bias, output],
+                    &push_constants,
+                    spatial_groups(out_shape),
+                )
+            }
+            (LayerKind::PRelu { slopes: values }, [slopes]) => {
+                let push_constants = [
+                    u(in_shape.channels),
+                    u(in_shape.height * in_shape.width),
+                    u(values.len()),
+                ]
+                .concat();
+                self.prelu.dispatch(
+                    context,
+                    &[&inputs[0], slopes, output],
+                    &push_constants,
+                    plane_groups(in_shape),
+                )
+            }
+            (LayerKind::PixelShuffle { factor }, _) => {
+                let push_constants = [
+                    u(out_shape.channels),
+                    u(out_shape.height),
+                    u(out_shape.width),
+                    u(*factor),
+                ]
+                .concat();
+                self.pixel_shuffle.dispatch(
+                    context,
+                    &[&inputs[0], output],
+                    &push_constants,
+                    spatial_groups(out_shape),
+                )
+            }
+            (LayerKind::Upsample { scale_w, scale_h }, _) => {
+                let push_constants = [
+                    u(out_shape.channels),
+                    u(out_shape.height),
+                    u(out_shape.width),
+                    u(*scale_h),
+                    u(*scale_w),
+                ]
+                .concat();
+                self.upsample.dispatch(
+                    context,
+                    &[&inputs[0], output],
+                    &push_constants,
+                    spatial_groups(out_shape),
+                )
+            }
+            (
+                LayerKind::Binary {
+                    op,
+                    coef_a,
+                    coef_b,
+                    scalar,
+                },
+                _,
+            ) => {
+                let push_constants = [
+                    u(in_shape.channels),
+                    u(in_shape.height * in_shape.width),
+                    u(*op as usize),
+                    u(scalar.is_some() as usize),
+                    scalar.unwrap_or(0.0).to_ne_bytes(),
+                    coef_a.to_ne_bytes(),
+                    coef_b.to_ne_bytes(),
+                ]
+                .concat();
+                let b: &GpuBuffer = inputs.get(1).map_or(&self.placeholder, |b| b);
+                self.binary.dispatch(
+                    context,
+                    &[&inputs[0], b, output],
+                    &push_constants,
+                    plane_groups(in_shape),
+                )
+            }
+            _ => Err("Layer has no GPU implementation".into()),
+        }
+    }
+}
+
+fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_ne_bytes()).collect()
+}
+
+/// Run `network` over `image` tile by tile.
+///
+/// Each tile is extended by `overlap` pixels of context on every side, the
+/// RGB channels are scaled to `0..=1` and passed through `run_tile`, and the
+/// output without the context border is pasted into the result. Alpha isn't
+/// seen by the network; it is resized with Catmull-Rom. The result is `scale`
+/// times larger than `image`.
+pub fn upscale_tiled<F>(
+    image: &RgbaImage,
+    scale: u32,
+    tile_size: u32,
+    overlap: u32,
+    mut run_tile: F,
+) -> Result<RgbaImage, Box<dyn std::error::Error>>
+where
+    F: FnMut(Tensor) -> Result<Tensor, Box<dyn std::error::Error>>,
+{
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err("Cannot upscale an empty image".into());
+    }
+    if scale == 0 || tile_size == 0 {
+        return Err("Scale and tile size must be at least 1".into());
+    }
+
+    let mut output = RgbaImage::new(width * scale, height * scale);
+    let columns = width.div_ceil(tile_size);
+    let rows = height.div_ceil(tile_size);
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let (x0, y0) = (column * tile_size, row * tile_size);
+            let (x1, y1) = ((x0 + tile_size).min(width), (y0 + tile_size).min(height));
+            let (px0, py0) = (x0.saturating_sub(overlap), y0.saturating_sub(overlap));
+            let (px1, py1) = ((x1 + overlap).min(width), (y1 + overlap).min(height));
+            let (tile_w, tile_h) = ((px1 - px0) as usize, (py1 - py0) as usize);
+
+            println!(
+                "  - Tile {}/{} ({}x{})",
+                row * columns + column + 1,
+                rows * columns,
+                tile_w,
+                tile_h
+            );
+
+            let mut data = vec![0.0f32; 3 * tile_w * tile_h];
+            for y in 0..tile_h {
+                for x in 0..tile_w {
+                    let pixel = image.get_pixel(px0 + x as u32, py0 + y as u32);
+                    for c in 0..3 {
+                        data[(c * tile_h + y) * tile_w + x] = pixel[c] as f32 / 255.0;
+                    }
+                }
+            }
+
+            let result = run_tile(Tensor::new(3, tile_h, tile_w, data)?)?;
+            let s = scale as usize;
+            if result.channels != 3 || result.height != tile_h * s || result.width != tile_w * s {
+                return Err(format!(
+                    "Model returned a {}x{}x{} tile, expected 3x{}x{}",
+                    result.channels,
+                    result.height,
+                    result.width,
+                    tile_h * s,
+                    tile_w * s
+                )
+                .into());
+            }
+
+            for y in (y0 - py0) * scale..(y1 - py0) * scale {
+                for x in (x0 - px0) * scale..(x1 - px0) * scale {
+                    let pixel = output.get_pixel_mut(px0 * scale + x, py0 * scale + y);
+                    for c in 0..3 {
+                        let value = result.data
+                            [(c * result.height + y as usize) * result.width + x as usize];
+                        pixel[c] = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+                    }
+                    pixel[3] = 255;
+                }
+            }
+        }
+    }
+
+    resize_alpha(image, &mut output);
+    Ok(output)
+}
+
+/// Replace the alpha channel of `output` with the alpha of `source`, resized
+/// with Catmull-Rom. Networks only see RGB; opaque images are left alone.
+pub fn resize_alpha(source: &RgbaImage, output: &mut RgbaImage) {
+    if source.pixels().all(|pixel| pixel[3] == 255) {
+        return;
+    }
+
+    let alpha = GrayImage::from_fn(source.width(), source.height(), |x, y| {
+        image::Luma([source.get_pixel(x, y)[3]])
+    });
+    let alpha = imageops::resize(
+        &alpha,
+        output.width(),
+        output.height(),
+        imageops::FilterType::CatmullRom,
+    );
+    for (pixel, a) in output.pixels_mut().zip(alpha.pixels()) {
+        pixel[3] = a[0];
+    }
+}
+
+/// Upscale `image` with the model in `settings` on the GPU. The result is
+/// enlarged by the model's own scale factor, see [`Network::scale`].
+pub fn upscale_gpu(
+    context: &VulkanContext,
+    image: &RgbaImage,
+    settings: &NeuralSettings,
+) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+    settings.validate()?;
+    let network = Network::load(Path::new(&settings.model))?;
+    let scale = network.scale()?;
+    println!(
+        "🧠 Loaded model {} ({} layers, {}x)",
+        settings.model,
+        network.layers.len(),
+        scale
+    );
+
+    // Shrink tiles until every intermediate tensor fits in one storage buffer
+    let limits = unsafe {
+        context
+            .instance
+            .get_physical_device_properties(context.physical_device)
+    }
+    .limits;
+    let buffer_limit = limits.max_storage_buffer_range as usize;
+    let mut tile_size = settings.tile_size;
+    loop {
+        let side = (tile_size + 2 * settings.tile_overlap) as usize;
+        let input = Shape {
+            channels: 3,
+            height: side,
+            width: side,
+        };
+        if network.largest_blob_bytes(input)? <= buffer_limit {
+            break;
+        }
+        if tile_size <= 16 {
+            return Err("Model needs more memory per tile than the GPU allows".into());
+        }
+        tile_size /= 2;
+    }
+    if tile_size != settings.tile_size {
+        println!(
+            "📐 Reduced tile size from {} to {} to fit the device's buffer limit",
+            settings.tile_size, tile_size
+        );
+    }
+
+    let gpu_network = GpuNetwork::new(context, &network)?;
+    upscale_tiled(image, scale, tile_size, settings.tile_overlap, |tile| {
+        gpu_network.forward(context, &tile)
+    })
+}
@@ -0,0 +1,759 @@
+//! Loader for ncnn models (`.param` + `.bin`)
+//!
+//! ncnn is the format Real-ESRGAN, Real-CUGAN and waifu2x ship their Vulkan
+//! builds in. The `.param` file is text: a magic number, the layer and blob
+//! counts, then one line per layer:
+//!
+//! ```text
+//! Convolution  conv0  1 1  input conv0  0=64 1=3 4=1 5=1 6=1728 9=2 -23310=1,0.2
+//! ```
+//!
+//! (type, name, input count, output count, input blob names, output blob
+//! names, then `key=value` parameters; keys below -23300 hold arrays). The
+//! `.bin` file is the layers' weights back to back, in layer order.
+//!
+//! Only the layers plain convolutional upscalers are built from are
+//! supported: Input, Convolution, ConvolutionDepthWise, ReLU, PReLU,
+//! PixelShuffle, nearest-neighbour Interp, BinaryOp, Eltwise, Split, and the
+//! no-op Noop/Dropout. Anything else fails to load with the layer's name.
+
+use super::{Activation, BinaryOp, Convolution, Layer, LayerKind, Network, Padding};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// First line of every `.param` file
+const PARAM_MAGIC: u32 = 7767517;
+
+/// `.bin` tags in front of weight arrays
+const TAG_FLOAT16: u32 = 0x0130_6B47;
+const TAG_INT8: u32 = 0x000D_4B38;
+const TAG_FLOAT32_SCALED: u32 = 0x0002_C056;
+
+/// Load a network from an ncnn `.param` file and its `.bin` weights
+pub fn load(param_path: &Path, bin_path: &Path) -> Result<Network, Box<dyn std::error::Error>> {
+    let param = std::fs::read_to_string(param_path)
+        .map_err(|e| format!("Failed to read {}: {}", param_path.display(), e))?;
+    let bin = std::fs::read(bin_path)
+        .map_err(|e| format!("Failed to read {}: {}", bin_path.display(), e))?;
+    parse(&param, &bin)
+}
+
+/// Build a network from the contents of a `.param` and a `.bin` file
+pub fn parse(param: &str, bin: &[u8]) -> Result<Network, Box<dyn std::error::Error>> {
+    let mut lines = param.lines().filter(|line| !line.trim().is_empty());
+
+    let magic = lines.next().ok_or("Empty ncnn param file")?.trim();
+    if magic.parse::<u32>() != Ok(PARAM_MAGIC) {
+        return Err(format!("Not an ncnn param file (magic '{}')", magic).into());
+    }
+    let counts: Vec<usize> = lines
+        .next()
+        .ok_or("ncnn param file has no layer count")?
+        .split_whitespace()
+        .map(|token| token.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| "Invalid layer and blob counts in ncnn param file")?;
+    let [layer_count, _] = counts[..] else {
+        return Err("Invalid layer and blob counts in ncnn param file".into());
+    };
+
+    let mut builder = Builder::default();
+    let mut weights = WeightReader { data: bin, pos: 0 };
+    for line in lines.take(layer_count) {
+        builder.add_line(line, &mut weights)?;
+    }
+    if weights.pos != bin.len() {
+        return Err(format!(
+            "ncnn bin file has {} bytes left over; it doesn't belong to this param file",
+            bin.len() - weights.pos
+        )
+        .into());
+    }
+    builder.finish()
+}
+
+/// Parameter value of a layer line
+#[derive(Debug, Clone, PartialEq)]
+enum ParamValue {
+    Int(i32),
+    Float(f32),
+    Array(Vec<f32>),
+}
+
+/// `key=value` parameters of one layer
+struct ParamDict(HashMap<i32, ParamValue>);
+
+impl ParamDict {
+    fn parse(tokens: &[&str]) -> Result<Self, String> {
+        let mut params = HashMap::new();
+        for token in tokens {
+            let (key, value) = token
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid parameter '{}'", token))?;
+            let key: i32 = key
+                .parse()
+                .map_err(|_| format!("Invalid parameter key '{}'", key))?;
+            let invalid = || format!("Invalid value for parameter {}: '{}'", key, value);
+
+            if key <= -23300 {
+                // Array: element count, then the elements
+                let mut items = value.split(',');
+                let count: usize = items
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(invalid)?;
+                let values = items
+                    .map(|item| item.trim().parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid())?;
+                if values.len() != count {
+                    return Err(invalid());
+                }
+                params.insert(-key - 23300, ParamValue::Array(values));
+            } else if value.contains(['.', 'e', 'E']) {
+                params.insert(
+                    key,
+                    ParamValue::Float(value.parse().map_err(|_| invalid())?),
+                );
+            } else {
+                params.insert(key, ParamValue::Int(value.parse().map_err(|_| invalid())?));
+            }
+        }
+        Ok(ParamDict(params))
+    }
+
+    fn int(&self, key: i32, default: i32) -> i32 {
+        match self.0.get(&key) {
+            Some(ParamValue::Int(value)) => *value,
+            Some(ParamValue::Float(value)) => *value as i32,
+            _ => default,
+        }
+    }
+
+    fn float(&self, key: i32, default: f32) -> f32 {
+        match self.0.get(&key) {
+            Some(ParamValue::Int(value)) => *value as f32,
+            Some(ParamValue::Float(value)) => *value,
+            _ => default,
+        }
+    }
+
+    fn array(&self, key: i32) -> &[f32] {
+        match self.0.get(&key) {
+            Some(ParamValue::Array(values)) => values,
+            _ => &[],
+        }
+    }
+
+    /// Non-negative integer parameter
+    fn size(&self, key: i32, default: i32) -> Result<usize, String> {
+        let value = self.int(key, default);
+        usize::try_from(value).map_err(|_| format!("Parameter {} must not be negative", key))
+    }
+}
+
+/// Sequential reader over the `.bin` file
+struct WeightReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl WeightReader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self.pos + len;
+        if end > self.data.len() {
+            return Err("ncnn bin file ends early; it doesn't belong to this param file".into());
+        }
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// `count` plain little-endian floats (ncnn type 1: biases, slopes)
+    fn floats(&mut self, count: usize) -> Result<Vec<f32>, String> {
+        Ok(self
+            .bytes(count * 4)?
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+
+    /// `count` weights behind a storage tag (ncnn type 0: convolution weights)
+    fn tagged_weights(&mut self, count: usize) -> Result<Vec<f32>, String> {
+        match self.u32()? {
+            0 | TAG_FLOAT32_SCALED => self.floats(count),
+            TAG_FLOAT16 => {
+                let bytes = self.bytes((count * 2).next_multiple_of(4))?;
+                Ok(bytes[..count * 2]
+                    .chunks_exact(2)
+                    .map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+                    .collect())
+            }
+            TAG_INT8 => Err("int8-quantized ncnn models are not supported".into()),
+            _ => {
+                // 256-entry lookup table followed by one byte per weight
+                let table = self.floats(256)?;
+                let indices = self.bytes(count.next_multiple_of(4))?;
+                Ok(indices[..count]
+                    .iter()
+                    .map(|&i| table[i as usize])
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Decode an IEEE 754 half-precision float
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10) & 0x1F;
+    let mantissa = (bits & 0x3FF) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1F if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+/// Collects layers and maps blob names to indices
+#[derive(Default)]
+struct Builder {
+    layers: Vec<Layer>,
+    blobs: HashMap<String, usize>,
+    blob_count: usize,
+    input: Option<usize>,
+}
+
+impl Builder {
+    fn blob(&mut self, name: &str) -> usize {
+        if let Some(&index) = self.blobs.get(name) {
+            return index;
+        }
+        self.blobs.insert(name.to_string(), self.blob_count);
+        self.new_blob()
+    }
+
+    /// A blob that exists only inside the engine, e.g. between the steps of
+    /// a multi-input Eltwise
+    fn new_blob(&mut self) -> usize {
+        self.blob_count += 1;
+        self.blob_count - 1
+    }
+
+    fn add_line(
+        &mut self,
+        line: &str,
+        weights: &mut WeightReader,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || format!("Invalid ncnn layer line: '{}'", line.trim());
+        if tokens.len() < 4 {
+            return Err(invalid().into());
+        }
+        let (layer_type, name) = (tokens[0], tokens[1]);
+        let input_count: usize = tokens[2].parse().map_err(|_| invalid())?;
+        let output_count: usize = tokens[3].parse().map_err(|_| invalid())?;
+        let params_start = 4 + input_count + output_count;
+        if tokens.len() < params_start {
+            return Err(invalid().into());
+        }
+        let inputs: Vec<usize> = tokens[4..4 + input_count]
+            .iter()
+            .map(|blob| self.blob(blob))
+            .collect();
+        let outputs: Vec<usize> = tokens[4 + input_count..params_start]
+            .iter()
+            .map(|blob| self.blob(blob))
+            .collect();
+        let params = ParamDict::parse(&tokens[params_start..])
+            .map_err(|e| format!("Layer '{}': {}", name, e))?;
+
+        self.add_layer(layer_type, name, inputs, outputs, &params, weights)
+            .map_err(|e| format!("Layer '{}' ({}): {}", name, layer_type, e).into())
+    }
+
+    fn add_layer(
+        &mut self,
+        layer_type: &str,
+        name: &str,
+        inputs: Vec<usize>,
+        outputs: Vec<usize>,
+        params: &ParamDict,
+        weights: &mut WeightReader,
+    ) -> Result<(), String> {
+        let expect_io = |n_in: usize, n_out: usize| {
+            if inputs.len() == n_in && outputs.len() == n_out {
+                Ok(())
+            } else {
+                Err(format!(
+                    "expected {} input(s) and {} output(s), got {} and {}",
+                    n_in,
+                    n_out,
+                    inputs.len(),
+                    outputs.len()
+                ))
+            }
+        };
+
+        let kind = match layer_type {
+            "Input" => {
+                expect_io(0, 1)?;
+                if self.input.is_some() {
+                    return Err("networks with more than one input are not supported".into());
+                }
+                self.input = Some(outputs[0]);
+                return Ok(());
+            }
+            "Convolution" | "ConvolutionDepthWise" => {
+                expect_io(1, 1)?;
+                let groups = if layer_type == "ConvolutionDepthWise" {
+                    params.size(7, 1)?
+                } else {
+                    1
+                };
+                LayerKind::Convolution(convolution(params, groups, weights)?)
+            }
+            "ReLU" => {
+                expect_io(1, 1)?;
+                LayerKind::PRelu {
+                    slopes: vec![params.float(0, 0.0)],
+                }
+            }
+            "PReLU" => {
+                expect_io(1, 1)?;
+                let count = params.size(0, 0)?;
+                if count == 0 {
+                    return Err("PReLU has no slopes".into());
+                }
+                LayerKind::PRelu {
+                    slopes: weights.floats(count)?,
+                }
+            }
+            "PixelShuffle" => {
+                expect_io(1, 1)?;
+                if params.int(1, 0) != 0 {
+                    return Err("only PyTorch-order pixel shuffle (mode 0) is supported".into());
+                }
+                let factor = params.size(0, 1)?;
+                if factor == 0 {
+                    return Err("upscale factor must be at least 1".into());
+                }
+                LayerKind::PixelShuffle { factor }
+            }
+            "Interp" => {
+                expect_io(1, 1)?;
+                if params.int(0, 0) != 1 {
+                    return Err("only nearest-neighbour interpolation is supported".into());
+                }
+                if params.int(3, 0) != 0 || params.int(4, 0) != 0 || params.int(5, 0) != 0 {
+                    return Err("fixed output sizes are not supported, only scale factors".into());
+                }
+                let integer_scale = |scale: f32| {
+                    if scale >= 1.0 && scale.fract() == 0.0 {
+                        Ok(scale as usize)
+                    } else {
+                        Err(format!("scale {} is not a positive integer", scale))
+                    }
+                };
+                LayerKind::Upsample {
+                    scale_h: integer_scale(params.float(1, 1.0))?,
+                    scale_w: integer_scale(params.float(2, 1.0))?,
+                }
+            }
+            "BinaryOp" => {
+                let op = binary_op(params.int(0, 0))?;
+                let scalar = (params.int(1, 0) != 0).then(|| params.float(2, 0.0));
+                expect_io(if scalar.is_some() { 1 } else { 2 }, 1)?;
+                LayerKind::Binary {
+                    op,
+                    coef_a: 1.0,
+                    coef_b: 1.0,
+                    scalar,
+                }
+            }
+            "Eltwise" => return self.add_eltwise(name, inputs, outputs, params),
+            "Split" | "Noop" => {
+                if inputs.len() != 1 || outputs.is_empty() {
+                    return Err("expected one input and at least one output".into());
+                }
+                LayerKind::Split
+            }
+            "Dropout" => {
+                expect_io(1, 1)?;
+                if params.float(0, 1.0) != 1.0 {
+                    return Err("scaling dropout is not supported".into());
+                }
+                LayerKind::Split
+            }
+            _ => return Err("unsupported layer type".into()),
+        };
+
+        self.layers.push(Layer {
+            name: name.to_string(),
+            kind,
+            inputs,
+            outputs,
+        });
+        Ok(())
+    }
+
+    /// Eltwise over n inputs becomes a chain of n - 1 binary layers
+    fn add_eltwise(
+        &mut self,
+        name: &str,
+        inputs: Vec<usize>,
+        outputs: Vec<usize>,
+        params: &ParamDict,
+    ) -> Result<(), String> {
+        if inputs.len() < 2 || outputs.len() != 1 {
+            return Err("expected at least two inputs and one output".into());
+        }
+        let op = match params.int(0, 1) {
+            0 => BinaryOp::Mul,
+            1 => BinaryOp::Add,
+            2 => BinaryOp::Max,
+            other => return Err(format!("unknown Eltwise operation {}", other)),
+        };
+        let coeffs = params.array(1);
+        if !coeffs.is_empty() && coeffs.len() != inputs.len() {
+            return Err(format!(
+                "{} coefficients for {} inputs",
+                coeffs.len(),
+                inputs.len()
+            ));
+        }
+        let coeff = |i: usize| {
+            if op == BinaryOp::Add {
+                coeffs.get(i).copied().unwrap_or(1.0)
+            } else {
+                1.0
+            }
+        };
+
+        let mut accumulated = inputs[0];
+        for (i, &input) in inputs.iter().enumerate().skip(1) {
+            let output = if i + 1 == inputs.len() {
+                outputs[0]
+            } else {
+                self.new_blob()
+            };
+            self.layers.push(Layer {
+                name: format!("{}/{}", name, i),
+                kind: LayerKind::Binary {
+                    op,
+                    coef_a: if i == 1 { coeff(0) } else { 1.0 },
+                    coef_b: coeff(i),
+                    scalar: None,
+                },
+                inputs: vec![accumulated, input],
+                outputs: vec![output],
+            });
+            accumulated = output;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Network, Box<dyn std::error::Error>> {
+        let input = self.input.ok_or("ncnn model has no Input layer")?;
+
+        // The output is the last blob that no layer reads
+        let mut read = vec![false; self.blob_count];
+        for layer in &self.layers {
+            for &blob in &layer.inputs {
+                read[blob] = true;
+            }
+        }
+        let output = self
+            .layers
+            .iter()
+            .rev()
+            .flat_map(|layer| layer.outputs.iter().rev())
+            .copied()
+            .find(|&blob| !read[blob])
+            .ok_or("ncnn model has no output")?;
+
+        Ok(Network {
+            layers: self.layers,
+            blob_count: self.blob_count,
+            input,
+            output,
+        })
+    }
+}
+
+fn convolution(
+    params: &ParamDict,
+    groups: usize,
+    weights: &mut WeightReader,
+) -> Result<Convolution, String> {
+    let out_channels = params.size(0, 0)?;
+    let kernel_w = params.size(1, 0)?;
+    let kernel_h = params.size(11, kernel_w as i32)?;
+    let dilation_w = params.size(2, 1)?;
+    let dilation_h = params.size(12, dilation_w as i32)?;
+    let stride_w = params.size(3, 1)?;
+    let stride_h = params.size(13, stride_w as i32)?;
+    let weight_count = params.size(6, 0)?;
+    if out_channels == 0 || kernel_w == 0 || kernel_h == 0 || stride_w == 0 || stride_h == 0 {
+        return Err("output channels, kernel size and stride must be at least 1".into());
+    }
+    if dilation_w == 0 || dilation_h == 0 || groups == 0 {
+        return Err("dilation and group count must be at least 1".into());
+    }
+    if weight_count == 0 || weight_count % (out_channels * kernel_w * kernel_h) != 0 {
+        return Err(format!(
+            "weight count {} doesn't match {} outputs with a {}x{} kernel",
+            weight_count, out_channels, kernel_w, kernel_h
+        ));
+    }
+    if params.int(8, 0) != 0 {
+        return Err("int8-quantized convolutions are not supported".into());
+    }
+    if params.float(18, 0.0) != 0.0 {
+        return Err("non-zero padding values are not supported".into());
+    }
+    if params.int(19, 0) != 0 {
+        return Err("dynamic weights are not supported".into());
+    }
+
+    let pad_left = params.int(4, 0);
+    let padding = match pad_left {
+        -233 => Padding::SameUpper,
+        -234 => Padding::SameLower,
+        _ => {
+            let pad = |key: i32, default: i32| {
+                usize::try_from(params.int(key, default))
+                    .map_err(|_| format!("unsupported padding {}", params.int(key, default)))
+            };
+            Padding::Explicit {
+                left: pad(4, 0)?,
+                right: pad(15, pad_left)?,
+                top: pad(14, pad_left)?,
+                bottom: pad(16, params.int(14, pad_left))?,
+            }
+        }
+    };
+
+    let activation_params = params.array(10);
+    let activation_param = |i: usize| {
+        activation_params
+            .get(i)
+            .copied()
+            .ok_or_else(|| "activation parameters are missing".to_string())
+    };
+    let activation = match params.int(9, 0) {
+        0 => Activation::None,
+        1 => Activation::Relu,
+        2 => Activation::LeakyRelu(activation_param(0)?),
+        3 => Activation::Clip(activation_param(0)?, activation_param(1)?),
+        4 => Activation::Sigmoid,
+        other => return Err(format!("unsupported fused activation {}", other)),
+    };
+
+    let weight_data = weights.tagged_weights(weight_count)?;
+    let bias = if params.int(5, 0) != 0 {
+        Some(weights.floats(out_channels)?)
+    } else {
+        None
+    };
+
+    Ok(Convolution {
+        out_channels,
+        kernel_w,
+        kernel_h,
+        stride_w,
+        stride_h,
+        dilation_w,
+        dilation_h,
+        padding,
+        groups,
+        weights: weight_data,
+        bias,
+        activation,
+    })
+}
+
+fn binary_op(op_type: i32) -> Result<BinaryOp, String> {
+    Ok(match op_type {
+        0 => BinaryOp::Add,
+        1 => BinaryOp::Sub,
+        2 => BinaryOp::Mul,
+        3 => BinaryOp::Div,
+        4 => BinaryOp::Max,
+        5 => BinaryOp::Min,
+        7 => BinaryOp::ReverseSub,
+        other => return Err(format!("unsupported binary operation {}", other)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Tensor;
+    use super::*;
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn f16_bytes(bits: &[u16]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = bits.iter().flat_map(|b| b.to_le_bytes()).collect();
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes
+    }
+
+    /// Input split into a 1x1 convolution to 4 channels, PReLU and a 2x pixel
+    /// shuffle, added to a 2x nearest-neighbour upsample of the input
+    const SMALL_UPSCALER: &str = "7767517
+7 9
+Input        data    0 1 data
+Split        split   1 2 data a b
+Convolution  conv    1 1 a c 0=4 1=1 5=1 6=4
+PReLU        prelu   1 1 c p 0=4
+PixelShuffle shuffle 1 1 p s 0=2
+Interp       up      1 1 b u 0=1 1=2.0 2=2.0
+BinaryOp     add     2 1 s u out 0=0
+";
+
+    /// Weights of [`SMALL_UPSCALER`] behind `tagged_weights`: 1, 2, -1, 0.5
+    fn small_upscaler_bin(tagged_weights: Vec<u8>) -> Vec<u8> {
+        [
+            tagged_weights,
+            f32_bytes(&[0.0, 0.0, 0.0, 1.0]),
+            f32_bytes(&[0.5, 0.25, 0.125, 2.0]),
+        ]
+        .concat()
+    }
+
+    fn run(network: &Network, channels: usize, size: usize, data: Vec<f32>) -> Tensor {
+        network
+            .forward_cpu(Tensor::new(channels, size, size, data).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn decodes_half_floats() {
+        assert_eq!(f16_to_f32(0x3C00), 1.0);
+        assert_eq!(f16_to_f32(0xC000), -2.0);
+        assert_eq!(f16_to_f32(0x3800), 0.5);
+        assert_eq!(f16_to_f32(0x7BFF), 65504.0);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x0400), 2f32.powi(-14));
+        assert_eq!(f16_to_f32(0x7C00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0xFC00), f32::NEG_INFINITY);
+        assert!(f16_to_f32(0x7E00).is_nan());
+        assert!(f16_to_f32(0x8000) == 0.0 && f16_to_f32(0x8000).is_sign_negative());
+    }
+
+    #[test]
+    fn runs_a_small_upscaler_with_fp32_and_fp16_weights() {
+        let fp32 = [
+            0u32.to_le_bytes().to_vec(),
+            f32_bytes(&[1.0, 2.0, -1.0, 0.5]),
+        ]
+        .concat();
+        let fp16 = [
+            TAG_FLOAT16.to_le_bytes().to_vec(),
+            f16_bytes(&[0x3C00, 0x4000, 0xBC00, 0x3800]),
+        ]
+        .concat();
+
+        // Channels after the convolution: x, 2x, -x, x / 2 + 1, then PReLU
+        // with slopes 0.5, 0.25, 0.125 and 2. The shuffle interleaves them
+        // as [c0 c1; c2 c3] blocks.
+        #[rustfmt::skip]
+        let expected = [
+            2.0,   3.0, -3.0, -3.0,
+            0.875, 2.5,  0.0, -2.0,
+            6.0,   9.0, -6.0, -6.0,
+            2.625, 5.5,  0.0, -6.0,
+        ];
+        for weights in [fp32, fp16] {
+            let network = parse(SMALL_UPSCALER, &small_upscaler_bin(weights)).unwrap();
+            let output = run(&network, 1, 2, vec![1.0, -2.0, 3.0, -4.0]);
+            assert_eq!((output.channels, output.height, output.width), (1, 4, 4));
+            assert_eq!(output.data, expected);
+        }
+    }
+
+    #[test]
+    fn reads_weights_through_a_lookup_table() {
+        let param = "7767517
+2 2
+Input       data 0 1 data
+Convolution conv 1 1 data out 0=2 1=1 6=2
+";
+        // Any other tag is a 256-entry table; the two indices are padded to 4
+        let mut table = vec![0.0f32; 256];
+        table[3] = 1.5;
+        table[200] = -4.0;
+        let bin = [
+            1u32.to_le_bytes().to_vec(),
+            f32_bytes(&table),
+            vec![3, 200, 0, 0],
+        ]
+        .concat();
+
+        let network = parse(param, &bin).unwrap();
+        let output = run(&network, 1, 1, vec![2.0]);
+        assert_eq!(output.data, [3.0, -8.0]);
+    }
+
+    #[test]
+    fn convolves_grouped_and_dilated_kernels() {
+        // Two groups of one channel, 3x3 taps two pixels apart, padded by 2.
+        // Group 0 only has the top-left tap, so it shifts its channel down
+        // and right by 2; group 1 only has the centre tap, tripled.
+        let param = "7767517
+2 2
+Input                data 0 1 data
+ConvolutionDepthWise conv 1 1 data out 0=2 1=3 2=2 4=2 6=18 7=2
+";
+        let mut weights = [0.0f32; 18];
+        weights[0] = 1.0;
+        weights[9 + 4] = 3.0;
+        let bin = [0u32.to_le_bytes().to_vec(), f32_bytes(&weights)].concat();
+        let network = parse(param, &bin).unwrap();
+
+        let input: Vec<f32> = (1..=32).map(|v| v as f32).collect();
+        let output = run(&network, 2, 4, input.clone());
+        assert_eq!((output.channels, output.height, output.width), (2, 4, 4));
+        for y in 0..4 {
+            for x in 0..4 {
+                let shifted = if y >= 2 && x >= 2 {
+                    input[(y - 2) * 4 + x - 2]
+                } else {
+                    0.0
+                };
+                assert_eq!(output.data[y * 4 + x], shifted, "({}, {})", x, y);
+                assert_eq!(output.data[16 + y * 4 + x], 3.0 * input[16 + y * 4 + x]);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_and_unsupported_files() {
+        let weights = || {
+            [
+                0u32.to_le_bytes().to_vec(),
+                f32_bytes(&[1.0, 2.0, -1.0, 0.5]),
+            ]
+            .concat()
+        };
+        let bin = small_upscaler_bin(weights());
+
+        assert!(parse(SMALL_UPSCALER, &bin[..bin.len() - 4]).is_err());
+        assert!(parse(SMALL_UPSCALER, &[bin.as_slice(), &[0; 4]].concat()).is_err());
+        assert!(parse(&SMALL_UPSCALER.replace("7767517", "1234"), &bin).is_err());
+        assert!(parse(&SMALL_UPSCALER.replace("Interp ", "Deconv "), &bin).is_err());
+
+        let int8 = [TAG_INT8.to_le_bytes().to_vec(), vec![0; 4]].concat();
+        let error = parse(SMALL_UPSCALER, &small_upscaler_bin(int8)).unwrap_err();
+        assert!(error.to_string().contains("int8"), "{}", error);
+    }
+}
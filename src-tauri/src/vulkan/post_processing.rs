@@ -0,0 +1,1772 @@
+//! Post-processing functions for enhanced image quality
+
+use super::compute::{run_image_kernel, ComputeKernel};
+use super::{fsr, VulkanContext};
+use image::{ImageBuffer, Rgba};
+use serde::{Deserialize, Serialize};
+
+/// Which sharpening filter the enhanced pipeline runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SharpenMode {
+    /// Fixed 3x3 high-pass kernel ([`apply_sharpening`])
+    Classic,
+    /// Gaussian unsharp mask with radius, amount and threshold ([`unsharp_mask`])
+    UnsharpMask,
+    /// Contrast-adaptive sharpening, strength limited by local contrast ([`cas`])
+    Cas,
+    /// AMD FSR 1 robust contrast-adaptive sharpening ([`fsr::rcas`])
+    Rcas,
+}
+
+/// Which contrast enhancement the enhanced pipeline runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContrastMode {
+    /// Fixed gamma, contrast and saturation curve ([`enhance_contrast`])
+    Classic,
+    /// Per-channel black/white point stretch from the histogram ([`auto_levels`])
+    AutoLevels,
+    /// Contrast-limited adaptive histogram equalization on luminance ([`clahe`])
+    Clahe,
+}
+
+/// Which noise reduction filter the enhanced pipeline runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DenoiseMode {
+    /// Box filter blended by local difference ([`reduce_noise`])
+    Classic,
+    /// Edge-preserving bilateral filter ([`bilateral_filter`])
+    Bilateral,
+    /// Patch-based non-local means, best for grainy scans ([`non_local_means`])
+    NonLocalMeans,
+}
+
+/// Tunable parameters for the post-processing chain run by [`process`] after
+/// the GPU upscale of the enhanced and model methods.
+///
+/// Every field has a default, so callers only need to send the values they
+/// want to change. Filters run in the order noise reduction, contrast
+/// enhancement, sharpening.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessSettings {
+    /// Run the sharpening filter. Default: `true`.
+    pub sharpening: bool,
+    /// Sharpening filter to run. Default: `classic`.
+    pub sharpen_mode: SharpenMode,
+    /// Strength of the classic sharpening kernel, `0.0..=5.0`. Default: `1.8`.
+    pub sharpen_intensity: f32,
+    /// Gaussian sigma of the unsharp mask in source pixels, `0.1..=10.0`.
+    /// Default: `1.0`.
+    pub unsharp_radius: f32,
+    /// How much of the high-frequency detail the unsharp mask adds back,
+    /// `0.0..=5.0`. Default: `0.8`.
+    pub unsharp_amount: f32,
+    /// Minimum luminance difference (0-255 scale) between a pixel and its
+    /// blurred version before the unsharp mask touches it, `0.0..=255.0`.
+    /// Keeps flat areas and fine grain untouched. Default: `3.0`.
+    pub unsharp_threshold: f32,
+    /// Multiply `unsharp_radius` by the upscale factor, so the mask works at
+    /// the scale of the interpolation blur. Default: `true`.
+    pub unsharp_scale_with_factor: bool,
+    /// Contrast-adaptive sharpening strength, `0.0..=1.0`. Default: `0.5`.
+    pub cas_sharpness: f32,
+    /// RCAS sharpness in stops, `0.0..=2.0`. `0.0` is the strongest and every
+    /// stop halves the effect. Default: `0.2`.
+    pub rcas_sharpness: f32,
+    /// Run the CAS and RCAS filters as compute shaders instead of on the CPU.
+    /// Default: `true`.
+    pub sharpen_on_gpu: bool,
+    /// Run contrast enhancement. Default: `true`.
+    pub contrast_enhancement: bool,
+    /// Contrast enhancement to run. Default: `classic`.
+    pub contrast_mode: ContrastMode,
+    /// Classic gamma applied before the contrast curve, `0.1..=5.0`. Values
+    /// above 1 brighten the image. Default: `1.15`.
+    pub gamma: f32,
+    /// Classic contrast multiplier around mid-grey, `0.0..=3.0`. Default: `1.25`.
+    pub contrast: f32,
+    /// Classic saturation multiplier around luminance, `0.0..=3.0`. Default: `1.1`.
+    pub saturation: f32,
+    /// Percentage of pixels auto-levels clips at each end of every channel's
+    /// histogram, `0.0..=10.0`. Default: `0.5`.
+    pub levels_clip_percent: f32,
+    /// Number of CLAHE tiles across the image, `1..=64`. Default: `8`.
+    pub clahe_tiles_x: u32,
+    /// Number of CLAHE tiles down the image, `1..=64`. Default: `8`.
+    pub clahe_tiles_y: u32,
+    /// CLAHE clip limit as a multiple of the average histogram bin height,
+    /// `1.0..=40.0`. Lower values give a gentler result. Default: `2.0`.
+    pub clahe_clip_limit: f32,
+    /// Run noise reduction. Off by default to avoid blur. Default: `false`.
+    pub noise_reduction: bool,
+    /// Noise reduction filter to run. Default: `classic`.
+    pub denoise_mode: DenoiseMode,
+    /// Classic noise reduction strength, `0.0..=2.0`. Default: `0.5`.
+    pub denoise_strength: f32,
+    /// Bilateral spatial sigma in pixels, `0.5..=10.0`. Default: `2.0`.
+    pub bilateral_sigma_spatial: f32,
+    /// Bilateral range sigma in 0-255 colour units, `1.0..=255.0`. Colour
+    /// steps well above this are treated as edges. Default: `20.0`.
+    pub bilateral_sigma_range: f32,
+    /// Non-local means filter strength `h` in 0-255 colour units,
+    /// `1.0..=100.0`. Should roughly match the noise level. Default: `10.0`.
+    pub nlm_strength: f32,
+    /// Non-local means patch radius, `1..=3` (3x3 to 7x7 patches). Default: `1`.
+    pub nlm_patch_radius: u32,
+    /// Non-local means search window radius, `1..=10`. Default: `5`.
+    pub nlm_search_radius: u32,
+    /// Run the bilateral and non-local means filters as compute shaders
+    /// instead of on the CPU. Default: `true`.
+    pub denoise_on_gpu: bool,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        PostProcessSettings {
+            sharpening: true,
+            sharpen_mode: SharpenMode::Classic,
+            sharpen_intensity: 1.8,
+            unsharp_radius: 1.0,
+            unsharp_amount: 0.8,
+            unsharp_threshold: 3.0,
+            unsharp_scale_with_factor: true,
+            cas_sharpness: 0.5,
+            rcas_sharpness: 0.2,
+            sharpen_on_gpu: true,
+            contrast_enhancement: true,
+            contrast_mode: ContrastMode::Classic,
+            gamma: 1.15,
+            contrast: 1.25,
+            saturation: 1.1,
+            levels_clip_percent: 0.5,
+            clahe_tiles_x: 8,
+            clahe_tiles_y: 8,
+            clahe_clip_limit: 2.0,
+            noise_reduction: false,
+            denoise_mode: DenoiseMode::Classic,
+            denoise_strength: 0.5,
+            bilateral_sigma_spatial: 2.0,
+            bilateral_sigma_range: 20.0,
+            nlm_strength: 10.0,
+            nlm_patch_radius: 1,
+            nlm_search_radius: 5,
+            denoise_on_gpu: true,
+        }
+    }
+}
+
+impl PostProcessSettings {
+    /// Check that every parameter is finite and inside its documented range
+    pub fn validate(&self) -> Result<(), String> {
+        let ranges = [
+            ("sharpen_intensity", self.sharpen_intensity, 0.0, 5.0),
+            ("unsharp_radius", self.unsharp_radius, 0.1, 10.0),
+            ("unsharp_amount", self.unsharp_amount, 0.0, 5.0),
+            ("unsharp_threshold", self.unsharp_threshold, 0.0, 255.0),
+            ("cas_sharpness", self.cas_sharpness, 0.0, 1.0),
+            ("rcas_sharpness", self.rcas_sharpness, 0.0, 2.0),
+            ("gamma", self.gamma, 0.1, 5.0),
+            ("contrast", self.contrast, 0.0, 3.0),
+            ("saturation", self.saturation, 0.0, 3.0),
+            ("levels_clip_percent", self.levels_clip_percent, 0.0, 10.0),
+            ("clahe_clip_limit", self.clahe_clip_limit, 1.0, 40.0),
+            ("denoise_strength", self.denoise_strength, 0.0, 2.0),
+            (
+                "bilateral_sigma_spatial",
+                self.bilateral_sigma_spatial,
+                0.5,
+                10.0,
+            ),
+            (
+                "bilateral_sigma_range",
+                self.bilateral_sigma_range,
+                1.0,
+                255.0,
+            ),
+            ("nlm_strength", self.nlm_strength, 1.0, 100.0),
+        ];
+
+        for (name, value, min, max) in ranges {
+            if !value.is_finite() || value < min || value > max {
+                return Err(format!(
+                    "Invalid {}: {}. Must be between {} and {}.",
+                    name, value, min, max
+                ));
+            }
+        }
+
+        let integer_ranges = [
+            ("clahe_tiles_x", self.clahe_tiles_x, 1, 64),
+            ("clahe_tiles_y", self.clahe_tiles_y, 1, 64),
+            ("nlm_patch_radius", self.nlm_patch_radius, 1, 3),
+            ("nlm_search_radius", self.nlm_search_radius, 1, 10),
+        ];
+
+        for (name, value, min, max) in integer_ranges {
+            if value < min || value > max {
+                return Err(format!(
+                    "Invalid {}: {}. Must be between {} and {}.",
+                    name, value, min, max
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unsharp mask radius in output pixels for the given upscale factor
+    pub fn effective_unsharp_radius(&self, factor: u32) -> f32 {
+        if self.unsharp_scale_with_factor {
+            self.unsharp_radius * factor.max(1) as f32
+        } else {
+            self.unsharp_radius
+        }
+    }
+}
+
+/// Run the post-processing chain configured by `settings` on an upscaled
+/// image. `factor` is the upscale factor the image was produced with.
+pub fn process(
+    context: &VulkanContext,
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    factor: u32,
+    settings: &PostProcessSettings,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    process_shot_frame(
+        context, image_data, width, height, factor, settings, None, None,
+    )
+}
+
+/// [`process`] for one frame of a shot: the contrast step is replaced by
+/// `shot_contrast` when given, so every frame gets the same curve, and noise
+/// reduction is followed by `temporal` when given
+#[allow(clippy::too_many_arguments)]
+pub fn process_shot_frame(
+    context: &VulkanContext,
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    factor: u32,
+    settings: &PostProcessSettings,
+    shot_contrast: Option<&ShotContrast>,
+    temporal: Option<&mut TemporalDenoise>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_image_len(image_data, width, height)?;
+    let mut image_data = image_data.to_vec();
+
+    println!("Applying post-processing filters...");
+
+    // Apply noise reduction first (if enabled)
+    if settings.noise_reduction {
+        let device = if settings.denoise_on_gpu {
+            "GPU"
+        } else {
+            "CPU"
+        };
+        image_data = match settings.denoise_mode {
+            DenoiseMode::Classic => {
+                println!("  - Reducing noise...");
+                reduce_noise(&image_data, width, height, settings.denoise_strength)?
+            }
+            DenoiseMode::Bilateral => {
+                println!("  - Applying bilateral filter ({})...", device);
+                let (sigma_spatial, sigma_range) = (
+                    settings.bilateral_sigma_spatial,
+                    settings.bilateral_sigma_range,
+                );
+                if settings.denoise_on_gpu {
+                    bilateral_filter_gpu(
+                        context,
+                        &image_data,
+                        width,
+                        height,
+                        sigma_spatial,
+                        sigma_range,
+                    )?
+                } else {
+                    bilateral_filter(&image_data, width, height, sigma_spatial, sigma_range)?
+                }
+            }
+            DenoiseMode::NonLocalMeans => {
+                println!("  - Applying non-local means ({})...", device);
+                let (h, patch_radius, search_radius) = (
+                    settings.nlm_strength,
+                    settings.nlm_patch_radius,
+                    settings.nlm_search_radius,
+                );
+                if settings.denoise_on_gpu {
+                    non_local_means_gpu(
+                        context,
+                        &image_data,
+                        width,
+                        height,
+                        h,
+                        patch_radius,
+                        search_radius,
+                    )?
+                } else {
+                    non_local_means(&image_data, width, height, h, patch_radius, search_radius)?
+                }
+            }
+        };
+        if let Some(temporal) = temporal {
+            println!("  - Applying temporal denoise...");
+            image_data = temporal.apply(&image_data, width, height)?;
+        }
+    }
+
+    // Apply contrast enhancement
+    if let (true, Some(shot_contrast)) = (settings.contrast_enhancement, shot_contrast) {
+        println!("  - Applying shot-wide {:?}...", settings.contrast_mode);
+        image_data = shot_contrast.apply(&image_data, width, height)?;
+    } else if settings.contrast_enhancement {
+        image_data = match settings.contrast_mode {
+            ContrastMode::Classic => {
+                println!("  - Enhancing contrast...");
+                enhance_contrast(
+                    &image_data,
+                    width,
+                    height,
+                    settings.gamma,
+                    settings.contrast,
+                    settings.saturation,
+                )?
+            }
+            ContrastMode::AutoLevels => {
+                println!("  - Applying auto-levels...");
+                auto_levels(&image_data, width, height, settings.levels_clip_percent)?
+            }
+            ContrastMode::Clahe => {
+                println!(
+                    "  - Applying CLAHE ({}x{} tiles)...",
+                    settings.clahe_tiles_x, settings.clahe_tiles_y
+                );
+                clahe(
+                    &image_data,
+                    width,
+                    height,
+                    settings.clahe_tiles_x,
+                    settings.clahe_tiles_y,
+                    settings.clahe_clip_limit,
+                )?
+            }
+        };
+    }
+
+    // Apply sharpening last (more aggressive to counter any remaining blur)
+    if settings.sharpening {
+        match settings.sharpen_mode {
+            SharpenMode::Classic => {
+                println!("  - Applying sharpening...");
+                image_data =
+                    apply_sharpening(&image_data, width, height, settings.sharpen_intensity)?;
+            }
+            SharpenMode::UnsharpMask => {
+                let radius = settings.effective_unsharp_radius(factor);
+                println!("  - Applying unsharp mask (radius: {:.1}px)...", radius);
+                image_data = unsharp_mask(
+                    &image_data,
+                    width,
+                    height,
+                    radius,
+                    settings.unsharp_amount,
+                    settings.unsharp_threshold,
+                )?;
+            }
+            SharpenMode::Cas => {
+                println!(
+                    "  - Applying contrast-adaptive sharpening ({})...",
+                    if settings.sharpen_on_gpu {
+                        "GPU"
+                    } else {
+                        "CPU"
+                    }
+                );
+                let sharpness = settings.cas_sharpness;
+                image_data = if settings.sharpen_on_gpu {
+                    cas_gpu(context, &image_data, width, height, sharpness)?
+                } else {
+                    cas(&image_data, width, height, sharpness)?
+                };
+            }
+            SharpenMode::Rcas => {
+                println!(
+                    "  - Applying RCAS (sharpness: {} stops, {})...",
+                    settings.rcas_sharpness,
+                    if settings.sharpen_on_gpu {
+                        "GPU"
+                    } else {
+                        "CPU"
+                    }
+                );
+                let sharpness = settings.rcas_sharpness;
+                image_data = if settings.sharpen_on_gpu {
+                    fsr::rcas_gpu(context, &image_data, width, height, sharpness)?
+                } else {
+                    fsr::rcas(&image_data, width, height, sharpness)?
+                };
+            }
+        }
+    }
+
+    Ok(image_data)
+}
+
+/// Apply sharpening filter to enhance details
+pub fn apply_sharpening(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    intensity: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let img: ImageBuffer<Rgba<u8>, Vec<u8>> =
+        ImageBuffer::from_raw(width, height, image_data.to_vec())
+            .ok_or("Failed to create image buffer")?;
+
+    // Simple unsharp masking implementation
+    let result = ImageBuffer::from_fn(width, height, |x, y| {
+        let pixel = img.get_pixel(x, y);
+
+        // Get neighborhood pixels for edge detection
+        let get_safe_pixel = |px: i32, py: i32| -> Rgba<u8> {
+            let px = px.clamp(0, width as i32 - 1) as u32;
+            let py = py.clamp(0, height as i32 - 1) as u32;
+            *img.get_pixel(px, py)
+        };
+
+        // Identity plus a high-pass kernel scaled by the intensity. The
+        // weights sum to 1, so flat areas keep their colour.
+        let amount = intensity * 0.5;
+        let kernel = [
+            [-0.25 * amount, -0.5 * amount, -0.25 * amount],
+            [-0.5 * amount, 1.0 + 3.0 * amount, -0.5 * amount],
+            [-0.25 * amount, -0.5 * amount, -0.25 * amount],
+        ];
+
+        let mut r_sum = 0.0;
+        let mut g_sum = 0.0;
+        let mut b_sum = 0.0;
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let neighbor = get_safe_pixel(x as i32 + dx, y as i32 + dy);
+                let weight = kernel[(dy + 1) as usize][(dx + 1) as usize];
+                r_sum += neighbor[0] as f32 * weight;
+                g_sum += neighbor[1] as f32 * weight;
+                b_sum += neighbor[2] as f32 * weight;
+            }
+        }
+
+        Rgba([
+            r_sum.round().clamp(0.0, 255.0) as u8,
+            g_sum.round().clamp(0.0, 255.0) as u8,
+            b_sum.round().clamp(0.0, 255.0) as u8,
+            pixel[3], // Preserve alpha
+        ])
+    });
+
+    Ok(result.into_raw())
+}
+
+/// Sharpen with a Gaussian unsharp mask.
+///
+/// `radius` is the Gaussian sigma in pixels, `amount` scales the detail
+/// (original minus blurred) added back, and pixels whose luminance differs
+/// from the blurred image by less than `threshold` (0-255 scale) are left
+/// untouched so flat areas don't pick up noise.
+pub fn unsharp_mask(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    radius: f32,
+    amount: f32,
+    threshold: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let expected_len = width as usize * height as usize * 4;
+    if image_data.len() != expected_len {
+        return Err("Failed to create image buffer".into());
+    }
+    if radius <= 0.0 || amount <= 0.0 {
+        return Ok(image_data.to_vec());
+    }
+
+    let blurred = gaussian_blur(image_data, width, height, radius);
+
+    let mut result = image_data.to_vec();
+    for (pixel, blurred) in result.chunks_exact_mut(4).zip(blurred.chunks_exact(4)) {
+        let diff = [
+            pixel[0] as f32 - blurred[0],
+            pixel[1] as f32 - blurred[1],
+            pixel[2] as f32 - blurred[2],
+        ];
+
+        // Threshold on luminance so the decision is the same for all channels
+        // and doesn't shift hues
+        let luma_diff = 0.299 * diff[0] + 0.587 * diff[1] + 0.114 * diff[2];
+        if luma_diff.abs() < threshold {
+            continue;
+        }
+
+        for channel in 0..3 {
+            let value = pixel[channel] as f32 + amount * diff[channel];
+            pixel[channel] = value.round().clamp(0.0, 255.0) as u8;
+        }
+        // Alpha (pixel[3]) is preserved
+    }
+
+    Ok(result)
+}
+
+/// Contrast-adaptive sharpening, after AMD FidelityFX CAS.
+///
+/// Each pixel is sharpened with a 5-tap cross whose negative weight is scaled
+/// per channel by the local contrast: the soft minimum of the 3x3
+/// neighbourhood's distance to black or white, divided by its soft maximum.
+/// Low-contrast areas get the full `sharpness` (`0.0..=1.0`), while strong
+/// edges and pixels close to clipping get little, which avoids halos. Alpha is
+/// preserved. Matches `shaders/cas.comp`.
+pub fn cas(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    sharpness: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_image_len(image_data, width, height)?;
+
+    // Weight of the cross at full amplitude, -1/8 (soft) to -1/5 (sharp)
+    let peak = -1.0 / (8.0 + (5.0 - 8.0) * sharpness.clamp(0.0, 1.0));
+    let mut result = Vec::with_capacity(image_data.len());
+
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            // a b c
+            // d e f
+            // g h i
+            let mut window = [[0.0f32; 4]; 9];
+            for (i, pixel) in window.iter_mut().enumerate() {
+                let (dx, dy) = (i as i32 % 3 - 1, i as i32 / 3 - 1);
+                *pixel = load_pixel(image_data, width, height, x + dx, y + dy);
+            }
+            let [a, b, c, d, e, f, g, h, i] = window;
+
+            for ch in 0..3 {
+                // Soft min/max: the cross plus the full 3x3 window (2x scale)
+                let cross_min = b[ch].min(d[ch]).min(e[ch]).min(f[ch]).min(h[ch]);
+                let cross_max = b[ch].max(d[ch]).max(e[ch]).max(f[ch]).max(h[ch]);
+                let min = cross_min + cross_min.min(a[ch]).min(c[ch]).min(g[ch]).min(i[ch]);
+                let max = cross_max + cross_max.max(a[ch]).max(c[ch]).max(g[ch]).max(i[ch]);
+
+                let amplitude = if max > 0.0 {
+                    (min.min(2.0 * 255.0 - max) / max).clamp(0.0, 1.0).sqrt()
+                } else {
+                    0.0
+                };
+                let weight = amplitude * peak;
+
+                let value =
+                    (weight * (b[ch] + d[ch] + f[ch] + h[ch]) + e[ch]) / (1.0 + 4.0 * weight);
+                result.push((value + 0.5).clamp(0.0, 255.0) as u8);
+            }
+            result.push(e[3] as u8);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Separable Gaussian blur of the RGB channels, returned as RGBA `f32` values
+/// (alpha is copied through). Edges are clamped.
+fn gaussian_blur(image_data: &[u8], width: u32, height: u32, sigma: f32) -> Vec<f32> {
+    let (width, height) = (width as usize, height as usize);
+    let kernel = gaussian_kernel(sigma);
+    let half = (kernel.len() / 2) as isize;
+
+    let source: Vec<f32> = image_data.iter().map(|&v| v as f32).collect();
+
+    // Horizontal pass
+    let mut horizontal = vec![0.0f32; source.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0f32; 3];
+            for (k, weight) in kernel.iter().enumerate() {
+                let sx = (x as isize + k as isize - half).clamp(0, width as isize - 1) as usize;
+                let idx = (y * width + sx) * 4;
+                for c in 0..3 {
+                    sum[c] += source[idx + c] * weight;
+                }
+            }
+            let idx = (y * width + x) * 4;
+            horizontal[idx..idx + 3].copy_from_slice(&sum);
+            horizontal[idx + 3] = source[idx + 3];
+        }
+    }
+
+    // Vertical pass
+    let mut result = vec![0.0f32; source.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0f32; 3];
+            for (k, weight) in kernel.iter().enumerate() {
+                let sy = (y as isize + k as isize - half).clamp(0, height as isize - 1) as usize;
+                let idx = (sy * width + x) * 4;
+                for c in 0..3 {
+                    sum[c] += horizontal[idx + c] * weight;
+                }
+            }
+            let idx = (y * width + x) * 4;
+            result[idx..idx + 3].copy_from_slice(&sum);
+            result[idx + 3] = horizontal[idx + 3];
+        }
+    }
+
+    result
+}
+
+/// Normalized 1D Gaussian kernel covering +/- 3 sigma
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let half = (sigma * 3.0).ceil().max(1.0) as i32;
+    let weights: Vec<f32> = (-half..=half)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|w| w / total).collect()
+}
+
+/// Enhance contrast and brightness for old/faded images
+pub fn enhance_contrast(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    gamma: f32,
+    contrast: f32,
+    saturation: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let img: ImageBuffer<Rgba<u8>, Vec<u8>> =
+        ImageBuffer::from_raw(width, height, image_data.to_vec())
+            .ok_or("Failed to create image buffer")?;
+
+    // Convert to grayscale for histogram analysis, but keep RGB data
+    let enhanced_img = ImageBuffer::from_fn(width, height, |x, y| {
+        let pixel = img.get_pixel(x, y);
+
+        // Apply gamma correction first
+        let r_gamma = (pixel[0] as f32 / 255.0).powf(1.0 / gamma);
+        let g_gamma = (pixel[1] as f32 / 255.0).powf(1.0 / gamma);
+        let b_gamma = (pixel[2] as f32 / 255.0).powf(1.0 / gamma);
+
+        // Apply contrast enhancement
+        let r_contrast = (r_gamma - 0.5) * contrast + 0.5;
+        let g_contrast = (g_gamma - 0.5) * contrast + 0.5;
+        let b_contrast = (b_gamma - 0.5) * contrast + 0.5;
+
+        // Apply saturation boost
+        let luminance = 0.299 * r_contrast + 0.587 * g_contrast + 0.114 * b_contrast;
+        let r = ((r_contrast - luminance) * saturation + luminance).clamp(0.0, 1.0) * 255.0;
+        let g = ((g_contrast - luminance) * saturation + luminance).clamp(0.0, 1.0) * 255.0;
+        let b = ((b_contrast - luminance) * saturation + luminance).clamp(0.0, 1.0) * 255.0;
+
+        Rgba([r as u8, g as u8, b as u8, pixel[3]])
+    });
+
+    Ok(enhanced_img.into_raw())
+}
+
+/// Per-channel 256-bin histograms of the RGB channels
+pub type ChannelHistograms = [[u64; 256]; 3];
+
+/// Black and white points per RGB channel, as found by auto-levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Levels {
+    pub low: [u8; 3],
+    pub high: [u8; 3],
+}
+
+/// Count RGB values of every pixel that isn't fully transparent
+pub fn channel_histograms(image_data: &[u8]) -> ChannelHistograms {
+    let mut histograms = [[0u64; 256]; 3];
+    for pixel in image_data.chunks_exact(4) {
+        if pixel[3] == 0 {
+            continue;
+        }
+        for (channel, histogram) in histograms.iter_mut().enumerate() {
+            histogram[pixel[channel] as usize] += 1;
+        }
+    }
+    histograms
+}
+
+/// Find the values below and above which `clip_percent` of each channel lies
+pub fn levels_from_histograms(histograms: &ChannelHistograms, clip_percent: f32) -> Levels {
+    let mut levels = Levels {
+        low: [0; 3],
+        high: [255; 3],
+    };
+
+    for (channel, histogram) in histograms.iter().enumerate() {
+        let total: u64 = histogram.iter().sum();
+        if total == 0 {
+            continue;
+        }
+        let clip = (total as f64 * clip_percent as f64 / 100.0) as u64;
+
+        let mut count = 0;
+        let low = histogram
+            .iter()
+            .position(|&n| {
+                count += n;
+                count > clip
+            })
+            .unwrap_or(0);
+
+        let mut count = 0;
+        let high = 255
+            - histogram
+                .iter()
+                .rev()
+                .position(|&n| {
+                    count += n;
+                    count > clip
+                })
+                .unwrap_or(0);
+
+        // A single-valued channel has nothing to stretch
+        if high > low {
+            levels.low[channel] = low as u8;
+            levels.high[channel] = high as u8;
+        }
+    }
+
+    levels
+}
+
+/// Linearly stretch each channel so `levels.low` maps to 0 and `levels.high`
+/// to 255. Alpha is preserved.
+pub fn apply_levels(image_data: &[u8], levels: &Levels) -> Vec<u8> {
+    let luts: Vec<[u8; 256]> = (0..3)
+        .map(|channel| {
+            let low = levels.low[channel] as f32;
+            let range = (levels.high[channel] as f32 - low).max(1.0);
+            let mut lut = [0u8; 256];
+            for (value, entry) in lut.iter_mut().enumerate() {
+                *entry = ((value as f32 - low) / range * 255.0)
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+            lut
+        })
+        .collect();
+
+    let mut result = image_data.to_vec();
+    for pixel in result.chunks_exact_mut(4) {
+        for channel in 0..3 {
+            pixel[channel] = luts[channel][pixel[channel] as usize];
+        }
+    }
+    result
+}
+
+/// Auto-levels: stretch each channel between its `clip_percent` and
+/// `100 - clip_percent` percentiles. Faded images get their full range back
+/// while well-exposed ones are left nearly untouched.
+pub fn auto_levels(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    clip_percent: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_image_len(image_data, width, height)?;
+
+    let levels = levels_from_histograms(&channel_histograms(image_data), clip_percent);
+    Ok(apply_levels(image_data, &levels))
+}
+
+fn luminance(r: u8, g: u8, b: u8) -> f32 {
+    0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+}
+
+/// Contrast-limited adaptive histogram equalization on luminance.
+///
+/// The image is split into `tiles_x` x `tiles_y` tiles. Each tile's luminance
+/// histogram is clipped at `clip_limit` times the average bin height (the
+/// excess is spread over all bins) and turned into an equalization curve.
+/// Pixels blend the curves of the four nearest tile centres bilinearly, so
+/// there are no seams. Only luminance changes: the same offset is added to
+/// R, G and B, which keeps the chroma of the pixel. Alpha is preserved.
+pub fn clahe(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    tiles_x: u32,
+    tiles_y: u32,
+    clip_limit: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_image_len(image_data, width, height)?;
+
+    // Never use more tiles than pixels
+    let tiles = (
+        tiles_x.clamp(1, width) as usize,
+        tiles_y.clamp(1, height) as usize,
+    );
+    let histograms = clahe_tile_histograms(image_data, width, height, tiles);
+    let luts = clahe_luts(&histograms, clip_limit);
+    Ok(apply_clahe_luts(image_data, width, height, tiles, &luts))
+}
+
+/// Luminance of every pixel, rounded to 0-255
+fn luma_plane(image_data: &[u8]) -> Vec<u8> {
+    image_data
+        .chunks_exact(4)
+        .map(|p| luminance(p[0], p[1], p[2]).round().clamp(0.0, 255.0) as u8)
+        .collect()
+}
+
+/// First column (or row) of tile `tile` out of `tiles` across `size` pixels.
+/// Tiles are spread as evenly as possible.
+fn tile_start(tile: usize, tiles: usize, size: usize) -> usize {
+    tile * size / tiles
+}
+
+/// Luminance histogram of every CLAHE tile, row by row
+fn clahe_tile_histograms(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    (tiles_x, tiles_y): (usize, usize),
+) -> Vec<[u64; 256]> {
+    let (width, height) = (width as usize, height as usize);
+    let luma = luma_plane(image_data);
+
+    let mut histograms = vec![[0u64; 256]; tiles_x * tiles_y];
+    for ty in 0..tiles_y {
+        let (y0, y1) = (
+            tile_start(ty, tiles_y, height),
+            tile_start(ty + 1, tiles_y, height),
+        );
+        for tx in 0..tiles_x {
+            let (x0, x1) = (
+                tile_start(tx, tiles_x, width),
+                tile_start(tx + 1, tiles_x, width),
+            );
+            let histogram = &mut histograms[ty * tiles_x + tx];
+            for y in y0..y1 {
+                for &value in &luma[y * width + x0..y * width + x1] {
+                    histogram[value as usize] += 1;
+                }
+            }
+        }
+    }
+    histograms
+}
+
+/// Clip each tile histogram at `clip_limit` times its average bin height and
+/// turn it into an equalization curve
+fn clahe_luts(histograms: &[[u64; 256]], clip_limit: f32) -> Vec<[u8; 256]> {
+    histograms
+        .iter()
+        .map(|counts| {
+            let mut histogram = counts.map(|count| count as f32);
+            let pixels: f32 = histogram.iter().sum::<f32>().max(1.0);
+            let limit = (clip_limit * pixels / 256.0).max(1.0);
+            let mut excess = 0.0;
+            for bin in histogram.iter_mut() {
+                if *bin > limit {
+                    excess += *bin - limit;
+                    *bin = limit;
+                }
+            }
+            let bonus = excess / 256.0;
+
+            let mut lut = [0u8; 256];
+            let mut cumulative = 0.0;
+            for (value, bin) in histogram.iter().enumerate() {
+                cumulative += bin + bonus;
+                lut[value] = (cumulative / pixels * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
+            lut
+        })
+        .collect()
+}
+
+/// Map luminance through the tile curves, blending the four nearest tile
+/// centres bilinearly
+fn apply_clahe_luts(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    (tiles_x, tiles_y): (usize, usize),
+    luts: &[[u8; 256]],
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let luma = luma_plane(image_data);
+
+    // Position of a coordinate between tile centres: (lower tile, upper tile, weight)
+    let locate = |position: usize, tiles: usize, size: usize| {
+        let center =
+            |t: usize| (tile_start(t, tiles, size) + tile_start(t + 1, tiles, size)) as f32 / 2.0;
+        let position = position as f32 + 0.5;
+        if position <= center(0) {
+            return (0, 0, 0.0);
+        }
+        if position >= center(tiles - 1) {
+            return (tiles - 1, tiles - 1, 0.0);
+        }
+        let lower = (0..tiles - 1)
+            .find(|&t| position < center(t + 1))
+            .unwrap_or(tiles - 2);
+        let weight = (position - center(lower)) / (center(lower + 1) - center(lower));
+        (lower, lower + 1, weight)
+    };
+
+    let columns: Vec<(usize, usize, f32)> = (0..width).map(|x| locate(x, tiles_x, width)).collect();
+
+    let mut result = image_data.to_vec();
+    for y in 0..height {
+        let (ty0, ty1, wy) = locate(y, tiles_y, height);
+        for (x, &(tx0, tx1, wx)) in columns.iter().enumerate() {
+            let value = luma[y * width + x] as usize;
+            let sample = |tx: usize, ty: usize| luts[ty * tiles_x + tx][value] as f32;
+            let top = sample(tx0, ty0) * (1.0 - wx) + sample(tx1, ty0) * wx;
+            let bottom = sample(tx0, ty1) * (1.0 - wx) + sample(tx1, ty1) * wx;
+            let mapped = top * (1.0 - wy) + bottom * wy;
+
+            let idx = (y * width + x) * 4;
+            let pixel = &mut result[idx..idx + 4];
+            let delta = mapped - luminance(pixel[0], pixel[1], pixel[2]);
+            for channel in pixel.iter_mut().take(3) {
+                *channel = (*channel as f32 + delta).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    result
+}
+
+/// Contrast curve shared by every frame of a shot. Fitting auto-levels or
+/// CLAHE to each frame on its own makes the brightness of a video flicker as
+/// the content moves; fitting them once to the whole shot doesn't.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShotContrast {
+    /// Auto-levels black and white points
+    Levels(Levels),
+    /// CLAHE curve of every tile, row by row
+    Clahe {
+        tiles_x: usize,
+        tiles_y: usize,
+        luts: Vec<[u8; 256]>,
+    },
+}
+
+impl ShotContrast {
+    /// Apply the curve to one frame
+    pub fn apply(&self, image_data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, String> {
+        check_image_len(image_data, width, height)?;
+        match self {
+            ShotContrast::Levels(levels) => Ok(apply_levels(image_data, levels)),
+            ShotContrast::Clahe {
+                tiles_x,
+                tiles_y,
+                luts,
+            } => {
+                if *tiles_x > width as usize || *tiles_y > height as usize {
+                    return Err(format!(
+                        "{}x{} CLAHE tiles don't fit a {}x{} frame",
+                        tiles_x, tiles_y, width, height
+                    ));
+                }
+                Ok(apply_clahe_luts(
+                    image_data,
+                    width,
+                    height,
+                    (*tiles_x, *tiles_y),
+                    luts,
+                ))
+            }
+        }
+    }
+}
+
+/// Histograms of a shot, gathered frame by frame, that [`ShotContrast`] is
+/// fitted to. Frames of different sizes add up as long as the CLAHE tile
+/// grid fits all of them; each tile covers the same part of every frame.
+#[derive(Debug, Clone)]
+pub struct ShotStatistics {
+    mode: ContrastMode,
+    histograms: ChannelHistograms,
+    tiles: (usize, usize),
+    tile_histograms: Vec<[u64; 256]>,
+}
+
+impl ShotStatistics {
+    /// Statistics for the contrast step of `settings`, or `None` when that
+    /// step is off or needs none. CLAHE uses fewer tiles than configured when
+    /// the smallest frame it will see, `min_width` x `min_height`, is
+    /// narrower than the grid.
+    pub fn new(settings: &PostProcessSettings, min_width: u32, min_height: u32) -> Option<Self> {
+        if !settings.contrast_enhancement || settings.contrast_mode == ContrastMode::Classic {
+            return None;
+        }
+        let tiles = (
+            settings.clahe_tiles_x.clamp(1, min_width.max(1)) as usize,
+            settings.clahe_tiles_y.clamp(1, min_height.max(1)) as usize,
+        );
+        Some(ShotStatistics {
+            mode: settings.contrast_mode,
+            histograms: [[0; 256]; 3],
+            tiles,
+            tile_histograms: vec![[0; 256]; tiles.0 * tiles.1],
+        })
+    }
+
+    /// Add the histograms of one frame
+    pub fn add_frame(&mut self, image_data: &[u8], width: u32, height: u32) -> Result<(), String> {
+        check_image_len(image_data, width, height)?;
+        match self.mode {
+            ContrastMode::AutoLevels => {
+                let frame = channel_histograms(image_data);
+                for (total, frame) in self.histograms.iter_mut().zip(&frame) {
+                    for (total, count) in total.iter_mut().zip(frame) {
+                        *total += count;
+                    }
+                }
+            }
+            ContrastMode::Clahe => {
+                if self.tiles.0 > width as usize || self.tiles.1 > height as usize {
+                    return Err(format!(
+                        "{}x{} CLAHE tiles don't fit a {}x{} frame",
+                        self.tiles.0, self.tiles.1, width, height
+                    ));
+                }
+                let frame = clahe_tile_histograms(image_data, width, height, self.tiles);
+                for (total, frame) in self.tile_histograms.iter_mut().zip(&frame) {
+                    for (total, count) in total.iter_mut().zip(frame) {
+                        *total += count;
+                    }
+                }
+            }
+            ContrastMode::Classic => {}
+        }
+        Ok(())
+    }
+
+    /// Fit the contrast curve of `settings` to everything added so far
+    pub fn contrast(&self, settings: &PostProcessSettings) -> ShotContrast {
+        match self.mode {
+            ContrastMode::Clahe => ShotContrast::Clahe {
+                tiles_x: self.tiles.0,
+                tiles_y: self.tiles.1,
+                luts: clahe_luts(&self.tile_histograms, settings.clahe_clip_limit),
+            },
+            ContrastMode::AutoLevels | ContrastMode::Classic => ShotContrast::Levels(
+                levels_from_histograms(&self.histograms, settings.levels_clip_percent),
+            ),
+        }
+    }
+}
+
+/// Noise level of a shot, measured on the source frames, that
+/// [`TemporalDenoise`] tells noise from motion with. Uses Immerkær's
+/// estimator: the mean absolute response of luma to a 3x3 mask that cancels
+/// flat areas and gradients and passes white noise.
+#[derive(Debug, Clone, Default)]
+pub struct ShotNoise {
+    response_sum: f64,
+    samples: u64,
+}
+
+impl ShotNoise {
+    /// Add the noise of one frame. Frames narrower or shorter than the mask
+    /// add nothing.
+    pub fn add_frame(&mut self, image_data: &[u8], width: u32, height: u32) -> Result<(), String> {
+        check_image_len(image_data, width, height)?;
+        if width < 3 || height < 3 {
+            return Ok(());
+        }
+
+        let luma: Vec<f64> = image_data
+            .chunks_exact(4)
+            .map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+            .collect();
+        let (width, height) = (width as usize, height as usize);
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                let row = |dy: usize| &luma[(y + dy - 1) * width + x - 1..][..3];
+                let (above, center, below) = (row(0), row(1), row(2));
+                let response = above[0] - 2.0 * above[1] + above[2] - 2.0 * center[0]
+                    + 4.0 * center[1]
+                    - 2.0 * center[2]
+                    + below[0]
+                    - 2.0 * below[1]
+                    + below[2];
+                self.response_sum += response.abs();
+                self.samples += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Standard deviation of the luma noise, in 0-255 units
+    pub fn sigma(&self) -> f32 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        let mean_response = self.response_sum / self.samples as f64;
+        (std::f64::consts::FRAC_PI_2.sqrt() * mean_response / 6.0) as f32
+    }
+}
+
+/// Largest weight of the previous frame in [`TemporalDenoise`], where the two
+/// frames agree: a running average over about four frames
+const TEMPORAL_WEIGHT: f32 = 0.6;
+
+/// Motion-adaptive recursive filter over the frames of a shot.
+///
+/// The spatial denoisers run on each frame on its own, and what noise they
+/// leave differs from frame to frame, so static areas crawl. This blends each
+/// pixel with the previous output frame where the two differ by about the
+/// shot's noise level or less, and leaves it alone where they differ by much
+/// more, so moving edges and cuts don't ghost. Alpha is taken from the
+/// current frame.
+#[derive(Debug, Clone)]
+pub struct TemporalDenoise {
+    /// Noise standard deviation that frame differences are measured against
+    sigma: f32,
+    /// Last output frame and its size
+    previous: Option<(u32, u32, Vec<u8>)>,
+}
+
+impl TemporalDenoise {
+    /// Filter for a shot whose noise has standard deviation `sigma`, see
+    /// [`ShotNoise`]. Clean shots are still treated as having a little noise,
+    /// which evens out rounding differences between frames.
+    pub fn new(sigma: f32) -> Self {
+        TemporalDenoise {
+            sigma: sigma.max(1.0),
+            previous: None,
+        }
+    }
+
+    /// Filter the next frame of the shot. The first frame, and any frame of a
+    /// different size than the one before, passes unchanged.
+    pub fn apply(&mut self, image_data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, String> {
+        check_image_len(image_data, width, height)?;
+        let output = match &self.previous {
+            Some((previous_width, previous_height, previous))
+                if (*previous_width, *previous_height) == (width, height) =>
+            {
+                // Differences up to about two sigma count as noise
+                let scale = -0.5 / (2.0 * self.sigma).powi(2);
+                image_data
+                    .chunks_exact(4)
+                    .zip(previous.chunks_exact(4))
+                    .flat_map(|(current, previous)| {
+                        let difference = (0..3)
+                            .map(|c| (current[c] as f32 - previous[c] as f32).abs())
+                            .sum::<f32>()
+                            / 3.0;
+                        let weight = TEMPORAL_WEIGHT * (difference * difference * scale).exp();
+                        let blend = |c: usize| {
+                            let (current, previous) = (current[c] as f32, previous[c] as f32);
+                            (current + (previous - current) * weight + 0.5).clamp(0.0, 255.0) as u8
+                        };
+                        [blend(0), blend(1), blend(2), current[3]]
+                    })
+                    .collect()
+            }
+            _ => image_data.to_vec(),
+        };
+        self.previous = Some((width, height, output.clone()));
+        Ok(output)
+    }
+}
+
+/// Apply noise reduction using a simple bilateral-like filter
+pub fn reduce_noise(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    strength: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if strength <= 0.0 {
+        return Ok(image_data.to_vec());
+    }
+
+    let img: ImageBuffer<Rgba<u8>, Vec<u8>> =
+        ImageBuffer::from_raw(width, height, image_data.to_vec())
+            .ok_or("Failed to create image buffer")?;
+
+    // Simple noise reduction using mean filtering
+    let result = ImageBuffer::from_fn(width, height, |x, y| {
+        let pixel = img.get_pixel(x, y);
+
+        // Apply simple box filter for noise reduction
+        let radius = (strength * 2.0) as i32;
+        let mut r_sum = 0u32;
+        let mut g_sum = 0u32;
+        let mut b_sum = 0u32;
+        let mut count = 0u32;
+
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let px = (x as i32 + dx).clamp(0, width as i32 - 1) as u32;
+                let py = (y as i32 + dy).clamp(0, height as i32 - 1) as u32;
+                let neighbor = img.get_pixel(px, py);
+
+                r_sum += neighbor[0] as u32;
+                g_sum += neighbor[1] as u32;
+                b_sum += neighbor[2] as u32;
+                count += 1;
+            }
+        }
+
+        // Simple edge preservation: blend based on variance
+        let avg_r = r_sum / count;
+        let avg_g = g_sum / count;
+        let avg_b = b_sum / count;
+
+        let edge_factor = ((pixel[0] as i32 - avg_r as i32).abs()
+            + (pixel[1] as i32 - avg_g as i32).abs()
+            + (pixel[2] as i32 - avg_b as i32).abs()) as f32
+            / 3.0;
+
+        let blend = (1.0 - (edge_factor / 100.0).min(1.0)) * strength * 0.5;
+
+        Rgba([
+            ((pixel[0] as f32 * (1.0 - blend) + avg_r as f32 * blend).clamp(0.0, 255.0)) as u8,
+            ((pixel[1] as f32 * (1.0 - blend) + avg_g as f32 * blend).clamp(0.0, 255.0)) as u8,
+            ((pixel[2] as f32 * (1.0 - blend) + avg_b as f32 * blend).clamp(0.0, 255.0)) as u8,
+            pixel[3], // Preserve alpha
+        ])
+    });
+
+    Ok(result.into_raw())
+}
+
+/// Window radius covering +/- 2 sigma of the bilateral spatial Gaussian
+fn bilateral_radius(sigma_spatial: f32) -> i32 {
+    (sigma_spatial * 2.0).ceil().max(1.0) as i32
+}
+
+/// Load a pixel as 0-255 floats, clamping coordinates to the image
+fn load_pixel(image_data: &[u8], width: u32, height: u32, x: i32, y: i32) -> [f32; 4] {
+    let x = x.clamp(0, width as i32 - 1) as usize;
+    let y = y.clamp(0, height as i32 - 1) as usize;
+    let idx = (y * width as usize + x) * 4;
+    [
+        image_data[idx] as f32,
+        image_data[idx + 1] as f32,
+        image_data[idx + 2] as f32,
+        image_data[idx + 3] as f32,
+    ]
+}
+
+fn check_image_len(image_data: &[u8], width: u32, height: u32) -> Result<(), String> {
+    if width == 0 || height == 0 || image_data.len() != width as usize * height as usize * 4 {
+        return Err("Failed to create image buffer".to_string());
+    }
+    Ok(())
+}
+
+/// Edge-preserving bilateral filter.
+///
+/// Neighbours are weighted by a spatial Gaussian (`sigma_spatial`, in pixels)
+/// and by a Gaussian on their RGB distance to the centre pixel
+/// (`sigma_range`, in 0-255 units). Alpha is preserved. Matches
+/// `shaders/bilateral.comp`.
+pub fn bilateral_filter(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    sigma_spatial: f32,
+    sigma_range: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_image_len(image_data, width, height)?;
+
+    let radius = bilateral_radius(sigma_spatial);
+    let spatial_scale = -0.5 / (sigma_spatial * sigma_spatial);
+    let range_scale = -0.5 / (sigma_range * sigma_range);
+
+    let mut result = Vec::with_capacity(image_data.len());
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let center = load_pixel(image_data, width, height, x, y);
+            let mut sum = [0.0f32; 3];
+            let mut weight_sum = 0.0f32;
+
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let neighbor = load_pixel(image_data, width, height, x + dx, y + dy);
+                    let range: f32 = (0..3).map(|c| (neighbor[c] - center[c]).powi(2)).sum();
+                    let spatial = (dx * dx + dy * dy) as f32;
+                    let weight = (spatial * spatial_scale + range * range_scale).exp();
+                    for c in 0..3 {
+                        sum[c] += neighbor[c] * weight;
+                    }
+                    weight_sum += weight;
+                }
+            }
+
+            for channel_sum in sum {
+                result.push((channel_sum / weight_sum + 0.5).clamp(0.0, 255.0) as u8);
+            }
+            result.push(center[3] as u8);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Non-local means denoising.
+///
+/// Each pixel in the `(2 * search_radius + 1)`² window is weighted by
+/// `exp(-d / h²)`, where `d` is the mean squared RGB difference between the
+/// `(2 * patch_radius + 1)`² patches around it and around the centre pixel.
+/// Alpha is preserved. Matches `shaders/nlm.comp`.
+pub fn non_local_means(
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    h: f32,
+    patch_radius: u32,
+    search_radius: u32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_image_len(image_data, width, height)?;
+
+    let patch_radius = patch_radius as i32;
+    let search_radius = search_radius as i32;
+    let patch_size = 2 * patch_radius + 1;
+    let patch_samples = (patch_size * patch_size * 3) as f32;
+    let h2 = h * h;
+
+    let mut result = Vec::with_capacity(image_data.len());
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let mut sum = [0.0f32; 3];
+            let mut weight_sum = 0.0f32;
+
+            for sy in -search_radius..=search_radius {
+                for sx in -search_radius..=search_radius {
+                    // Mean squared difference between the two patches
+                    let mut distance = 0.0f32;
+                    for py in -patch_radius..=patch_radius {
+                        for px in -patch_radius..=patch_radius {
+                            let a = load_pixel(image_data, width, height, x + px, y + py);
+                            let b = load_pixel(image_data, width, height, x + sx + px, y + sy + py);
+                            distance += (0..3).map(|c| (a[c] - b[c]).powi(2)).sum::<f32>();
+                        }
+                    }
+                    distance /= patch_samples;
+
+                    let weight = (-distance / h2).exp();
+                    let candidate = load_pixel(image_data, width, height, x + sx, y + sy);
+                    for c in 0..3 {
+                        sum[c] += candidate[c] * weight;
+                    }
+                    weight_sum += weight;
+                }
+            }
+
+            for channel_sum in sum {
+                result.push((channel_sum / weight_sum + 0.5).clamp(0.0, 255.0) as u8);
+            }
+            result.push(load_pixel(image_data, width, height, x, y)[3] as u8);
+        }
+    }
+
+    Ok(result)
+}
+
+/// GPU version of [`bilateral_filter`]
+pub fn bilateral_filter_gpu(
+    context: &VulkanContext,
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    sigma_spatial: f32,
+    sigma_range: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_image_len(image_data, width, height)?;
+
+    let kernel = ComputeKernel::new(
+        context,
+        include_bytes!("../../shaders/bilateral.spv"),
+        2,
+        20,
+    )?;
+    let push_constants = [
+        width.to_ne_bytes(),
+        height.to_ne_bytes(),
+        bilateral_radius(sigma_spatial).to_ne_bytes(),
+        sigma_spatial.to_ne_bytes(),
+        sigma_range.to_ne_bytes(),
+    ]
+    .concat();
+
+    run_image_kernel(context, &kernel, image_data, width, height, &push_constants)
+}
+
+/// GPU version of [`non_local_means`]
+pub fn non_local_means_gpu(
+    context: &VulkanContext,
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    h: f32,
+    patch_radius: u32,
+    search_radius: u32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_image_len(image_data, width, height)?;
+
+    let kernel = ComputeKernel::new(context, include_bytes!("../../shaders/nlm.spv"), 2, 20)?;
+    let push_constants = [
+        width.to_ne_bytes(),
+        height.to_ne_bytes(),
+        (patch_radius as i32).to_ne_bytes(),
+        (search_radius as i32).to_ne_bytes(),
+        h.to_ne_bytes(),
+    ]
+    .concat();
+
+    run_image_kernel(context, &kernel, image_data, width, height, &push_constants)
+}
+
+/// GPU version of [`cas`]
+pub fn cas_gpu(
+    context: &VulkanContext,
+    image_data: &[u8],
+    width: u32,
+    height: u32,
+    sharpness: f32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_image_len(image_data, width, height)?;
+
+    let kernel = ComputeKernel::new(context, include_bytes!("../../shaders/cas.spv"), 2, 12)?;
+    let peak = -1.0 / (8.0 + (5.0 - 8.0) * sharpness.clamp(0.0, 1.0));
+    let push_constants = [
+        width.to_ne_bytes(),
+        height.to_ne_bytes(),
+        peak.to_ne_bytes(),
+    ]
+    .concat();
+
+    run_image_kernel(context, &kernel, image_data, width, height, &push_constants)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Filter = fn(&[u8], u32, u32) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+
+    /// Every CPU filter with its default settings
+    const FILTERS: [(&str, Filter); 10] = [
+        ("sharpening", |d, w, h| apply_sharpening(d, w, h, 1.8)),
+        ("unsharp_mask", |d, w, h| {
+            unsharp_mask(d, w, h, 1.0, 0.8, 3.0)
+        }),
+        ("cas", |d, w, h| cas(d, w, h, 0.5)),
+        ("rcas", |d, w, h| fsr::rcas(d, w, h, 0.2)),
+        ("contrast", |d, w, h| {
+            enhance_contrast(d, w, h, 1.15, 1.25, 1.1)
+        }),
+        ("auto_levels", |d, w, h| auto_levels(d, w, h, 0.5)),
+        ("clahe", |d, w, h| clahe(d, w, h, 8, 8, 2.0)),
+        ("noise", |d, w, h| reduce_noise(d, w, h, 0.5)),
+        ("bilateral", |d, w, h| bilateral_filter(d, w, h, 2.0, 20.0)),
+        ("nlm", |d, w, h| non_local_means(d, w, h, 10.0, 1, 5)),
+    ];
+
+    /// Filters that only move pixels towards or away from their neighbours,
+    /// so they must leave a flat image alone
+    const LOCAL_FILTERS: [&str; 7] = [
+        "sharpening",
+        "unsharp_mask",
+        "cas",
+        "rcas",
+        "noise",
+        "bilateral",
+        "nlm",
+    ];
+
+    /// Image with a different colour and alpha in every pixel
+    fn varied_image(width: u32, height: u32) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| {
+                let i = i as u8;
+                [
+                    i.wrapping_mul(37),
+                    200u8.wrapping_sub(i.wrapping_mul(13)),
+                    i.wrapping_mul(i),
+                    i.wrapping_mul(29),
+                ]
+            })
+            .collect()
+    }
+
+    /// Vertical step from `left` to `right` between columns 3 and 4
+    fn step_edge(left: u8, right: u8) -> Vec<u8> {
+        (0..8 * 4)
+            .flat_map(|i| {
+                let value = if i % 8 < 4 { left } else { right };
+                [value, value, value, 255]
+            })
+            .collect()
+    }
+
+    fn alpha(image_data: &[u8]) -> Vec<u8> {
+        image_data.iter().skip(3).step_by(4).copied().collect()
+    }
+
+    #[test]
+    fn every_filter_preserves_alpha() {
+        let image = varied_image(6, 5);
+        for (name, filter) in FILTERS {
+            let output = filter(&image, 6, 5).unwrap();
+            assert_eq!(alpha(&output), alpha(&image), "{} changed alpha", name);
+        }
+    }
+
+    #[test]
+    fn local_filters_leave_flat_images_unchanged() {
+        let image = [90u8, 160, 40, 255].repeat(7 * 5);
+        for (name, filter) in FILTERS {
+            if LOCAL_FILTERS.contains(&name) {
+                let output = filter(&image, 7, 5).unwrap();
+                assert_eq!(output, image, "{} changed a flat image", name);
+            }
+        }
+    }
+
+    #[test]
+    fn single_pixel_and_single_line_images() {
+        for (width, height) in [(1, 1), (1, 7), (7, 1)] {
+            let image = varied_image(width, height);
+            for (name, filter) in FILTERS {
+                let output = filter(&image, width, height)
+                    .unwrap_or_else(|e| panic!("{} failed on {}x{}: {}", name, width, height, e));
+                assert_eq!(
+                    output.len(),
+                    image.len(),
+                    "{} on {}x{}",
+                    name,
+                    width,
+                    height
+                );
+                assert_eq!(
+                    alpha(&output),
+                    alpha(&image),
+                    "{} on {}x{}",
+                    name,
+                    width,
+                    height
+                );
+                if width * height == 1 && LOCAL_FILTERS.contains(&name) {
+                    assert_eq!(output, image, "{} changed a single pixel", name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sharpening_steepens_edges() {
+        let image = step_edge(60, 180);
+        for name in ["sharpening", "unsharp_mask", "cas", "rcas"] {
+            let (_, filter) = FILTERS.iter().find(|(n, _)| *n == name).unwrap();
+            let output = filter(&image, 8, 4).unwrap();
+            for row in output.chunks_exact(8 * 4) {
+                assert!(row[3 * 4] < 60, "{} didn't darken the dark side", name);
+                assert!(row[4 * 4] > 180, "{} didn't brighten the bright side", name);
+                assert_eq!(row[0], 60, "{} touched pixels away from the edge", name);
+                assert_eq!(
+                    row[7 * 4],
+                    180,
+                    "{} touched pixels away from the edge",
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn edge_preserving_denoisers_keep_steps() {
+        let image = step_edge(20, 220);
+        for name in ["bilateral", "nlm"] {
+            let (_, filter) = FILTERS.iter().find(|(n, _)| *n == name).unwrap();
+            let output = filter(&image, 8, 4).unwrap();
+            assert_eq!(output, image, "{} blurred a clean edge", name);
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_lengths() {
+        let image = varied_image(4, 4);
+        for (name, filter) in FILTERS {
+            assert!(
+                filter(&image[..60], 4, 4).is_err(),
+                "{} accepted a short buffer",
+                name
+            );
+            assert!(
+                filter(&image, 5, 4).is_err(),
+                "{} accepted wrong dimensions",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn shot_statistics_of_one_frame_match_the_frame_filters() {
+        let image = varied_image(12, 10);
+        let mut settings = PostProcessSettings {
+            contrast_enhancement: true,
+            ..Default::default()
+        };
+
+        settings.contrast_mode = ContrastMode::AutoLevels;
+        let mut statistics = ShotStatistics::new(&settings, 12, 10).unwrap();
+        statistics.add_frame(&image, 12, 10).unwrap();
+        let shot = statistics.contrast(&settings).apply(&image, 12, 10);
+        assert_eq!(shot.unwrap(), auto_levels(&image, 12, 10, 0.5).unwrap());
+
+        settings.contrast_mode = ContrastMode::Clahe;
+        let mut statistics = ShotStatistics::new(&settings, 12, 10).unwrap();
+        statistics.add_frame(&image, 12, 10).unwrap();
+        let shot = statistics.contrast(&settings).apply(&image, 12, 10);
+        assert_eq!(shot.unwrap(), clahe(&image, 12, 10, 8, 8, 2.0).unwrap());
+
+        settings.contrast_mode = ContrastMode::Classic;
+        assert!(ShotStatistics::new(&settings, 12, 10).is_none());
+    }
+
+    #[test]
+    fn shot_statistics_fit_all_frames_at_once() {
+        let settings = PostProcessSettings {
+            contrast_enhancement: true,
+            contrast_mode: ContrastMode::AutoLevels,
+            ..Default::default()
+        };
+        let dark: Vec<u8> = varied_image(6, 6)
+            .chunks_exact(4)
+            .flat_map(|p| [p[0] / 2, p[1] / 2, p[2] / 2, p[3]])
+            .collect();
+        let bright = varied_image(6, 6);
+
+        let mut statistics = ShotStatistics::new(&settings, 6, 6).unwrap();
+        statistics.add_frame(&dark, 6, 6).unwrap();
+        statistics.add_frame(&bright, 6, 6).unwrap();
+        let ShotContrast::Levels(levels) = statistics.contrast(&settings) else {
+            panic!("auto-levels fitted a CLAHE curve");
+        };
+        // Same as fitting to both frames stacked into one image
+        let stacked = [dark.as_slice(), bright.as_slice()].concat();
+        let expected = levels_from_histograms(&channel_histograms(&stacked), 0.5);
+        assert_eq!(levels, expected);
+        assert_ne!(
+            levels,
+            levels_from_histograms(&channel_histograms(&dark), 0.5)
+        );
+        assert!(statistics.add_frame(&bright[..40], 6, 6).is_err());
+    }
+
+    /// Flat grey frame with roughly Gaussian grey noise of standard deviation
+    /// `sigma`, different for every `seed`
+    fn noisy_frame(width: u32, height: u32, sigma: f32, seed: u32) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9e37_79b9) | 1;
+        let mut uniform = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 - 0.5
+        };
+        (0..width * height)
+            .flat_map(|_| {
+                // The sum of four uniforms has variance 1/3
+                let noise = (0..4).map(|_| uniform()).sum::<f32>() * 3f32.sqrt();
+                let value = (128.0 + noise * sigma).round() as u8;
+                [value, value, value, 255]
+            })
+            .collect()
+    }
+
+    /// Mean squared difference between two frames
+    fn frame_difference(a: &[u8], b: &[u8]) -> f32 {
+        let sum: f32 = a
+            .iter()
+            .zip(b)
+            .map(|(&a, &b)| (a as f32 - b as f32).powi(2))
+            .sum();
+        sum / a.len() as f32
+    }
+
+    #[test]
+    fn shot_noise_measures_the_noise_level() {
+        let mut noise = ShotNoise::default();
+        for seed in 1..=3 {
+            noise
+                .add_frame(&noisy_frame(64, 64, 6.0, seed), 64, 64)
+                .unwrap();
+        }
+        assert!(
+            (noise.sigma() - 6.0).abs() < 0.6,
+            "estimated {}",
+            noise.sigma()
+        );
+
+        // Gradients aren't noise
+        let mut clean = ShotNoise::default();
+        let gradient: Vec<u8> = (0..32 * 32)
+            .flat_map(|i| {
+                let value = (i % 32 * 4 + i / 32 * 3) as u8;
+                [value, value, value, 255]
+            })
+            .collect();
+        clean.add_frame(&gradient, 32, 32).unwrap();
+        assert!(clean.sigma() < 1e-6, "estimated {}", clean.sigma());
+        assert_eq!(ShotNoise::default().sigma(), 0.0);
+    }
+
+    #[test]
+    fn temporal_denoise_steadies_static_noise() {
+        let mut temporal = TemporalDenoise::new(6.0);
+        let frames: Vec<Vec<u8>> = (1..=8).map(|seed| noisy_frame(32, 32, 6.0, seed)).collect();
+        let outputs: Vec<Vec<u8>> = frames
+            .iter()
+            .map(|frame| temporal.apply(frame, 32, 32).unwrap())
+            .collect();
+        assert_eq!(outputs[0], frames[0]);
+
+        let flicker = |frames: &[Vec<u8>]| {
+            frames
+                .windows(2)
+                .map(|pair| frame_difference(&pair[0], &pair[1]))
+                .sum::<f32>()
+        };
+        let (before, after) = (flicker(&frames[4..]), flicker(&outputs[4..]));
+        assert!(after < before / 2.0, "{} -> {}", before, after);
+        assert!(outputs
+            .iter()
+            .all(|output| alpha(output).iter().all(|&a| a == 255)));
+    }
+
+    #[test]
+    fn temporal_denoise_passes_motion_and_cuts() {
+        let mut temporal = TemporalDenoise::new(6.0);
+        let first = noisy_frame(16, 16, 6.0, 1);
+        temporal.apply(&first, 16, 16).unwrap();
+
+        let cut: Vec<u8> = noisy_frame(16, 16, 6.0, 2)
+            .chunks_exact(4)
+            .flat_map(|p| [p[0] - 100, p[1] - 100, p[2] - 100, 128])
+            .collect();
+        assert_eq!(temporal.apply(&cut, 16, 16).unwrap(), cut);
+
+        // A new size starts over
+        let small = noisy_frame(8, 8, 6.0, 3);
+        assert_eq!(temporal.apply(&small, 8, 8).unwrap(), small);
+        assert!(temporal.apply(&small[..40], 8, 8).is_err());
+    }
+}
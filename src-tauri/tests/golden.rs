@@ -0,0 +1,345 @@
+//! Golden-image regression tests
+//!
+//! The CPU reference of `upscale.comp` is compared exactly against the PNGs in
+//! `tests/golden`, so any change to the interpolation shows up as a diff of
+//! those images. Run with `UPDATE_GOLDEN=1` to rewrite them after an
+//! intentional change.
+//!
+//! The GPU tests run every shader that has a CPU reference and compare the
+//! two within a small tolerance, since the GPU's `sin`, `exp` and FMA
+//! contraction round differently. They need a Vulkan device; a software one
+//! such as lavapipe is enough. They are ignored by default so a machine
+//! without a device can't report them as passing; run them with
+//! `cargo test --test golden -- --ignored`, where a missing device fails.
+
+use image::RgbaImage;
+use std::path::{Path, PathBuf};
+use v_upscale_lib::vulkan::{
+    self, downscale, edge_directed, fsr, interpolation, line_art, neural, post_processing,
+    InterpolationKernel, VulkanContext,
+};
+
+/// Upscale factors covered by the goldens
+const FACTORS: [u32; 3] = [2, 3, 4];
+
+/// Kernels of `upscale.comp` covered by the goldens
+const KERNELS: [(&str, InterpolationKernel); 2] = [
+    ("mitchell", InterpolationKernel::Mitchell),
+    ("lanczos", InterpolationKernel::Lanczos),
+];
+
+/// Largest per-channel difference allowed between `upscale.comp` and its
+/// reference
+const UPSCALE_TOLERANCE: u8 = 1;
+
+/// Largest per-channel difference allowed for the other shaders, which use
+/// `exp` and longer sums
+const FILTER_TOLERANCE: u8 = 2;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Small test inputs: smooth gradients, hard edges, noise with varying
+/// alpha, and a single row
+fn inputs() -> Vec<(&'static str, RgbaImage)> {
+    let mut seed = 0x2545_f491u32;
+    let mut noise = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    vec![
+        (
+            "gradient",
+            RgbaImage::from_fn(12, 9, |x, y| {
+                image::Rgba([(x * 23) as u8, (y * 31) as u8, ((x + y) * 12) as u8, 255])
+            }),
+        ),
+        (
+            "checker",
+            RgbaImage::from_fn(10, 10, |x, y| {
+                let value = if (x / 2 + y / 2) % 2 == 0 { 230 } else { 20 };
+                image::Rgba([value, 255 - value, value / 2, 255])
+            }),
+        ),
+        (
+            "noise_alpha",
+            RgbaImage::from_fn(8, 8, |_, _| {
+                let value = noise();
+                image::Rgba([
+                    value as u8,
+                    (value >> 8) as u8,
+                    (value >> 16) as u8,
+                    (value >> 24) as u8,
+                ])
+            }),
+        ),
+        (
+            "row",
+            RgbaImage::from_fn(7, 1, |x, _| {
+                image::Rgba([
+                    (x * 40) as u8,
+                    200,
+                    (255 - x * 35) as u8,
+                    (60 + x * 30) as u8,
+                ])
+            }),
+        ),
+    ]
+}
+
+/// Check that every channel is within `tolerance`, naming the first pixel
+/// that isn't
+fn compare(actual: &[u8], expected: &[u8], width: u32, tolerance: u8) -> Result<(), String> {
+    if actual.len() != expected.len() {
+        return Err(format!(
+            "size differs: {} bytes instead of {}",
+            actual.len(),
+            expected.len()
+        ));
+    }
+    for (index, (a, e)) in actual.iter().zip(expected).enumerate() {
+        let difference = a.abs_diff(*e);
+        if difference > tolerance {
+            let pixel = index as u32 / 4;
+            return Err(format!(
+                "pixel ({}, {}) channel {} is {} instead of {}",
+                pixel % width,
+                pixel / width,
+                index % 4,
+                a,
+                e
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn reference_matches_goldens() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+
+    for (name, input) in inputs() {
+        let (width, height) = input.dimensions();
+        for (kernel_name, kernel) in KERNELS {
+            for factor in FACTORS {
+                let output =
+                    interpolation::upscale(input.as_raw(), width, height, factor, kernel).unwrap();
+                let path = golden_dir().join(format!("{}_{}_{}x.png", name, kernel_name, factor));
+
+                if update {
+                    std::fs::create_dir_all(golden_dir()).unwrap();
+                    RgbaImage::from_raw(width * factor, height * factor, output)
+                        .unwrap()
+                        .save(&path)
+                        .unwrap();
+                    continue;
+                }
+
+                let golden = match image::open(&path) {
+                    Ok(golden) => golden.to_rgba8(),
+                    Err(e) => {
+                        failures.push(format!("{}: {}", path.display(), e));
+                        continue;
+                    }
+                };
+                if let Err(e) = compare(&output, golden.as_raw(), width * factor, 0) {
+                    failures.push(format!("{}: {}", path.display(), e));
+                }
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "Output differs from the goldens (rerun with UPDATE_GOLDEN=1 if intended):\n{}",
+        failures.join("\n")
+    );
+}
+
+/// Vulkan context for the GPU tests; fails the test when there is no device
+fn gpu_context() -> VulkanContext {
+    VulkanContext::new().unwrap_or_else(|e| panic!("The GPU tests need a Vulkan device: {}", e))
+}
+
+#[test]
+#[ignore = "needs a Vulkan device; run with --ignored"]
+fn gpu_upscale_matches_reference() {
+    let context = gpu_context();
+
+    for (name, input) in inputs() {
+        let (width, height) = input.dimensions();
+        for kernel in [
+            InterpolationKernel::Auto,
+            InterpolationKernel::Mitchell,
+            InterpolationKernel::Lanczos,
+        ] {
+            for factor in [1, 2, 3, 4, 8] {
+                let expected =
+                    interpolation::upscale(input.as_raw(), width, height, factor, kernel).unwrap();
+                let actual =
+                    vulkan::upscale_rgba(&context, input.as_raw(), width, height, factor, kernel)
+                        .unwrap();
+                if let Err(e) = compare(&actual, &expected, width * factor, UPSCALE_TOLERANCE) {
+                    panic!("{} with {:?} at {}x: {}", name, kernel, factor, e);
+                }
+            }
+        }
+    }
+}
+
+/// Small 2x RGB upscaler exercising every layer `GpuNetwork` runs: a 3x3
+/// convolution to 12 channels, PReLU, a grouped and dilated 3x3 convolution,
+/// a pixel shuffle, and a nearest-neighbour skip connection added on top
+fn nn_network() -> neural::Network {
+    let param = "7767517
+8 10
+Input                data    0 1 data
+Split                split   1 2 data a b
+Convolution          conv    1 1 a c 0=12 1=3 4=1 5=1 6=324
+PReLU                prelu   1 1 c p 0=12
+ConvolutionDepthWise dw      1 1 p d 0=12 1=3 2=2 4=2 5=1 6=108 7=12 9=1
+PixelShuffle         shuffle 1 1 d s 0=2
+Interp               up      1 1 b u 0=1 1=2.0 2=2.0
+BinaryOp             add     2 1 s u out 0=0
+";
+    // Small weights of both signs, so no layer saturates
+    let weights = |count: usize, step: usize| -> Vec<f32> {
+        (0..count)
+            .map(|i| ((i * step) % 17) as f32 / 34.0 - 0.25)
+            .collect()
+    };
+    let bin: Vec<u8> = [
+        vec![0.0],
+        weights(324, 7),
+        weights(12, 5),
+        weights(12, 3).iter().map(|w| w + 0.5).collect(),
+        vec![0.0],
+        weights(108, 11),
+        weights(12, 13),
+    ]
+    .concat()
+    .iter()
+    .flat_map(|v| v.to_le_bytes())
+    .collect();
+    neural::ncnn::parse(param, &bin).unwrap()
+}
+
+/// Shader with a CPU reference, both taking RGBA8 pixels of the given size
+struct ShaderCase<'a> {
+    name: &'static str,
+    /// Output width for an input width
+    output_width: fn(u32) -> u32,
+    reference: Filter<'a>,
+    shader: Filter<'a>,
+}
+
+type Filter<'a> = Box<dyn Fn(&[u8], u32, u32) -> FilterOutput + 'a>;
+type FilterOutput = Result<Vec<u8>, Box<dyn std::error::Error>>;
+
+#[test]
+#[ignore = "needs a Vulkan device; run with --ignored"]
+fn gpu_filters_match_reference() {
+    let context = gpu_context();
+    let network = nn_network();
+    let gpu_network = neural::GpuNetwork::new(&context, &network).unwrap();
+    // Tiles smaller than the inputs, so the tile seams are compared too
+    let nn_upscale = |d: &[u8], w, h, run_tile: &mut dyn FnMut(_) -> _| {
+        let image = RgbaImage::from_raw(w, h, d.to_vec()).ok_or("Invalid image size")?;
+        let output = neural::upscale_tiled(&image, 2, 5, 2, run_tile)?;
+        Ok(output.into_raw())
+    };
+
+    let half = |size: u32| size.div_ceil(2);
+    let line_art_settings = line_art::LineArtSettings {
+        passes: 2,
+        ..Default::default()
+    };
+    let cases = [
+        ShaderCase {
+            name: "edge_directed",
+            output_width: |w| w * 2,
+            reference: Box::new(|d, w, h| edge_directed::upscale(d, w, h, 2)),
+            shader: Box::new(|d, w, h| edge_directed::upscale_gpu(&context, d, w, h, 2)),
+        },
+        ShaderCase {
+            name: "easu",
+            output_width: |w| w * 2,
+            reference: Box::new(|d, w, h| fsr::easu(d, w, h, w * 2, h * 2)),
+            shader: Box::new(|d, w, h| fsr::easu_gpu(&context, d, w, h, w * 2, h * 2)),
+        },
+        ShaderCase {
+            name: "rcas",
+            output_width: |w| w,
+            reference: Box::new(|d, w, h| fsr::rcas(d, w, h, 0.2)),
+            shader: Box::new(|d, w, h| fsr::rcas_gpu(&context, d, w, h, 0.2)),
+        },
+        ShaderCase {
+            name: "cas",
+            output_width: |w| w,
+            reference: Box::new(|d, w, h| post_processing::cas(d, w, h, 0.5)),
+            shader: Box::new(|d, w, h| post_processing::cas_gpu(&context, d, w, h, 0.5)),
+        },
+        ShaderCase {
+            name: "bilateral",
+            output_width: |w| w,
+            reference: Box::new(|d, w, h| post_processing::bilateral_filter(d, w, h, 2.0, 20.0)),
+            shader: Box::new(|d, w, h| {
+                post_processing::bilateral_filter_gpu(&context, d, w, h, 2.0, 20.0)
+            }),
+        },
+        ShaderCase {
+            name: "nlm",
+            output_width: |w| w,
+            reference: Box::new(|d, w, h| post_processing::non_local_means(d, w, h, 10.0, 1, 5)),
+            shader: Box::new(|d, w, h| {
+                post_processing::non_local_means_gpu(&context, d, w, h, 10.0, 1, 5)
+            }),
+        },
+        ShaderCase {
+            name: "downscale",
+            output_width: |w| w.div_ceil(2),
+            reference: Box::new(move |d, w, h| {
+                let kernel = InterpolationKernel::Auto;
+                Ok(downscale::downscale(d, w, h, half(w), half(h), kernel)?)
+            }),
+            shader: Box::new(|d, w, h| {
+                let kernel = InterpolationKernel::Auto;
+                downscale::downscale_gpu(&context, d, w, h, half(w), half(h), kernel)
+            }),
+        },
+        ShaderCase {
+            name: "line_art",
+            output_width: |w| w,
+            reference: Box::new(|d, w, h| line_art::process(d, w, h, &line_art_settings)),
+            shader: Box::new(|d, w, h| {
+                line_art::process_gpu(&context, d, w, h, &line_art_settings)
+            }),
+        },
+        ShaderCase {
+            name: "nn",
+            output_width: |w| w * 2,
+            reference: Box::new(|d, w, h| {
+                nn_upscale(d, w, h, &mut |tile| network.forward_cpu(tile))
+            }),
+            shader: Box::new(|d, w, h| {
+                nn_upscale(d, w, h, &mut |tile| gpu_network.forward(&context, &tile))
+            }),
+        },
+    ];
+
+    for (name, input) in inputs() {
+        let (width, height) = input.dimensions();
+        for case in &cases {
+            let expected = (case.reference)(input.as_raw(), width, height).unwrap();
+            let actual = (case.shader)(input.as_raw(), width, height).unwrap();
+            let output_width = (case.output_width)(width);
+            if let Err(e) = compare(&actual, &expected, output_width, FILTER_TOLERANCE) {
+                panic!("{} on {}: {}", case.name, name, e);
+            }
+        }
+    }
+}
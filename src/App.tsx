@@ -52,10 +52,13 @@ function App() {
 
     try {
       // This is where you would call your Rust backend
-      const upscaledPath = await invoke<string>("upscale_image", {
-        path: originalImagePath,
-        factor: upscaleFactor,
-      });
+      const { output_path: upscaledPath } = await invoke<{ output_path: string }>(
+        "upscale_image",
+        {
+          path: originalImagePath,
+          factor: upscaleFactor,
+        }
+      );
 
       setUpscaledImagePath(upscaledPath);
       setUpscaledImage(convertFileSrc(upscaledPath));